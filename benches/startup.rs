@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+const ITERATIONS: u32 = 100;
+
+fn measure(mut create: impl FnMut() -> JsRuntime) -> Duration {
+    // Warm up V8 and the allocator before timing
+    drop(create());
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        drop(create());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let snapshot: &'static [u8] = JsRuntime::create_snapshot().leak();
+    println!("snapshot size: {} bytes", snapshot.len());
+
+    // An empty snapshot runs the bootstrap even when one is embedded
+    let bootstrap = measure(|| {
+        JsRuntime::with_options(RuntimeOptions {
+            startup_snapshot: Some(&[]),
+            ..Default::default()
+        })
+    });
+    let from_snapshot = measure(|| {
+        JsRuntime::with_options(RuntimeOptions {
+            startup_snapshot: Some(snapshot),
+            ..Default::default()
+        })
+    });
+
+    let default = measure(JsRuntime::new);
+
+    println!("JsRuntime::new (bootstrap):      {:?} per runtime", bootstrap);
+    println!("JsRuntime::new (startup snapshot): {:?} per runtime", from_snapshot);
+    println!("JsRuntime::new (default):        {:?} per runtime", default);
+    println!(
+        "speedup: {:.2}x",
+        bootstrap.as_secs_f64() / from_snapshot.as_secs_f64()
+    );
+}
@@ -0,0 +1,27 @@
+// With the `startup-snapshot` feature, bootstraps a context and embeds the snapshot of
+// it in the library (see `src/runtime/snapshot.rs`). The runtime's sources are compiled
+// into this script for that, since the library itself isn't built yet.
+
+// Declared the way `src/lib.rs` does, so the `crate::` paths in them resolve
+#[cfg(feature = "startup-snapshot")]
+#[allow(dead_code, unused_imports)]
+#[path = "src"]
+mod src {
+    pub mod modules;
+    pub mod runtime;
+}
+
+#[cfg(feature = "startup-snapshot")]
+use src::{modules, runtime};
+
+fn main() {
+    println!("cargo::rerun-if-changed=src");
+
+    #[cfg(feature = "startup-snapshot")]
+    {
+        let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+        let snapshot = runtime::JsRuntime::create_snapshot();
+        std::fs::write(out_dir.join("toyjs.snap"), snapshot).expect("Failed to write startup snapshot");
+        println!("cargo::rustc-cfg=toyjs_startup_snapshot");
+    }
+}
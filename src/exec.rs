@@ -1,26 +1,96 @@
-use toyjs::runtime::JsRuntime;
-use std::path::Path;
+use toyjs::logging;
+use toyjs::modules::ImportMap;
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+use std::path::{Path, PathBuf};
 use std::env;
-use std::time::Duration;
+
+/// Where `exec` keeps its caches: `$TOYJS_CACHE_DIR`, else the platform cache directory.
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("TOYJS_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("toyjs"))
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: exec <path_to_js>");
+    let mut code_cache = true;
+    let mut reload = false;
+    let mut import_map = None;
+    let mut log_level = None;
+    let mut js_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-code-cache" => code_cache = false,
+            "--reload" => reload = true,
+            "--import-map" => {
+                let Some(path) = args.next() else {
+                    eprintln!("Error: --import-map requires a path");
+                    std::process::exit(1);
+                };
+                match ImportMap::from_file(Path::new(&path)) {
+                    Ok(map) => import_map = Some(map),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--log-level" => {
+                let Some(level) = args.next() else {
+                    eprintln!("Error: --log-level requires a level or filter");
+                    std::process::exit(1);
+                };
+                log_level = Some(level);
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("Error: Unknown option: {}", arg);
+                std::process::exit(1);
+            }
+            _ => js_path = Some(arg),
+        }
+    }
+
+    let Some(js_path) = js_path else {
+        eprintln!("Usage: exec [--import-map <path>] [--log-level <filter>] [--no-code-cache] [--reload] <path_to_js>");
+        std::process::exit(1);
+    };
+
+    // Runtime diagnostics go to stderr, and only when asked for; `--log-level` wins over
+    // `$TOYJS_LOG`
+    let logging = match log_level {
+        Some(level) => logging::init(&level),
+        None => logging::init_from_env(),
+    };
+    if let Err(e) = logging {
+        eprintln!("Error: {}", e);
         std::process::exit(1);
     }
 
-    let js_path = Path::new(&args[1]);
+    let js_path = Path::new(&js_path);
     if !js_path.exists() {
-        eprintln!("Error: File not found: {}", args[1]);
+        eprintln!("Error: File not found: {}", js_path.display());
         std::process::exit(1);
     }
 
-    let mut runtime = JsRuntime::new();
+    let cache_dir = cache_dir();
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        code_cache_dir: cache_dir
+            .as_ref()
+            .filter(|_| code_cache)
+            .map(|dir| dir.join("code_cache")),
+        import_map,
+        remote_module_dir: cache_dir.as_ref().map(|dir| dir.join("remote")),
+        reload_remote_modules: reload,
+        ..Default::default()
+    });
     let event_loop = runtime.run_event_loop();
 
-    match runtime.execute_module(js_path) {
+    match runtime.execute_module(js_path).await {
         Ok(result) => {
             if result != "undefined" && !result.is_empty() {
                 println!("Result: {}", result);
@@ -32,12 +102,21 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    // Give some time for pending callbacks to process if any
-    // In a real runtime, we'd wait until the event loop is actually empty
-    // For this toy runtime, we'll just poll for a bit
-    for _ in 0..50 {
-        runtime.process_callbacks();
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    // Keep processing callbacks until no timers or fetches are outstanding
+    if let Err(e) = runtime.run_until_idle().await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    // Logged with the rest of module loading, so `--log-level debug` shows it
+    if let Some(stats) = runtime.code_cache_stats() {
+        tracing::debug!(
+            target: "toyjs::modules",
+            hits = stats.hits,
+            misses = stats.misses,
+            rejected = stats.rejected,
+            "code cache"
+        );
     }
 
     runtime.shutdown();
@@ -1,2 +1,8 @@
 pub mod runtime;
 pub mod modules;
+pub mod logging;
+
+// The temp-dir fixtures integration tests use, shared with the unit tests
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_common;
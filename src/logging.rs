@@ -0,0 +1,332 @@
+//! Diagnostics from the runtime itself: module loading, the event loop and ops.
+//!
+//! The runtime reports what it does through `tracing`, which is silent until a
+//! subscriber is installed. `init` installs a small one that writes to stderr, so the
+//! diagnostics never mix with what scripts print to stdout. Embedders with their own
+//! `tracing` setup can skip it and get the same events and spans there.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// The environment variable `init_from_env` reads the log filter from.
+pub const LOG_ENV: &str = "TOYJS_LOG";
+
+/// Logs events that pass `filter` to stderr. The filter is a comma-separated list of
+/// directives, each a level (`off`, `error`, `warn`, `info`, `debug` or `trace`) or a
+/// `target=level` pair:
+///
+/// * A bare level applies to the whole runtime. Other crates, such as the HTTP client,
+///   only log warnings and errors under it whatever the level.
+/// * `target=level` sets the level for a module path and everything below it, e.g.
+///   `toyjs::modules=trace` or `reqwest=debug`. The longest matching target wins.
+///
+/// So `warn,toyjs::modules=debug` shows module loading in detail and only problems
+/// from everything else.
+pub fn init(filter: &str) -> Result<(), String> {
+    let filter: Filter = filter.parse()?;
+    if filter.max_level() == LevelFilter::OFF {
+        return Ok(());
+    }
+    tracing::subscriber::set_global_default(StderrLogger::new(filter)).map_err(|e| e.to_string())
+}
+
+/// Calls `init` with the filter in `$TOYJS_LOG`, and stays silent if it isn't set.
+pub fn init_from_env() -> Result<(), String> {
+    match std::env::var(LOG_ENV) {
+        Ok(level) => init(&level),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Which levels are logged for which targets, parsed from directives like
+/// `info,toyjs::modules=trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter {
+    /// The level from a bare directive, `OFF` if there is none.
+    default: LevelFilter,
+    /// Targets with a level of their own.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let directive = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len());
+        match directive {
+            Some((_, level)) => *level,
+            None if target == "toyjs" || target.starts_with("toyjs::") => self.default,
+            None => self.default.min(LevelFilter::WARN),
+        }
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<Self, String> {
+        let parse_level = |level: &str| {
+            level.trim().parse::<LevelFilter>().map_err(|_| {
+                format!(
+                    "Invalid log level '{}': expected off, error, warn, info, debug or trace",
+                    level.trim()
+                )
+            })
+        };
+
+        let mut parsed = Filter {
+            default: LevelFilter::OFF,
+            targets: Vec::new(),
+        };
+        for directive in filter.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(format!("Invalid log directive '{}': missing target before '='", directive));
+                    }
+                    parsed.targets.push((target.to_string(), parse_level(level)?));
+                }
+                None => parsed.default = parse_level(directive)?,
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Writes one line per event, prefixed with the time since startup, the level and the
+/// spans the event happened in:
+///
+/// ```text
+///   0.004213 DEBUG execute_module{module="main.js"}:load_module{specifier="./a.js"}: resolved path="/app/a.js"
+/// ```
+struct StderrLogger {
+    filter: Filter,
+    start: Instant,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    /// Handles to the span still alive; it's forgotten once the last one is dropped.
+    refs: usize,
+}
+
+thread_local! {
+    /// The spans entered on this thread, innermost last.
+    static CURRENT_SPANS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+impl StderrLogger {
+    fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            start: Instant::now(),
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `outer{fields}:inner{fields}: ` for the spans entered on this thread.
+    fn span_context(&self) -> String {
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let mut context = String::new();
+        CURRENT_SPANS.with(|current| {
+            for id in current.borrow().iter() {
+                let Some(span) = spans.get(id) else {
+                    continue;
+                };
+                context.push_str(span.name);
+                if !span.fields.is_empty() {
+                    let _ = write!(context, "{{{}}}", span.fields);
+                }
+                context.push(':');
+            }
+        });
+        if !context.is_empty() {
+            context.push(' ');
+        }
+        context
+    }
+}
+
+impl Subscriber for StderrLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max_level())
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = FieldWriter::default();
+        attributes.record(&mut fields);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.spans.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            id,
+            SpanData {
+                name: attributes.metadata().name(),
+                fields: fields.fields,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(span) = spans.get_mut(&span.into_u64()) {
+            let mut fields = FieldWriter {
+                fields: std::mem::take(&mut span.fields),
+                ..Default::default()
+            };
+            values.record(&mut fields);
+            span.fields = fields.fields;
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = FieldWriter::default();
+        event.record(&mut fields);
+
+        let metadata = event.metadata();
+        let mut line = format!(
+            "{:>11.6} {:>5} {}",
+            self.start.elapsed().as_secs_f64(),
+            metadata.level(),
+            self.span_context()
+        );
+        if !metadata.target().starts_with("toyjs") {
+            let _ = write!(line, "{}: ", metadata.target());
+        }
+        line.push_str(&fields.message);
+        if !fields.fields.is_empty() {
+            if !fields.message.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&fields.fields);
+        }
+        line.push('\n');
+        let _ = std::io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn enter(&self, span: &Id) {
+        CURRENT_SPANS.with(|current| current.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        CURRENT_SPANS.with(|current| {
+            let mut current = current.borrow_mut();
+            if let Some(i) = current.iter().rposition(|id| *id == span.into_u64()) {
+                current.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+/// Formats an event's message as-is and its other fields as `name=value`.
+#[derive(Default)]
+struct FieldWriter {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldWriter {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+            return;
+        }
+        if !self.fields.is_empty() {
+            self.fields.push(' ');
+        }
+        let _ = write!(self.fields, "{}={:?}", field.name(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_level_covers_the_runtime_and_caps_other_crates() {
+        let filter: Filter = "debug".parse().unwrap();
+        assert_eq!(filter.level_for("toyjs"), LevelFilter::DEBUG);
+        assert_eq!(filter.level_for("toyjs::runtime::ops"), LevelFilter::DEBUG);
+        assert_eq!(filter.level_for("reqwest::connect"), LevelFilter::WARN);
+        assert_eq!(filter.level_for("toyjsx"), LevelFilter::WARN);
+    }
+
+    #[test]
+    fn target_directives_override_the_default() {
+        let filter: Filter = "warn, toyjs::modules=trace,reqwest=debug".parse().unwrap();
+        assert_eq!(filter.level_for("toyjs::modules"), LevelFilter::TRACE);
+        assert_eq!(filter.level_for("toyjs::modules::loader"), LevelFilter::TRACE);
+        assert_eq!(filter.level_for("toyjs::modulesx"), LevelFilter::WARN);
+        assert_eq!(filter.level_for("toyjs::runtime"), LevelFilter::WARN);
+        assert_eq!(filter.level_for("reqwest::async_impl"), LevelFilter::DEBUG);
+        assert_eq!(filter.max_level(), LevelFilter::TRACE);
+    }
+
+    #[test]
+    fn longest_target_wins() {
+        let filter: Filter = "toyjs=info,toyjs::runtime::ops=off".parse().unwrap();
+        assert_eq!(filter.level_for("toyjs::runtime"), LevelFilter::INFO);
+        assert_eq!(filter.level_for("toyjs::runtime::ops"), LevelFilter::OFF);
+        // Without a bare level, only the named targets log
+        assert_eq!(filter.level_for("hyper"), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        assert!("verbose".parse::<Filter>().unwrap_err().contains("Invalid log level 'verbose'"));
+        assert!("toyjs=loud".parse::<Filter>().unwrap_err().contains("Invalid log level 'loud'"));
+        assert!("=debug".parse::<Filter>().unwrap_err().contains("missing target"));
+        assert_eq!("".parse::<Filter>().unwrap().max_level(), LevelFilter::OFF);
+    }
+}
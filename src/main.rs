@@ -1,5 +1,4 @@
 use toyjs::runtime::JsRuntime;
-use std::time::Duration;
 
 #[tokio::main]
 async fn main() {
@@ -16,11 +15,12 @@ async fn main() {
         }, 1000);
         print("Timer scheduled");
     "#;
-    runtime.execute_script_module(timer_code);
+    if let Err(e) = runtime.execute_script_module(timer_code).await {
+        eprintln!("Error: {}", e);
+    }
 
-    for _ in 0..20 {
-        runtime.process_callbacks();
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    if let Err(e) = runtime.run_until_idle().await {
+        eprintln!("Error: {}", e);
     }
 
     println!("\n--- Test 2: Simple fetch ---");
@@ -38,11 +38,12 @@ async fn main() {
 
         print("Fetch initiated, waiting for response...");
     "#;
-    runtime.execute_script_module(fetch_code);
+    if let Err(e) = runtime.execute_script_module(fetch_code).await {
+        eprintln!("Error: {}", e);
+    }
 
-    for _ in 0..50 {
-        runtime.process_callbacks();
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    if let Err(e) = runtime.run_until_idle().await {
+        eprintln!("Error: {}", e);
     }
 
     println!("\n--- Shutting down ---");
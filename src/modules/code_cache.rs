@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+use v8;
+
+/// Counts of how module compilations used the code cache, for reporting.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CodeCacheStats {
+    /// Modules compiled from cached data that V8 accepted.
+    pub hits: usize,
+    /// Modules with no cached data yet.
+    pub misses: usize,
+    /// Modules whose cached data V8 rejected, e.g. after a V8 flag change.
+    pub rejected: usize,
+}
+
+/// V8 code cache for compiled modules, stored on disk so repeated runs can skip parsing
+/// and compiling unchanged modules.
+///
+/// Each V8 version gets its own subdirectory holding one entry per module, named after
+/// a hash of the module path. An entry starts with a hash of the source it was produced
+/// from, so an edited module misses the cache and its new entry replaces the old one.
+/// V8 checks the data again when consuming it and rejects anything that doesn't match.
+pub struct CodeCache {
+    dir: PathBuf,
+    stats: CodeCacheStats,
+}
+
+impl CodeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into().join(format!("v8-{}", v8::VERSION_STRING)),
+            stats: CodeCacheStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CodeCacheStats {
+        self.stats
+    }
+
+    /// Returns the cached data for this version of the module, if any.
+    pub fn get(&self, path: &str, source: &str) -> Option<Vec<u8>> {
+        let entry = std::fs::read(self.entry_path(path)).ok()?;
+        let data = entry.strip_prefix(&hash(source.as_bytes()).to_le_bytes())?;
+        Some(data.to_vec())
+    }
+
+    /// Stores freshly produced cache data, replacing the entry for any older version of
+    /// the same module.
+    pub fn set(&self, path: &str, source: &str, data: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let mut entry = Vec::with_capacity(8 + data.len());
+        entry.extend_from_slice(&hash(source.as_bytes()).to_le_bytes());
+        entry.extend_from_slice(data);
+
+        // Write to a temporary file first so a concurrent run never reads a partial entry
+        let entry_path = self.entry_path(path);
+        let tmp_path = entry_path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp_path, entry)?;
+        std::fs::rename(&tmp_path, &entry_path)
+    }
+
+    pub(crate) fn record(&mut self, cached: bool, rejected: bool) {
+        match (cached, rejected) {
+            (false, _) => self.stats.misses += 1,
+            (true, false) => self.stats.hits += 1,
+            (true, true) => self.stats.rejected += 1,
+        }
+    }
+
+    fn entry_path(&self, path: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", hash(path.as_bytes())))
+    }
+}
+
+/// FNV-1a, so file names stay the same across builds (unlike `DefaultHasher`).
+pub(super) fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::fixture;
+
+    fn cache(name: &str) -> CodeCache {
+        CodeCache::new(fixture(&format!("code-cache-{}", name), &[]))
+    }
+
+    fn entries(cache: &CodeCache) -> usize {
+        std::fs::read_dir(&cache.dir).map_or(0, |entries| entries.count())
+    }
+
+    #[test]
+    fn returns_data_for_the_same_source_only() {
+        let cache = cache("source");
+        assert_eq!(cache.get("/app/main.js", "let a = 1;"), None);
+
+        cache.set("/app/main.js", "let a = 1;", b"compiled").unwrap();
+        assert_eq!(cache.get("/app/main.js", "let a = 1;").as_deref(), Some(&b"compiled"[..]));
+        assert_eq!(cache.get("/app/main.js", "let a = 2;"), None);
+        assert_eq!(cache.get("/app/other.js", "let a = 1;"), None);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn keeps_one_entry_per_module() {
+        let cache = cache("entries");
+        cache.set("/app/main.js", "let a = 1;", b"one").unwrap();
+        cache.set("/app/main.js", "let a = 2;", b"two").unwrap();
+        cache.set("/app/util.js", "let b = 1;", b"util").unwrap();
+
+        assert_eq!(entries(&cache), 2);
+        assert_eq!(cache.get("/app/main.js", "let a = 1;"), None);
+        assert_eq!(cache.get("/app/main.js", "let a = 2;").as_deref(), Some(&b"two"[..]));
+        assert_eq!(cache.get("/app/util.js", "let b = 1;").as_deref(), Some(&b"util"[..]));
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn separates_v8_versions() {
+        let cache = cache("version");
+        assert!(cache.dir.ends_with(format!("v8-{}", v8::VERSION_STRING)));
+    }
+
+    #[test]
+    fn counts_hits_misses_and_rejections() {
+        let mut cache = cache("stats");
+        cache.record(false, false);
+        cache.record(true, false);
+        cache.record(true, false);
+        cache.record(true, true);
+
+        assert_eq!(
+            cache.stats(),
+            CodeCacheStats {
+                hits: 2,
+                misses: 1,
+                rejected: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use serde_json::Value;
+use std::path::Path;
+use url::Url;
+
+/// An import map, as described by the WICG import maps spec: `imports` remaps specifiers
+/// for every module, and `scopes` remaps them only for modules under a URL prefix.
+///
+/// Keys ending in `/` remap every specifier starting with them. Addresses are URLs,
+/// resolved against the URL of the map itself. A `null` (or invalid) address blocks
+/// the specifier instead of letting it fall through.
+#[derive(Debug, Clone)]
+pub struct ImportMap {
+    imports: SpecifierMap,
+    /// Scope prefixes with their maps, most specific first.
+    scopes: Vec<(String, SpecifierMap)>,
+}
+
+/// Normalized specifiers and their addresses, sorted so longer prefixes come first.
+type SpecifierMap = Vec<(String, Option<Url>)>;
+
+impl ImportMap {
+    /// Reads an import map file. Relative addresses resolve against its location.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read import map '{}': {}", path.display(), e))?;
+        let base_url = std::path::absolute(path)
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+            .ok_or_else(|| format!("Invalid import map path '{}'", path.display()))?;
+        Self::parse(&json, &base_url).map_err(|e| format!("Invalid import map '{}': {}", path.display(), e))
+    }
+
+    /// Parses an import map whose relative addresses resolve against `base_url`.
+    /// Invalid entries are skipped or blocked, as the spec asks, rather than failing.
+    pub fn parse(json: &str, base_url: &Url) -> Result<Self, String> {
+        let json: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let json = json
+            .as_object()
+            .ok_or("the top-level value must be a JSON object")?;
+
+        let imports = match json.get("imports") {
+            Some(Value::Object(imports)) => specifier_map(imports, base_url),
+            Some(_) => return Err("\"imports\" must be a JSON object".to_string()),
+            None => Vec::new(),
+        };
+
+        let mut scopes = Vec::new();
+        match json.get("scopes") {
+            Some(Value::Object(map)) => {
+                for (prefix, imports) in map {
+                    let Value::Object(imports) = imports else {
+                        return Err(format!("the scope \"{}\" must be a JSON object", prefix));
+                    };
+                    if let Ok(prefix) = base_url.join(prefix) {
+                        scopes.push((prefix.to_string(), specifier_map(imports, base_url)));
+                    }
+                }
+            }
+            Some(_) => return Err("\"scopes\" must be a JSON object".to_string()),
+            None => {}
+        }
+        scopes.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(Self { imports, scopes })
+    }
+
+    /// Applies the map to `specifier` imported from the module at `referrer`. Returns
+    /// `Ok(None)` when no entry matches, so the specifier resolves as usual.
+    pub fn resolve(&self, specifier: &str, referrer: &Url) -> Result<Option<Url>, String> {
+        let as_url = parse_url_like(specifier, referrer);
+        let normalized = as_url.as_ref().map_or(specifier.to_string(), |url| url.to_string());
+        let referrer = referrer.as_str();
+
+        for (prefix, imports) in &self.scopes {
+            let in_scope = prefix == referrer || (prefix.ends_with('/') && referrer.starts_with(prefix.as_str()));
+            if in_scope && let Some(url) = resolve_imports_match(&normalized, as_url.as_ref(), imports)? {
+                return Ok(Some(url));
+            }
+        }
+        resolve_imports_match(&normalized, as_url.as_ref(), &self.imports)
+    }
+}
+
+fn specifier_map(map: &serde_json::Map<String, Value>, base_url: &Url) -> SpecifierMap {
+    let mut entries: SpecifierMap = map
+        .iter()
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, address)| {
+            let key = parse_url_like(key, base_url).map_or(key.clone(), |url| url.to_string());
+            let address = address
+                .as_str()
+                .and_then(|address| parse_url_like(address, base_url))
+                // A prefix must map to a prefix
+                .filter(|address| !key.ends_with('/') || address.as_str().ends_with('/'));
+            (key, address)
+        })
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| b.cmp(a));
+    entries
+}
+
+/// Parses a specifier that is a URL, or a path starting with `/`, `./` or `../`.
+/// Anything else is a bare specifier.
+fn parse_url_like(specifier: &str, base_url: &Url) -> Option<Url> {
+    if specifier.starts_with('/') || specifier.starts_with("./") || specifier.starts_with("../") {
+        return base_url.join(specifier).ok();
+    }
+    Url::parse(specifier).ok()
+}
+
+fn resolve_imports_match(normalized: &str, as_url: Option<&Url>, imports: &SpecifierMap) -> Result<Option<Url>, String> {
+    for (key, address) in imports {
+        if key == normalized {
+            return address
+                .clone()
+                .map(Some)
+                .ok_or_else(|| format!("Import of '{}' is blocked by the import map", normalized));
+        }
+
+        let is_prefix_match = key.ends_with('/')
+            && normalized.starts_with(key.as_str())
+            && as_url.is_none_or(|url| url.is_special());
+        if !is_prefix_match {
+            continue;
+        }
+
+        let Some(address) = address else {
+            return Err(format!("Import of '{}' is blocked by the import map", normalized));
+        };
+        let after_prefix = &normalized[key.len()..];
+        let url = address
+            .join(after_prefix)
+            .map_err(|_| format!("Import of '{}' maps to an invalid URL", normalized))?;
+        // "../" in the rest of the specifier must not escape the address
+        if !url.as_str().starts_with(address.as_str()) {
+            return Err(format!("Import of '{}' escapes its import map address", normalized));
+        }
+        return Ok(Some(url));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(json: &str) -> ImportMap {
+        ImportMap::parse(json, &Url::parse("file:///app/importmap.json").unwrap()).unwrap()
+    }
+
+    fn resolve(map: &ImportMap, specifier: &str, referrer: &str) -> Result<Option<String>, String> {
+        map.resolve(specifier, &Url::parse(referrer).unwrap())
+            .map(|url| url.map(|url| url.to_string()))
+    }
+
+    #[test]
+    fn maps_exact_specifiers_and_prefixes() {
+        let map = map(r#"{"imports": {
+            "lodash": "./vendor/lodash.js",
+            "lib/": "./vendor/lib/",
+            "lib/special/": "https://cdn.example.com/special/"
+        }}"#);
+        let main = "file:///app/main.js";
+
+        assert_eq!(resolve(&map, "lodash", main).unwrap().as_deref(), Some("file:///app/vendor/lodash.js"));
+        assert_eq!(resolve(&map, "lib/a/b.js", main).unwrap().as_deref(), Some("file:///app/vendor/lib/a/b.js"));
+        assert_eq!(
+            resolve(&map, "lib/special/x.js", main).unwrap().as_deref(),
+            Some("https://cdn.example.com/special/x.js")
+        );
+        assert_eq!(resolve(&map, "lodash/fp", main).unwrap(), None);
+        assert_eq!(resolve(&map, "./local.js", main).unwrap(), None);
+    }
+
+    #[test]
+    fn maps_relative_and_url_specifiers_after_normalizing_them() {
+        let map = map(r#"{"imports": {
+            "./src/config.js": "./src/config.prod.js",
+            "https://cdn.example.com/": "/app/mirror/"
+        }}"#);
+
+        assert_eq!(
+            resolve(&map, "./config.js", "file:///app/src/main.js").unwrap().as_deref(),
+            Some("file:///app/src/config.prod.js")
+        );
+        assert_eq!(
+            resolve(&map, "https://cdn.example.com/lib.js", "file:///app/main.js").unwrap().as_deref(),
+            Some("file:///app/mirror/lib.js")
+        );
+    }
+
+    #[test]
+    fn scopes_apply_to_modules_under_their_prefix() {
+        let map = map(r#"{
+            "imports": {"lodash": "./vendor/lodash.js"},
+            "scopes": {
+                "/app/legacy/": {"lodash": "./vendor/lodash-3.js"},
+                "/app/legacy/new/": {"lodash": "./vendor/lodash-4.js"}
+            }
+        }"#);
+
+        assert_eq!(
+            resolve(&map, "lodash", "file:///app/main.js").unwrap().as_deref(),
+            Some("file:///app/vendor/lodash.js")
+        );
+        assert_eq!(
+            resolve(&map, "lodash", "file:///app/legacy/index.js").unwrap().as_deref(),
+            Some("file:///app/vendor/lodash-3.js")
+        );
+        assert_eq!(
+            resolve(&map, "lodash", "file:///app/legacy/new/index.js").unwrap().as_deref(),
+            Some("file:///app/vendor/lodash-4.js")
+        );
+    }
+
+    #[test]
+    fn blocks_null_and_invalid_addresses() {
+        let map = map(r#"{"imports": {
+            "blocked": null,
+            "not-a-prefix/": "./vendor/file.js",
+            "lib/": "./vendor/lib/"
+        }}"#);
+        let main = "file:///app/main.js";
+
+        assert!(resolve(&map, "blocked", main).unwrap_err().contains("blocked by the import map"));
+        assert!(resolve(&map, "not-a-prefix/x.js", main).unwrap_err().contains("blocked by the import map"));
+        assert!(resolve(&map, "lib/../../secret.js", main).unwrap_err().contains("escapes"));
+    }
+
+    #[test]
+    fn rejects_malformed_maps() {
+        let base = Url::parse("file:///app/importmap.json").unwrap();
+        assert!(ImportMap::parse("[]", &base).is_err());
+        assert!(ImportMap::parse(r#"{"imports": []}"#, &base).is_err());
+        assert!(ImportMap::parse(r#"{"scopes": {"/x/": "y"}}"#, &base).is_err());
+        assert!(ImportMap::parse("{", &base).is_err());
+        assert!(ImportMap::parse("{}", &base).is_ok());
+    }
+}
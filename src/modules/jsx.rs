@@ -0,0 +1,427 @@
+use super::typescript::{TypeStripError, error_at, expression_end, strip_expression};
+use std::borrow::Cow;
+
+/// The functions JSX compiles to, like TypeScript's classic `"jsx": "react"` mode:
+/// `React.createElement` and `React.Fragment`, unless the leading comments of the file
+/// name others with `@jsx h` and `@jsxFrag Fragment`. The module has to bring them into
+/// scope itself, e.g. with `import * as React from "react"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Pragma {
+    factory: String,
+    fragment: String,
+}
+
+impl Pragma {
+    pub(super) fn from_source(src: &str) -> Self {
+        let mut pragma = Self {
+            factory: "React.createElement".to_string(),
+            fragment: "React.Fragment".to_string(),
+        };
+        for comment in leading_comments(src) {
+            let words: Vec<&str> = comment.split_whitespace().collect();
+            for pair in words.windows(2) {
+                match pair[0] {
+                    "@jsx" => pragma.factory = pair[1].to_string(),
+                    "@jsxFrag" => pragma.fragment = pair[1].to_string(),
+                    _ => {}
+                }
+            }
+        }
+        pragma
+    }
+}
+
+/// The text of the comments before the first token of `src`.
+fn leading_comments(src: &str) -> Vec<&str> {
+    let mut comments = Vec::new();
+    let mut rest = src
+        .strip_prefix("#!")
+        .map_or(src, |rest| rest.find('\n').map_or("", |end| &rest[end..]));
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("//") {
+            let end = comment.find('\n').unwrap_or(comment.len());
+            comments.push(&comment[..end]);
+            rest = &comment[end..];
+        } else if let Some(comment) = rest.strip_prefix("/*")
+            && let Some(end) = comment.find("*/")
+        {
+            comments.push(&comment[..end]);
+            rest = &comment[end + 2..];
+        } else {
+            return comments;
+        }
+    }
+}
+
+/// Compiles the JSX element or fragment starting at `start` to nested calls of the
+/// pragma's factory. Expressions inside it have their types stripped. The result has
+/// the same line breaks as the JSX, so the code after it stays on its original line.
+pub(super) fn compile(src: &str, start: usize, pragma: &Pragma) -> Result<String, TypeStripError> {
+    Compiler {
+        src,
+        i: start,
+        pragma: Some(pragma),
+    }
+    .element()
+}
+
+/// Where the JSX element or fragment starting at `start` ends, for the tokenizer.
+pub(super) fn element_end(src: &str, start: usize) -> Result<usize, TypeStripError> {
+    let mut compiler = Compiler {
+        src,
+        i: start,
+        pragma: None,
+    };
+    compiler.element()?;
+    Ok(compiler.i)
+}
+
+struct Compiler<'a> {
+    src: &'a str,
+    i: usize,
+    /// `None` when only looking for the end of the element, which skips stripping the
+    /// expressions in it.
+    pragma: Option<&'a Pragma>,
+}
+
+impl<'a> Compiler<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.i).copied()
+    }
+
+    fn error(&self, offset: usize, message: &str) -> TypeStripError {
+        error_at(self.src, offset, message)
+    }
+
+    /// Skips whitespace and comments inside a tag, adding their line breaks to `out`.
+    fn skip_trivia(&mut self, out: &mut String) -> Result<(), TypeStripError> {
+        let start = self.i;
+        loop {
+            let rest = &self.src[self.i..];
+            let trimmed = rest.trim_start();
+            self.i += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.i += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                let end = trimmed
+                    .find("*/")
+                    .ok_or_else(|| self.error(self.i, "Unterminated comment"))?;
+                self.i += end + 2;
+            } else {
+                break;
+            }
+        }
+        out.push_str(&line_breaks(&self.src[start..self.i]));
+        Ok(())
+    }
+
+    /// A tag or attribute name. Tag names may be member expressions like `Menu.Item`.
+    fn name(&mut self, what: &str) -> Result<&'a str, TypeStripError> {
+        let start = self.i;
+        let rest = &self.src[start..];
+        let len = rest
+            .find(|c: char| {
+                !(c == '$' || c == '_' || c == '-' || c == '.' || c == ':' || c.is_alphanumeric())
+            })
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error(start, &format!("Expected a JSX {}", what)));
+        }
+        self.i += len;
+        Ok(&self.src[start..self.i])
+    }
+
+    /// The expression between `start` and `end`, with its types stripped.
+    fn expression(&self, start: usize, end: usize) -> Result<String, TypeStripError> {
+        match self.pragma {
+            Some(pragma) => strip_expression(self.src, start, end, pragma),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn element(&mut self) -> Result<String, TypeStripError> {
+        let start = self.i;
+        self.i += 1;
+        let mut props = String::new();
+        self.skip_trivia(&mut props)?;
+        let name = if self.peek() == Some(b'>') {
+            None
+        } else {
+            Some(self.name("tag name")?)
+        };
+
+        let mut has_props = false;
+        let self_closing = loop {
+            self.skip_trivia(&mut props)?;
+            match self.peek() {
+                Some(b'/') if self.src[self.i..].starts_with("/>") => {
+                    self.i += 2;
+                    break true;
+                }
+                Some(b'>') => {
+                    self.i += 1;
+                    break false;
+                }
+                Some(b'{') if name.is_some() => {
+                    // `{...props}`
+                    self.i += 1;
+                    self.skip_trivia(&mut props)?;
+                    if !self.src[self.i..].starts_with("...") {
+                        return Err(self.error(self.i, "Expected '...' in a JSX spread attribute"));
+                    }
+                    let close = expression_end(self.src, self.i + 3)?;
+                    props.push_str("...");
+                    props.push_str(&self.expression(self.i + 3, close)?);
+                    props.push_str(", ");
+                    self.i = close + 1;
+                }
+                Some(_) if name.is_some() => {
+                    let attribute = self.name("attribute name")?;
+                    let key = if attribute.contains(['-', ':']) {
+                        quote(attribute)
+                    } else {
+                        attribute.to_string()
+                    };
+                    let mut after = String::new();
+                    self.skip_trivia(&mut after)?;
+                    let value = if self.peek() == Some(b'=') {
+                        self.i += 1;
+                        self.skip_trivia(&mut after)?;
+                        self.attribute_value(&mut after)?
+                    } else {
+                        "true".to_string()
+                    };
+                    props.push_str(&format!("{}: {}, {}", key, value, after));
+                }
+                _ => return Err(self.error(start, "Unterminated JSX element")),
+            }
+            has_props |= name.is_some() && !props.trim().is_empty();
+        };
+
+        let mut children = String::new();
+        if !self_closing {
+            self.children(start, &mut children)?;
+            let close_start = self.i;
+            self.i += 2;
+            self.skip_trivia(&mut children)?;
+            let close_name = if self.peek() == Some(b'>') {
+                None
+            } else {
+                Some(self.name("tag name")?)
+            };
+            self.skip_trivia(&mut children)?;
+            if self.peek() != Some(b'>') {
+                return Err(self.error(self.i, "Expected '>' to end the JSX closing tag"));
+            }
+            self.i += 1;
+            if close_name != name {
+                let message = match name {
+                    Some(name) => format!("Expected corresponding JSX closing tag for '{}'", name),
+                    None => "Expected corresponding closing tag for JSX fragment".to_string(),
+                };
+                return Err(self.error(close_start, &message));
+            }
+        }
+
+        let Some(pragma) = self.pragma else {
+            return Ok(String::new());
+        };
+        let element_type = match name {
+            None => pragma.fragment.clone(),
+            Some(name) if is_intrinsic(name) => quote(name),
+            Some(name) => name.to_string(),
+        };
+        let props = if has_props {
+            format!("{{ {}}}", props)
+        } else {
+            format!("null{}", props)
+        };
+        Ok(format!(
+            "{}({}, {}{})",
+            pragma.factory, element_type, props, children
+        ))
+    }
+
+    /// An attribute value after its `=`: a string, an expression or an element.
+    fn attribute_value(&mut self, after: &mut String) -> Result<String, TypeStripError> {
+        let start = self.i;
+        match self.peek() {
+            Some(quote_char @ (b'"' | b'\'')) => {
+                let end = self.src[start + 1..]
+                    .find(quote_char as char)
+                    .map(|end| start + 1 + end)
+                    .ok_or_else(|| self.error(start, "Unterminated string literal"))?;
+                let raw = &self.src[start + 1..end];
+                self.i = end + 1;
+                after.push_str(&line_breaks(raw));
+                Ok(quote(&decode_entities(raw)))
+            }
+            Some(b'{') => {
+                let close = expression_end(self.src, start + 1)?;
+                if is_empty_expression(&self.src[start + 1..close]) {
+                    return Err(self.error(
+                        start,
+                        "JSX attributes must only be assigned a non-empty expression",
+                    ));
+                }
+                self.i = close + 1;
+                self.expression(start + 1, close)
+            }
+            Some(b'<') => self.element(),
+            _ => Err(self.error(start, "Expected a JSX attribute value")),
+        }
+    }
+
+    /// Children up to the closing tag of the element at `open`, each appended to `out`
+    /// after a comma.
+    fn children(&mut self, open: usize, out: &mut String) -> Result<(), TypeStripError> {
+        loop {
+            let start = self.i;
+            match self.peek() {
+                None => return Err(self.error(open, "Unterminated JSX contents")),
+                Some(b'<') if self.src[start..].starts_with("</") => return Ok(()),
+                Some(b'<') => {
+                    let child = self.element()?;
+                    out.push_str(", ");
+                    out.push_str(&child);
+                }
+                Some(b'{') => {
+                    let close = expression_end(self.src, start + 1)?;
+                    if is_empty_expression(&self.src[start + 1..close]) {
+                        // `{/* comment */}`
+                        out.push_str(&line_breaks(&self.src[start..close]));
+                    } else {
+                        out.push_str(", ");
+                        out.push_str(&self.expression(start + 1, close)?);
+                    }
+                    self.i = close + 1;
+                }
+                Some(_) => {
+                    let end = self.src[start..]
+                        .find(['<', '{'])
+                        .map_or(self.src.len(), |end| start + end);
+                    let raw = &self.src[start..end];
+                    let text = clean_text(&decode_entities(raw));
+                    if !text.is_empty() {
+                        out.push_str(", ");
+                        out.push_str(&quote(&text));
+                    }
+                    out.push_str(&line_breaks(raw));
+                    self.i = end;
+                }
+            }
+        }
+    }
+}
+
+/// Lowercase names like `div` and names with a dash or namespace are HTML elements,
+/// passed as strings. Anything else names a component in scope.
+fn is_intrinsic(name: &str) -> bool {
+    !name.contains('.')
+        && (name.starts_with(|c: char| c.is_ascii_lowercase()) || name.contains(['-', ':']))
+}
+
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// One line break for each in `text`, to keep the compiled code on the same lines.
+fn line_breaks(text: &str) -> String {
+    "\n".repeat(text.matches('\n').count())
+}
+
+/// Whether the contents of `{...}` hold nothing but whitespace and comments.
+fn is_empty_expression(text: &str) -> bool {
+    let mut rest = text.trim_start();
+    loop {
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+        } else if let Some(comment) = rest.strip_prefix("/*")
+            && let Some(end) = comment.find("*/")
+        {
+            rest = &comment[end + 2..];
+        } else {
+            return rest.is_empty();
+        }
+        rest = rest.trim_start();
+    }
+}
+
+/// Collapses the whitespace of JSX text the way React compilers do: lines are trimmed,
+/// blank lines dropped, and the remaining lines joined with a space.
+fn clean_text(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect();
+    let last_non_empty = lines
+        .iter()
+        .rposition(|line| line.contains(|c| c != ' ' && c != '\t'));
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let mut line = line.replace('\t', " ");
+        if i != 0 {
+            line = line.trim_start_matches(' ').to_string();
+        }
+        if i != lines.len() - 1 {
+            line = line.trim_end_matches(' ').to_string();
+        }
+        if !line.is_empty() {
+            out.push_str(&line);
+            if Some(i) != last_non_empty {
+                out.push(' ');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes the HTML character references JSX text and attribute strings may use: the
+/// named ones for markup characters and `&nbsp;`, and numeric ones.
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((entity(&rest[1..end])?, end)));
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+fn entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
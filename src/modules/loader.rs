@@ -0,0 +1,296 @@
+use super::remote::{RemoteModules, is_remote};
+use super::resolve::{self, ResolveError};
+use super::ImportMap;
+use std::collections::HashMap;
+use url::Url;
+
+/// Where a runtime's modules come from. `resolve` turns an import specifier into the name
+/// of a module and `load` returns that module's source.
+///
+/// Names are what the runtime caches modules by, what relative imports inside them are
+/// resolved against, and what stack traces and `import.meta.url` report. Everything
+/// after loading, including JSON modules and stripping TypeScript, is up to the runtime,
+/// based on the name's extension and the import's `type` attribute.
+///
+/// A loader is shared with the runtime's workers, which run on other threads.
+pub trait ModuleLoader: Send + Sync {
+    /// Resolves `specifier` imported from `referrer`: the name of the importing module,
+    /// or the current directory for imports from scripts.
+    fn resolve(&self, specifier: &str, referrer: &str) -> Result<String, String>;
+
+    /// Loads the module `name` returned by `resolve`.
+    fn load(&self, name: &str) -> Result<ModuleSource, String>;
+}
+
+/// The source of a loaded module.
+#[derive(Debug, Clone)]
+pub struct ModuleSource {
+    /// The name the module was loaded under. Usually the requested one, but a download
+    /// that was redirected ends up at a different URL.
+    pub name: String,
+    pub code: String,
+}
+
+/// The default loader: modules are files, with Node-style resolution (see
+/// `resolve::resolve`) and an optional import map, or `http:` and `https:` URLs.
+#[derive(Default)]
+pub struct FsModuleLoader {
+    pub import_map: Option<ImportMap>,
+    pub remote_modules: RemoteModules,
+}
+
+impl FsModuleLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves an import specifier to a canonical file path. See `resolve::resolve`.
+    pub fn resolve_path(base: &str, specifier: &str) -> Result<String, ResolveError> {
+        resolve::resolve(base, specifier)
+    }
+
+    /// Resolves without the import map. URLs are used as-is, and relative specifiers in
+    /// remote modules resolve against the module's URL.
+    fn resolve_unmapped(base: &str, specifier: &str) -> Result<String, String> {
+        if is_remote(specifier) {
+            return Url::parse(specifier)
+                .map(|url| url.to_string())
+                .map_err(|e| format!("Invalid module URL '{}': {}", specifier, e));
+        }
+        if !is_remote(base) {
+            return Self::resolve_path(base, specifier).map_err(|e| e.to_string());
+        }
+
+        let is_relative = specifier.starts_with('/') || specifier.starts_with("./") || specifier.starts_with("../");
+        if !is_relative {
+            return Err(format!(
+                "Cannot resolve '{}' imported from '{}': bare specifiers in remote modules need an import map entry",
+                specifier, base
+            ));
+        }
+        Url::parse(base)
+            .and_then(|base_url| base_url.join(specifier))
+            .map(|url| url.to_string())
+            .map_err(|e| format!("Cannot resolve '{}' imported from '{}': {}", specifier, base, e))
+    }
+}
+
+impl ModuleLoader for FsModuleLoader {
+    /// Applies the import map first if there is one. Returns a file path for local modules
+    /// and a URL for remote ones.
+    fn resolve(&self, specifier: &str, base: &str) -> Result<String, String> {
+        let Some(import_map) = &self.import_map else {
+            return Self::resolve_unmapped(base, specifier);
+        };
+
+        let referrer = referrer_url(base)
+            .ok_or_else(|| format!("Cannot resolve '{}': invalid referrer '{}'", specifier, base))?;
+        match import_map.resolve(specifier, &referrer)? {
+            Some(url) if url.scheme() == "file" => {
+                if is_remote(base) {
+                    return Err(format!("Remote module '{}' cannot import local file '{}'", base, url));
+                }
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| format!("Import of '{}' maps to an invalid file URL '{}'", specifier, url))?;
+                Self::resolve_path(base, &path.to_string_lossy()).map_err(|e| e.to_string())
+            }
+            Some(url) if is_remote(url.as_str()) => Ok(url.to_string()),
+            Some(url) => Err(format!(
+                "Import of '{}' maps to '{}', but only file:, http: and https: URLs are supported",
+                specifier, url
+            )),
+            None => Self::resolve_unmapped(base, specifier),
+        }
+    }
+
+    /// Local modules are loaded under their canonical path, so an entry module given by a
+    /// relative path is the same module its importers resolve to.
+    fn load(&self, name: &str) -> Result<ModuleSource, String> {
+        if is_remote(name) {
+            return self.remote_modules.load(name);
+        }
+        let read_error = |e: std::io::Error| format!("Failed to read module '{}': {}", name, e);
+        let path = std::fs::canonicalize(name).map_err(read_error)?;
+        let code = std::fs::read_to_string(&path).map_err(read_error)?;
+        Ok(ModuleSource {
+            name: path.to_string_lossy().into_owned(),
+            code,
+        })
+    }
+}
+
+/// The URL import map entries are matched against for a module at `base`. Scripts
+/// without a module resolve against a directory, which needs a trailing slash.
+fn referrer_url(base: &str) -> Option<Url> {
+    if is_remote(base) {
+        return Url::parse(base).ok();
+    }
+    let path = std::path::absolute(base).ok()?;
+    if path.is_dir() {
+        Url::from_directory_path(path).ok()
+    } else {
+        Url::from_file_path(path).ok()
+    }
+}
+
+/// Serves module sources from memory, for applications that embed their scripts (e.g.
+/// with `include_str!`) instead of reading them from disk.
+///
+/// Names are absolute paths like `/app/main.js`. `/`, `./` and `../` specifiers resolve
+/// against the importing module's directory, and may leave out a `.js` or `.ts`
+/// extension or name a directory with an `index.js`. Other specifiers are looked up as
+/// names, so a module added as `lodash` can be imported with `import "lodash"`.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryModuleLoader {
+    modules: HashMap<String, String>,
+}
+
+impl InMemoryModuleLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the module `name`, replacing any earlier one.
+    pub fn with_module(mut self, name: impl Into<String>, code: impl Into<String>) -> Self {
+        self.insert(name, code);
+        self
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, code: impl Into<String>) {
+        self.modules.insert(name.into(), code.into());
+    }
+
+    /// The directory relative specifiers in `referrer` resolve against. Referrers that
+    /// aren't modules are directories already, like the working directory of a script.
+    fn referrer_dir<'a>(&self, referrer: &'a str) -> &'a str {
+        if self.modules.contains_key(referrer) {
+            referrer.rsplit_once('/').map_or("", |(dir, _)| dir)
+        } else {
+            referrer.trim_end_matches('/')
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for InMemoryModuleLoader {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            modules: iter.into_iter().map(|(name, code)| (name.into(), code.into())).collect(),
+        }
+    }
+}
+
+impl ModuleLoader for InMemoryModuleLoader {
+    fn resolve(&self, specifier: &str, referrer: &str) -> Result<String, String> {
+        let is_relative = specifier.starts_with("./")
+            || specifier.starts_with("../")
+            || specifier == "."
+            || specifier == "..";
+        let path = if specifier.starts_with('/') {
+            normalize_path(specifier)
+        } else if is_relative {
+            normalize_path(&format!("{}/{}", self.referrer_dir(referrer), specifier))
+        } else if self.modules.contains_key(specifier) {
+            return Ok(specifier.to_string());
+        } else {
+            return Err(format!("Cannot find module '{}' imported from '{}'", specifier, referrer));
+        };
+
+        let candidates = [
+            path.clone(),
+            format!("{}.js", path),
+            format!("{}.ts", path),
+            format!("{}/index.js", path),
+            format!("{}/index.ts", path),
+        ];
+        candidates
+            .into_iter()
+            .find(|name| self.modules.contains_key(name))
+            .ok_or_else(|| format!("Cannot find module '{}' imported from '{}'", specifier, referrer))
+    }
+
+    fn load(&self, name: &str) -> Result<ModuleSource, String> {
+        let code = self
+            .modules
+            .get(name)
+            .ok_or_else(|| format!("Module '{}' not found", name))?;
+        Ok(ModuleSource {
+            name: name.to_string(),
+            code: code.clone(),
+        })
+    }
+}
+
+/// Resolves `.` and `..` segments of an absolute path without touching the filesystem.
+fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loader() -> InMemoryModuleLoader {
+        [
+            ("/app/main.js", "import './lib/util.js';"),
+            ("/app/lib/util.js", ""),
+            ("/app/lib/types.ts", ""),
+            ("/app/widgets/index.js", ""),
+            ("lodash", "export default {};"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn resolves_relative_specifiers_against_the_importing_module() {
+        let loader = loader();
+        assert_eq!(loader.resolve("./lib/util.js", "/app/main.js").unwrap(), "/app/lib/util.js");
+        assert_eq!(loader.resolve("../main.js", "/app/lib/util.js").unwrap(), "/app/main.js");
+        assert_eq!(loader.resolve("/app/lib/../main.js", "/app/lib/util.js").unwrap(), "/app/main.js");
+        // Referrers that aren't modules are directories
+        assert_eq!(loader.resolve("./main.js", "/app").unwrap(), "/app/main.js");
+    }
+
+    #[test]
+    fn tries_extensions_and_index_files() {
+        let loader = loader();
+        assert_eq!(loader.resolve("./lib/util", "/app/main.js").unwrap(), "/app/lib/util.js");
+        assert_eq!(loader.resolve("./lib/types", "/app/main.js").unwrap(), "/app/lib/types.ts");
+        assert_eq!(loader.resolve("./widgets", "/app/main.js").unwrap(), "/app/widgets/index.js");
+    }
+
+    #[test]
+    fn looks_up_bare_specifiers_as_names() {
+        let loader = loader();
+        assert_eq!(loader.resolve("lodash", "/app/main.js").unwrap(), "lodash");
+        assert_eq!(
+            loader.resolve("react", "/app/main.js").unwrap_err(),
+            "Cannot find module 'react' imported from '/app/main.js'"
+        );
+        assert_eq!(
+            loader.resolve("./missing", "/app/main.js").unwrap_err(),
+            "Cannot find module './missing' imported from '/app/main.js'"
+        );
+    }
+
+    #[test]
+    fn loads_modules_by_name() {
+        let mut loader = loader();
+        assert_eq!(loader.load("lodash").unwrap().code, "export default {};");
+        loader.insert("lodash", "export default 1;");
+        let source = loader.load("lodash").unwrap();
+        assert_eq!((source.name.as_str(), source.code.as_str()), ("lodash", "export default 1;"));
+        assert_eq!(loader.load("/app/missing.js").unwrap_err(), "Module '/app/missing.js' not found");
+    }
+}
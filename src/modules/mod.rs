@@ -1,67 +1,137 @@
 use std::collections::HashMap;
 use std::num::NonZero;
 use std::path::Path;
-use std::sync::{Mutex, OnceLock};
+use std::sync::Arc;
+use url::Url;
 use v8;
 
-static MODULE_LOADER: OnceLock<Mutex<FsModuleLoader>> = OnceLock::new();
+mod code_cache;
+mod import_map;
+mod jsx;
+mod loader;
+mod remote;
+mod resolve;
+mod typescript;
 
-pub struct FsModuleLoader {
-    pub modules: HashMap<String, v8::Global<v8::Module>>,
-    pub paths: HashMap<NonZero<i32>, String>,
+pub use code_cache::{CodeCache, CodeCacheStats};
+pub use import_map::ImportMap;
+pub use loader::{FsModuleLoader, InMemoryModuleLoader, ModuleLoader, ModuleSource};
+pub use remote::{RemoteModules, is_remote};
+pub use resolve::ResolveError;
+pub use typescript::{TypeStripError, is_typescript, strip_if_typescript, strip_types};
+
+/// How a module's source is turned into a module record, chosen by the `type` import
+/// attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModuleType {
+    JavaScript,
+    /// A synthetic module whose default export is the parsed JSON.
+    Json,
 }
 
-// SAFETY: We only access FsModuleLoader from the V8 isolate thread
-unsafe impl Send for FsModuleLoader {}
+impl ModuleType {
+    /// Picks the type for the module at `path` from the `type` attribute it was imported
+    /// with. Files that aren't JavaScript must say what they are.
+    pub fn from_attribute(path: &str, type_attribute: Option<&str>) -> Result<Self, String> {
+        match type_attribute {
+            Some("json") => Ok(Self::Json),
+            Some(other) => Err(format!("Unknown module type '{}' for '{}'", other, path)),
+            None if is_javascript(path) => Ok(Self::JavaScript),
+            None if extension(path).as_deref() == Some("json") => Err(format!(
+                "'{}' is a JSON module and must be imported with {{ type: \"json\" }}",
+                path
+            )),
+            None => Err(format!(
+                "'{}' is not a JavaScript module; import it with a supported type attribute",
+                path
+            )),
+        }
+    }
+}
 
-impl FsModuleLoader {
-    pub fn new() -> Self {
+fn is_javascript(path: &str) -> bool {
+    match extension(path).as_deref() {
+        Some(ext) => ext == "js" || ext == "mjs" || ext == "cjs" || is_typescript(path),
+        None => true,
+    }
+}
+
+/// The extension of a module path, or of the path part of a remote module URL.
+fn extension(path: &str) -> Option<String> {
+    let ext = if is_remote(path) {
+        let url = Url::parse(path).ok()?;
+        Path::new(url.path()).extension()?.to_str()?.to_string()
+    } else {
+        Path::new(path).extension()?.to_str()?.to_string()
+    };
+    Some(ext)
+}
+
+/// The modules compiled by one runtime. Kept in an isolate slot, since module handles
+/// belong to a single isolate, and dropped together with it.
+pub struct ModuleMap {
+    /// Keyed by name and type: the same file imported as JavaScript and as JSON gives two
+    /// different modules.
+    pub modules: HashMap<(String, ModuleType), v8::Global<v8::Module>>,
+    pub paths: HashMap<NonZero<i32>, String>,
+    /// Parsed values of JSON modules that haven't been evaluated yet, by identity hash.
+    pub json_values: HashMap<NonZero<i32>, v8::Global<v8::Value>>,
+    pub code_cache: Option<CodeCache>,
+    /// Identity hash of the entry module, for `import.meta.main`.
+    pub main_module: Option<NonZero<i32>>,
+    /// Resolves and loads every module of the runtime.
+    pub loader: Arc<dyn ModuleLoader>,
+}
+
+impl ModuleMap {
+    pub fn new(loader: Arc<dyn ModuleLoader>) -> Self {
         Self {
             modules: HashMap::new(),
             paths: HashMap::new(),
+            json_values: HashMap::new(),
+            code_cache: None,
+            main_module: None,
+            loader,
         }
     }
 
-    pub fn global() -> &'static Mutex<FsModuleLoader> {
-        MODULE_LOADER.get_or_init(|| Mutex::new(FsModuleLoader::new()))
-    }
-
-    pub fn store_module(&mut self, path: String, module: v8::Global<v8::Module>, identity_hash: NonZero<i32>) {
+    pub fn store_module(
+        &mut self,
+        path: String,
+        module_type: ModuleType,
+        module: v8::Global<v8::Module>,
+        identity_hash: NonZero<i32>,
+    ) {
         self.paths.insert(identity_hash, path.clone());
-        self.modules.insert(path, module);
+        self.modules.insert((path, module_type), module);
     }
 
-    pub fn get_module(&self, path: &str) -> Option<&v8::Global<v8::Module>> {
-        self.modules.get(path)
+    pub fn get_module(&self, path: &str, module_type: ModuleType) -> Option<&v8::Global<v8::Module>> {
+        self.modules.get(&(path.to_string(), module_type))
     }
 
     pub fn get_path_by_hash(&self, hash: NonZero<i32>) -> Option<&String> {
         self.paths.get(&hash)
     }
+}
 
-    pub fn resolve_path(base: &str, specifier: &str) -> Option<String> {
-        // Simple resolution: if absolute, use it. If relative, join.
-        let path = if specifier.starts_with('/') {
-            std::path::PathBuf::from(specifier)
-        } else {
-            let base_path = Path::new(base);
-            let base_dir = if base_path.is_dir() {
-                base_path
-            } else {
-                base_path.parent().unwrap_or(Path::new("/"))
-            };
-            base_dir.join(specifier)
-        };
+/// The URL of a module for `import.meta`: remote modules already have one, local ones get
+/// a `file://` URL.
+pub fn module_url(path: &str) -> String {
+    if is_remote(path) { path.to_string() } else { file_url(path) }
+}
 
-        // For this toy, we just use the string path, assuming it exists or we'll fail to read later.
-        // Canonicalize ensures we don't have duplicates for "./a.js" and "a.js"
-        match path.canonicalize() {
-            Ok(p) => p.to_str().map(|s| s.to_string()),
-            Err(_) => {
-                // If file doesn't exist, canonicalize fails.
-                // For valid imports, it should exist.
-                None
-            }
+/// Converts an absolute path to a `file://` URL, percent-encoding the bytes that can't
+/// appear in a URL path as-is.
+pub fn file_url(path: &str) -> String {
+    let mut url = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => url.push(byte as char),
+            b'/' | b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b','
+            | b';' | b'=' | b':' | b'@' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
         }
     }
+    url
 }
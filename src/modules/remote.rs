@@ -0,0 +1,239 @@
+use super::ModuleSource;
+use super::code_cache::hash;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+/// Whether `specifier` is an `http:` or `https:` URL, imported over the network.
+pub fn is_remote(specifier: &str) -> bool {
+    specifier.starts_with("https://") || specifier.starts_with("http://")
+}
+
+/// Downloads remote modules and keeps them on disk, so later runs import them without
+/// the network.
+///
+/// Entries live under `<dir>/<scheme>/<host>[_<port>]/`, named after a hash of the
+/// requested URL: the source itself, plus a `.json` file recording the URL it was
+/// finally served from, which relative imports inside the module resolve against.
+#[derive(Default)]
+pub struct RemoteModules {
+    dir: Option<PathBuf>,
+    /// Download every module again instead of using cached copies.
+    reload: bool,
+}
+
+impl RemoteModules {
+    /// Without a `dir`, modules are downloaded on every run.
+    pub fn new(dir: Option<PathBuf>, reload: bool) -> Self {
+        Self { dir, reload }
+    }
+
+    /// Returns the module at `url`, from the cache if it has it and `reload` isn't set.
+    pub fn load(&self, url: &str) -> Result<ModuleSource, String> {
+        let url = Url::parse(url).map_err(|e| format!("Invalid module URL '{}': {}", url, e))?;
+        let entry = self.entry_path(&url);
+
+        if !self.reload
+            && let Some(entry) = &entry
+            && let Some(module) = read_entry(entry)
+        {
+            return Ok(module);
+        }
+
+        tracing::info!(%url, "downloading module");
+        let module = download(&url)?;
+        if let Some(entry) = &entry
+            && let Err(e) = write_entry(entry, &module)
+        {
+            tracing::warn!(%url, error = %e, "failed to cache remote module");
+        }
+        Ok(module)
+    }
+
+    fn entry_path(&self, url: &Url) -> Option<PathBuf> {
+        let host = match url.port() {
+            Some(port) => format!("{}_{}", url.host_str()?, port),
+            None => url.host_str()?.to_string(),
+        };
+        let dir = self.dir.as_ref()?.join(url.scheme()).join(host);
+        Some(dir.join(format!("{:016x}", hash(url.as_str().as_bytes()))))
+    }
+}
+
+fn read_entry(entry: &Path) -> Option<ModuleSource> {
+    let metadata: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(entry.with_extension("json")).ok()?).ok()?;
+    Some(ModuleSource {
+        name: metadata.get("url")?.as_str()?.to_string(),
+        code: std::fs::read_to_string(entry).ok()?,
+    })
+}
+
+/// Writes the source before the metadata, and each through a temporary file, so an entry
+/// only counts as cached once both are complete.
+fn write_entry(entry: &Path, module: &ModuleSource) -> std::io::Result<()> {
+    if let Some(dir) = entry.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let metadata = json!({ "url": module.name }).to_string();
+    for (path, contents) in [(entry.to_path_buf(), &module.code), (entry.with_extension("json"), &metadata)] {
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp_path, contents)?;
+        std::fs::rename(&tmp_path, &path)?;
+    }
+    Ok(())
+}
+
+/// How long a module download may take, from connecting to reading the last byte.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest module source accepted, so a misbehaving server can't exhaust memory.
+const MAX_MODULE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Module loading happens inside synchronous V8 callbacks, which can't drive the
+/// runtime's event loop. Downloads run instead on one background Tokio runtime shared by
+/// every runtime in the process, through one client, so connections are reused.
+struct Downloader {
+    runtime: tokio::runtime::Runtime,
+    client: reqwest::Client,
+}
+
+fn downloader() -> Result<&'static Downloader, String> {
+    static DOWNLOADER: OnceLock<Result<Downloader, String>> = OnceLock::new();
+    DOWNLOADER
+        .get_or_init(|| {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("toyjs-module-download")
+                .enable_all()
+                .build()
+                .map_err(|e| format!("Failed to start module downloads: {}", e))?;
+            let client = reqwest::Client::builder()
+                .timeout(DOWNLOAD_TIMEOUT)
+                .build()
+                .map_err(|e| format!("Failed to start module downloads: {}", e))?;
+            Ok(Downloader { runtime, client })
+        })
+        .as_ref()
+        .map_err(|e| e.clone())
+}
+
+/// Fetches `url` on the download runtime and blocks until the module has arrived.
+fn download(url: &Url) -> Result<ModuleSource, String> {
+    let downloader = downloader()?;
+    let (result_tx, result_rx) = std::sync::mpsc::channel();
+    let request = fetch_module(&downloader.client, url.clone());
+    downloader.runtime.spawn(async move {
+        let _ = result_tx.send(request.await);
+    });
+    result_rx
+        .recv()
+        .map_err(|_| format!("Failed to download '{}': the download was interrupted", url))?
+        .map_err(|e| format!("Failed to download '{}': {}", url, e))
+}
+
+async fn fetch_module(client: &reqwest::Client, url: Url) -> Result<ModuleSource, String> {
+    let mut response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(response.status().to_string());
+    }
+    let too_large = || format!("module is larger than {} bytes", MAX_MODULE_SIZE);
+    if response.content_length().is_some_and(|length| length > MAX_MODULE_SIZE as u64) {
+        return Err(too_large());
+    }
+
+    let name = response.url().to_string();
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > MAX_MODULE_SIZE {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let code = String::from_utf8_lossy(&body).into_owned();
+    Ok(ModuleSource { name, code })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::fixture;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers one connection with each response in turn on a local port, then stops
+    /// listening. Returns the server's base URL.
+    fn serve(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        base
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            status,
+            body.len(),
+            headers,
+            body
+        )
+    }
+
+    #[test]
+    fn downloads_once_and_loads_from_cache() {
+        let base = serve(vec![response("200 OK", "", "export const answer = 42;")]);
+        let dir = fixture("remote-cache", &[]);
+        let modules = RemoteModules::new(Some(dir.clone()), false);
+        let url = format!("{}/answer.js", base);
+
+        let module = modules.load(&url).unwrap();
+        assert_eq!(module.code, "export const answer = 42;");
+        assert_eq!(module.name, url);
+
+        // The server is gone after one response, so this must come from disk
+        let cached = modules.load(&url).unwrap();
+        assert_eq!(cached.code, module.code);
+        assert_eq!(cached.name, module.name);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn records_the_redirected_url() {
+        let base = serve(vec![
+            response("302 Found", "Location: /v2/lib.js\r\n", ""),
+            response("200 OK", "", "export default 1;"),
+        ]);
+
+        let module = RemoteModules::new(None, false).load(&format!("{}/lib.js", base)).unwrap();
+        assert_eq!(module.name, format!("{}/v2/lib.js", base));
+    }
+
+    #[test]
+    fn rejects_error_statuses() {
+        let base = serve(vec![response("404 Not Found", "", "missing")]);
+
+        let error = RemoteModules::new(None, false).load(&format!("{}/missing.js", base)).unwrap_err();
+        assert!(error.contains("404"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_oversized_modules() {
+        let length = MAX_MODULE_SIZE + 1;
+        let base = serve(vec![format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            length
+        )]);
+
+        let error = RemoteModules::new(None, false).load(&format!("{}/huge.js", base)).unwrap_err();
+        assert!(error.contains("larger than"), "{}", error);
+    }
+}
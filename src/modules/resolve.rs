@@ -0,0 +1,474 @@
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Extensions tried, in order, for specifiers that don't name a file exactly.
+const EXTENSIONS: &[&str] = &["js", "ts", "mts", "tsx"];
+
+/// TypeScript sources tried for a missing JavaScript file, since TypeScript code imports
+/// `./util.js` to mean the `./util.ts` it compiles to.
+const TYPESCRIPT_SOURCES: &[(&str, &[&str])] = &[("js", &["ts", "tsx"]), ("mjs", &["mts"])];
+
+/// Export conditions this runtime matches in `package.json` `exports`.
+const CONDITIONS: &[&str] = &["import", "default"];
+
+/// A specifier that didn't resolve to a module, with every path that was tried.
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    pub specifier: String,
+    pub referrer: String,
+    pub tried: Vec<PathBuf>,
+    /// Why resolution stopped early, e.g. a subpath a package doesn't export.
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot find module '{}' imported from '{}'", self.specifier, self.referrer)?;
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        if !self.tried.is_empty() {
+            write!(f, "\nTried:")?;
+            for path in &self.tried {
+                write!(f, "\n  {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Resolves `specifier` against `base`, the importing file or a directory:
+///
+/// * `/absolute`, `./relative` and `../relative` specifiers name a file or directory.
+/// * Anything else is a bare package specifier, looked up in the `node_modules`
+///   directories of `base` and its ancestors.
+///
+/// Files are tried as-is, then as their TypeScript source, then with each of
+/// `EXTENSIONS`; directories resolve to their `index.js` or `index.ts`. The result is
+/// canonicalized, so the same file always resolves to the same path.
+pub fn resolve(base: &str, specifier: &str) -> Result<String, ResolveError> {
+    let base_path = Path::new(base);
+    let base_dir = if base_path.is_dir() {
+        base_path
+    } else {
+        base_path.parent().unwrap_or(Path::new("/"))
+    };
+
+    let mut resolver = Resolver {
+        tried: Vec::new(),
+        reason: None,
+    };
+    let resolved = if specifier.starts_with('/') {
+        resolver.file_or_directory(Path::new(specifier))
+    } else if specifier.starts_with("./") || specifier.starts_with("../") || specifier == "." || specifier == ".." {
+        resolver.file_or_directory(&base_dir.join(specifier))
+    } else {
+        resolver.package(base_dir, specifier)
+    };
+
+    let error = |resolver: Resolver| ResolveError {
+        specifier: specifier.to_string(),
+        referrer: base.to_string(),
+        tried: resolver.tried,
+        reason: resolver.reason,
+    };
+    match resolved.map(|path| path.canonicalize()) {
+        Some(Ok(path)) => path.to_str().map(|s| s.to_string()).ok_or_else(|| error(resolver)),
+        _ => Err(error(resolver)),
+    }
+}
+
+struct Resolver {
+    tried: Vec<PathBuf>,
+    reason: Option<String>,
+}
+
+impl Resolver {
+    fn file(&mut self, path: &Path) -> Option<PathBuf> {
+        self.tried.push(path.to_path_buf());
+        path.is_file().then(|| path.to_path_buf())
+    }
+
+    fn file_or_directory(&mut self, path: &Path) -> Option<PathBuf> {
+        if let Some(file) = self.file(path) {
+            return Some(file);
+        }
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if let Some((_, sources)) = TYPESCRIPT_SOURCES.iter().find(|(js, _)| Some(*js) == extension) {
+            for source in *sources {
+                if let Some(file) = self.file(&path.with_extension(source)) {
+                    return Some(file);
+                }
+            }
+        }
+        for extension in EXTENSIONS {
+            let mut with_extension = path.as_os_str().to_owned();
+            with_extension.push(".");
+            with_extension.push(extension);
+            if let Some(file) = self.file(Path::new(&with_extension)) {
+                return Some(file);
+            }
+        }
+        if path.is_dir() {
+            return self.file(&path.join("index.js")).or_else(|| self.file(&path.join("index.ts")));
+        }
+        None
+    }
+
+    /// Looks for the package in `node_modules` directories from `dir` upwards. The
+    /// first directory that has the package decides the result.
+    fn package(&mut self, dir: &Path, specifier: &str) -> Option<PathBuf> {
+        let (name, subpath) = split_package_specifier(specifier)?;
+
+        for ancestor in dir.ancestors() {
+            let package_dir = ancestor.join("node_modules").join(name);
+            if !package_dir.is_dir() {
+                self.tried.push(package_dir);
+                continue;
+            }
+            return self.package_entry(&package_dir, &subpath);
+        }
+        None
+    }
+
+    /// Resolves `subpath` (`.` or `./...`) inside a package directory.
+    fn package_entry(&mut self, package_dir: &Path, subpath: &str) -> Option<PathBuf> {
+        let manifest_path = package_dir.join("package.json");
+        let manifest: Option<Manifest> = std::fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+
+        if let Some(exports) = manifest.as_ref().and_then(|manifest| manifest.exports.as_ref()) {
+            let Some(target) = exports_target(exports, subpath) else {
+                self.reason = Some(format!(
+                    "'{}' is not exported by {}",
+                    subpath,
+                    manifest_path.display()
+                ));
+                return None;
+            };
+            return self.file(&package_dir.join(target));
+        }
+
+        if subpath != "." {
+            return self.file_or_directory(&package_dir.join(subpath));
+        }
+
+        let entries = manifest.iter().flat_map(|manifest| [&manifest.module, &manifest.main]);
+        for entry in entries.flatten() {
+            if let Some(path) = self.file_or_directory(&package_dir.join(entry)) {
+                return Some(path);
+            }
+        }
+        self.file(&package_dir.join("index.js"))
+    }
+}
+
+/// Splits `name/sub/path` or `@scope/name/sub/path` into the package name and a
+/// `./sub/path` subpath (`.` for the package itself).
+fn split_package_specifier(specifier: &str) -> Option<(&str, String)> {
+    let name_len = if specifier.starts_with('@') {
+        let scope_end = specifier.find('/')?;
+        specifier[scope_end + 1..]
+            .find('/')
+            .map_or(specifier.len(), |i| scope_end + 1 + i)
+    } else {
+        specifier.find('/').unwrap_or(specifier.len())
+    };
+
+    let (name, rest) = specifier.split_at(name_len);
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, format!(".{}", rest)))
+}
+
+/// The fields of a `package.json` the resolver reads.
+#[derive(Default)]
+struct Manifest {
+    exports: Option<Exports>,
+    module: Option<String>,
+    main: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for Manifest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ManifestVisitor;
+
+        impl<'de> Visitor<'de> for ManifestVisitor {
+            type Value = Manifest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a package.json object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Manifest, A::Error> {
+                let mut manifest = Manifest::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "exports" => manifest.exports = Some(map.next_value()?),
+                        // Entry points that aren't strings are ignored rather than failing the manifest
+                        "module" => manifest.module = map.next_value::<Value>()?.as_str().map(str::to_string),
+                        "main" => manifest.main = map.next_value::<Value>()?.as_str().map(str::to_string),
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(manifest)
+            }
+        }
+
+        deserializer.deserialize_map(ManifestVisitor)
+    }
+}
+
+/// A `package.json` `exports` value. Unlike `serde_json::Value`, objects keep their keys
+/// in document order, since the first matching condition wins.
+enum Exports {
+    Path(String),
+    /// Fallbacks, the first one that resolves is used.
+    Array(Vec<Exports>),
+    /// Subpaths or conditions, in document order.
+    Object(Vec<(String, Exports)>),
+    /// `null` or anything else that can't be a target; it excludes the subpath.
+    None,
+}
+
+impl Exports {
+    fn get(&self, key: &str) -> Option<&Exports> {
+        match self {
+            Exports::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Exports {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ExportsVisitor;
+
+        impl<'de> Visitor<'de> for ExportsVisitor {
+            type Value = Exports;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a package.json exports value")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> Result<Exports, E> {
+                Ok(Exports::Path(path.to_string()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Exports, A::Error> {
+                let mut targets = Vec::new();
+                while let Some(target) = seq.next_element()? {
+                    targets.push(target);
+                }
+                Ok(Exports::Array(targets))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Exports, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Exports::Object(entries))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Exports, E> {
+                Ok(Exports::None)
+            }
+
+            fn visit_bool<E: de::Error>(self, _: bool) -> Result<Exports, E> {
+                Ok(Exports::None)
+            }
+
+            fn visit_i64<E: de::Error>(self, _: i64) -> Result<Exports, E> {
+                Ok(Exports::None)
+            }
+
+            fn visit_u64<E: de::Error>(self, _: u64) -> Result<Exports, E> {
+                Ok(Exports::None)
+            }
+
+            fn visit_f64<E: de::Error>(self, _: f64) -> Result<Exports, E> {
+                Ok(Exports::None)
+            }
+        }
+
+        deserializer.deserialize_any(ExportsVisitor)
+    }
+}
+
+/// Finds the target for `subpath` in a `package.json` `exports` field.
+fn exports_target(exports: &Exports, subpath: &str) -> Option<String> {
+    let subpaths = match exports {
+        Exports::Object(entries) if entries.iter().any(|(key, _)| key.starts_with('.')) => entries,
+        // A string, array or condition object is shorthand for the "." export
+        _ => return if subpath == "." { condition_target(exports) } else { None },
+    };
+
+    if let Some(target) = exports.get(subpath) {
+        return condition_target(target);
+    }
+
+    // Subpath patterns such as "./features/*": "./src/features/*.js". The pattern with
+    // the longest prefix wins.
+    subpaths
+        .iter()
+        .filter_map(|(key, target)| {
+            let (prefix, suffix) = key.split_once('*')?;
+            let matched = subpath.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some((prefix.len(), matched, target))
+        })
+        .max_by_key(|(prefix_len, _, _)| *prefix_len)
+        .and_then(|(_, matched, target)| {
+            condition_target(target).map(|target| target.replace('*', matched))
+        })
+}
+
+/// Picks the target of an export. Condition objects are walked in the package's order
+/// and the first key in `CONDITIONS` with a usable target wins, as in Node.
+fn condition_target(target: &Exports) -> Option<String> {
+    match target {
+        Exports::Path(path) => Some(path.clone()),
+        Exports::Array(targets) => targets.iter().find_map(condition_target),
+        Exports::Object(conditions) => conditions
+            .iter()
+            .filter(|(condition, _)| CONDITIONS.contains(&condition.as_str()))
+            .find_map(|(_, target)| condition_target(target)),
+        Exports::None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::fixture;
+
+    fn resolve_from(dir: &Path, specifier: &str) -> Result<String, ResolveError> {
+        resolve(dir.join("main.js").to_str().unwrap(), specifier)
+    }
+
+    fn path(dir: &Path, file: &str) -> String {
+        dir.join(file).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn conditions_match_in_package_order() {
+        let dir = fixture(
+            "resolve-conditions",
+            &[
+                ("node_modules/first/package.json", r#"{"exports": {"default": "./default.js", "import": "./import.js"}}"#),
+                ("node_modules/first/default.js", ""),
+                ("node_modules/first/import.js", ""),
+                ("node_modules/second/package.json", r#"{"exports": {"require": "./cjs.js", "import": "./esm.js", "default": "./default.js"}}"#),
+                ("node_modules/second/esm.js", ""),
+                ("node_modules/second/default.js", ""),
+            ],
+        );
+
+        assert_eq!(resolve_from(&dir, "first").unwrap(), path(&dir, "node_modules/first/default.js"));
+        assert_eq!(resolve_from(&dir, "second").unwrap(), path(&dir, "node_modules/second/esm.js"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn nested_conditions_and_fallbacks() {
+        let dir = fixture(
+            "resolve-nested",
+            &[
+                (
+                    "node_modules/pkg/package.json",
+                    r#"{"exports": {
+                        ".": {"node": {"import": "./node.js"}, "import": [{"worker": "./worker.js"}, "./esm.js"]},
+                        "./feature": {"require": "./feature.cjs"},
+                        "./hidden": null
+                    }}"#,
+                ),
+                ("node_modules/pkg/esm.js", ""),
+                ("node_modules/pkg/hidden.js", ""),
+            ],
+        );
+
+        assert_eq!(resolve_from(&dir, "pkg").unwrap(), path(&dir, "node_modules/pkg/esm.js"));
+        // No condition this runtime matches
+        assert!(resolve_from(&dir, "pkg/feature").is_err());
+        let error = resolve_from(&dir, "pkg/hidden").unwrap_err();
+        assert_eq!(error.reason.as_deref(), Some(&*format!("'./hidden' is not exported by {}", path(&dir, "node_modules/pkg/package.json"))));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn subpath_patterns_prefer_the_longest_prefix() {
+        let dir = fixture(
+            "resolve-patterns",
+            &[
+                (
+                    "node_modules/@scope/pkg/package.json",
+                    r#"{"exports": {"./*": "./src/*.js", "./features/*": {"import": "./features/*/index.js"}}}"#,
+                ),
+                ("node_modules/@scope/pkg/src/util.js", ""),
+                ("node_modules/@scope/pkg/features/auth/index.js", ""),
+            ],
+        );
+
+        assert_eq!(resolve_from(&dir, "@scope/pkg/util").unwrap(), path(&dir, "node_modules/@scope/pkg/src/util.js"));
+        assert_eq!(
+            resolve_from(&dir, "@scope/pkg/features/auth").unwrap(),
+            path(&dir, "node_modules/@scope/pkg/features/auth/index.js")
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn packages_without_exports_use_module_then_main() {
+        let dir = fixture(
+            "resolve-main",
+            &[
+                ("node_modules/both/package.json", r#"{"main": "./main.js", "module": "./module.js"}"#),
+                ("node_modules/both/main.js", ""),
+                ("node_modules/both/module.js", ""),
+                ("node_modules/main-only/package.json", r#"{"main": "lib/index", "module": 3}"#),
+                ("node_modules/main-only/lib/index.js", ""),
+                ("node_modules/bare/index.js", ""),
+                ("node_modules/bare/lib/extra.js", ""),
+            ],
+        );
+
+        assert_eq!(resolve_from(&dir, "both").unwrap(), path(&dir, "node_modules/both/module.js"));
+        assert_eq!(resolve_from(&dir, "main-only").unwrap(), path(&dir, "node_modules/main-only/lib/index.js"));
+        assert_eq!(resolve_from(&dir, "bare").unwrap(), path(&dir, "node_modules/bare/index.js"));
+        assert_eq!(resolve_from(&dir, "bare/lib/extra").unwrap(), path(&dir, "node_modules/bare/lib/extra.js"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn relative_specifiers_try_typescript_sources_and_extensions() {
+        let dir = fixture(
+            "resolve-relative",
+            &[("util.ts", ""), ("lib.mts", ""), ("dir/index.ts", ""), ("App.tsx", "")],
+        );
+
+        assert_eq!(resolve_from(&dir, "./util.js").unwrap(), path(&dir, "util.ts"));
+        assert_eq!(resolve_from(&dir, "./util").unwrap(), path(&dir, "util.ts"));
+        assert_eq!(resolve_from(&dir, "./lib.mjs").unwrap(), path(&dir, "lib.mts"));
+        assert_eq!(resolve_from(&dir, "./dir").unwrap(), path(&dir, "dir/index.ts"));
+        assert_eq!(resolve_from(&dir, "./App.js").unwrap(), path(&dir, "App.tsx"));
+        assert_eq!(resolve_from(&dir, "./App").unwrap(), path(&dir, "App.tsx"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn split_package_specifiers() {
+        assert_eq!(split_package_specifier("pkg"), Some(("pkg", ".".to_string())));
+        assert_eq!(split_package_specifier("pkg/a/b"), Some(("pkg", "./a/b".to_string())));
+        assert_eq!(split_package_specifier("@scope/pkg"), Some(("@scope/pkg", ".".to_string())));
+        assert_eq!(split_package_specifier("@scope/pkg/a"), Some(("@scope/pkg", "./a".to_string())));
+        assert_eq!(split_package_specifier(""), None);
+    }
+}
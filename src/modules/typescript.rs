@@ -0,0 +1,1933 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use super::jsx::{self, Pragma};
+
+/// Whether the module at `path` is TypeScript and needs its types stripped.
+pub fn is_typescript(path: &str) -> bool {
+    matches!(super::extension(path).as_deref(), Some("ts" | "mts" | "tsx"))
+}
+
+/// The JavaScript source of the module at `path`: `code` with its types stripped if
+/// it is TypeScript, unchanged otherwise. JSX in `.tsx` modules is compiled too.
+pub fn strip_if_typescript<'a>(path: &str, code: &'a str) -> Result<Cow<'a, str>, TypeStripError> {
+    if !is_typescript(path) {
+        return Ok(Cow::Borrowed(code));
+    }
+    let tsx = super::extension(path).as_deref() == Some("tsx");
+    strip_types(code, tsx).map(Cow::Owned)
+}
+
+/// TypeScript syntax that can't be run by stripping types, such as namespaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeStripError {
+    pub message: String,
+    /// 1-based line of the offending syntax.
+    pub line: usize,
+    /// 1-based column of the offending syntax.
+    pub column: usize,
+}
+
+impl fmt::Display for TypeStripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}:{})", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for TypeStripError {}
+
+/// Turns TypeScript into JavaScript by replacing type annotations, type declarations
+/// and other TypeScript-only syntax with whitespace.
+///
+/// Line breaks are kept, so every statement stays on its original line and stack
+/// traces point at the TypeScript source without a source map. Only enums and
+/// constructor parameter properties generate code, on the lines they were declared.
+///
+/// Like `tsc --verbatimModuleSyntax`, imports of types must be marked with `type`, since
+/// stripping can't tell which imported names are only types. Namespaces,
+/// `import x = require()` and `export =` are not supported.
+///
+/// With `tsx`, `<` is read the way TSX does and JSX is compiled to `React.createElement`
+/// calls, or to the factory named by an `@jsx` comment at the top of the file. The
+/// compiled elements keep their line breaks, like stripped types.
+pub fn strip_types(source: &str, tsx: bool) -> Result<String, TypeStripError> {
+    strip(source, tsx, &Pragma::from_source(source))
+}
+
+fn strip(source: &str, tsx: bool, pragma: &Pragma) -> Result<String, TypeStripError> {
+    let tokens = tokenize(source, tsx)?;
+    let matching = match_brackets(source, &tokens)?;
+    let mut stripper = Stripper {
+        src: source,
+        tokens,
+        matching,
+        edits: Vec::new(),
+        tsx,
+        pragma,
+    };
+    let end = stripper.tokens.len() - 1;
+    stripper.walk(0, end, Ctx::Block, &|_, _, _| false)?;
+    Ok(apply_edits(source, stripper.edits))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Ident,
+    PrivateName,
+    Number,
+    String,
+    Regex,
+    /// A template literal without substitutions.
+    Template,
+    TemplateHead,
+    TemplateMiddle,
+    TemplateTail,
+    Punct,
+    /// A JSX element or fragment, compiled as a whole.
+    Jsx,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    end: usize,
+    newline_before: bool,
+}
+
+/// Punctuators, longest first. `>` is always a token of its own so nested type
+/// arguments like `Array<Array<T>>` close one at a time.
+const PUNCTUATORS: &[&str] = &[
+    "...", "===", "!==", "**=", "<<=", "&&=", "||=", "??=", "=>", "==", "!=", "**", "*=",
+    "+=", "-=", "/=", "%=", "<<", "<=", "&&", "&=", "||", "|=", "^=", "??", "?.", "++", "--", "{",
+    "}", "(", ")", "[", "]", ";", ",", "<", ">", "=", "!", "+", "-", "*", "/", "%", "&", "|", "^",
+    "~", "?", ":", ".", "@",
+];
+
+/// Keywords after which an expression can start, so they never end one.
+const EXPRESSION_KEYWORDS: &[&str] = &[
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+    "await",
+    "if",
+    "while",
+    "for",
+    "switch",
+    "catch",
+    "with",
+    "extends",
+    "export",
+    "import",
+    "default",
+    "var",
+    "let",
+    "const",
+    "function",
+    "class",
+];
+
+/// In TSX, `<T>value` would read as JSX, so type assertions need `as`.
+const TSX_ASSERTION: &str = "'<T>' type assertions are not allowed in TSX, use 'value as T' instead";
+
+/// Class member modifiers that only exist in TypeScript.
+const TS_MODIFIERS: &[&str] = &["public", "private", "protected", "readonly", "override"];
+
+const MODIFIERS: &[&str] = &[
+    "public",
+    "private",
+    "protected",
+    "readonly",
+    "override",
+    "abstract",
+    "declare",
+    "static",
+    "accessor",
+    "async",
+    "get",
+    "set",
+];
+
+fn tokenize(src: &str, tsx: bool) -> Result<Vec<Token>, TypeStripError> {
+    let start = if src.starts_with("#!") {
+        src.find('\n').unwrap_or(src.len())
+    } else {
+        0
+    };
+    scan(src, start, tsx, false).map(|(tokens, _)| tokens)
+}
+
+/// Where the expression in a JSX `{...}` whose contents start at `start` ends: the
+/// offset of its closing `}`.
+pub(super) fn expression_end(src: &str, start: usize) -> Result<usize, TypeStripError> {
+    scan(src, start, true, true).map(|(_, end)| end)
+}
+
+/// Strips the types from the expression `src[start..end]` inside JSX, compiling the
+/// JSX nested in it. Errors point into `src`.
+pub(super) fn strip_expression(
+    src: &str,
+    start: usize,
+    end: usize,
+    pragma: &Pragma,
+) -> Result<String, TypeStripError> {
+    // Parenthesized, so an object literal isn't read as a block
+    let code = format!("({})", &src[start..end]);
+    match strip(&code, true, pragma) {
+        Ok(stripped) => Ok(stripped[1..stripped.len() - 1].to_string()),
+        Err(mut error) => {
+            let outer = error_at(src, start, "");
+            if error.line == 1 {
+                error.column = outer.column + error.column - 2;
+            }
+            error.line += outer.line - 1;
+            Err(error)
+        }
+    }
+}
+
+/// Tokenizes `src` from `start`. When `nested`, stops at the `}` closing a JSX
+/// expression and returns its offset; otherwise runs to the end and adds an `Eof`.
+fn scan(
+    src: &str,
+    start: usize,
+    tsx: bool,
+    nested: bool,
+) -> Result<(Vec<Token>, usize), TypeStripError> {
+    let bytes = src.as_bytes();
+    let mut tokens: Vec<Token> = Vec::new();
+    // One entry per open `{`: whether it opened a template substitution
+    let mut braces: Vec<bool> = Vec::new();
+    let mut i = start;
+    let mut newline_before = false;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        // Whitespace and comments
+        if c == b'\n' || c == b'\r' {
+            newline_before = true;
+            i += 1;
+            continue;
+        }
+        if c == b' ' || c == b'\t' || c == 0x0b || c == 0x0c {
+            i += 1;
+            continue;
+        }
+        if let Some(ch) = src[i..].chars().next()
+            && !ch.is_ascii()
+            && (ch.is_whitespace() || ch == '\u{feff}')
+        {
+            newline_before |= ch == '\u{2028}' || ch == '\u{2029}';
+            i += ch.len_utf8();
+            continue;
+        }
+        if src[i..].starts_with("//") {
+            i = src[i..].find('\n').map_or(src.len(), |end| i + end);
+            continue;
+        }
+        if src[i..].starts_with("/*") {
+            let end = src[i + 2..]
+                .find("*/")
+                .ok_or_else(|| error_at(src, i, "Unterminated comment"))?;
+            newline_before |= src[i..i + 2 + end].contains('\n');
+            i += end + 4;
+            continue;
+        }
+
+        if nested && c == b'}' && braces.is_empty() {
+            return Ok((tokens, i));
+        }
+
+        let kind = if c == b'"' || c == b'\'' {
+            i = skip_string(src, i)?;
+            Kind::String
+        } else if c == b'`' {
+            let (end, kind) = skip_template(src, i + 1, Kind::Template, Kind::TemplateHead)?;
+            if kind == Kind::TemplateHead {
+                braces.push(true);
+            }
+            i = end;
+            kind
+        } else if c == b'}' && braces.last() == Some(&true) {
+            braces.pop();
+            let (end, kind) = skip_template(src, i + 1, Kind::TemplateTail, Kind::TemplateMiddle)?;
+            if kind == Kind::TemplateMiddle {
+                braces.push(true);
+            }
+            i = end;
+            kind
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            i += 1;
+            while i < bytes.len() {
+                let b = bytes[i];
+                let exponent_sign = (b == b'+' || b == b'-')
+                    && matches!(bytes[i - 1], b'e' | b'E')
+                    && !src[start..].starts_with("0x");
+                if !(b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || exponent_sign) {
+                    break;
+                }
+                i += 1;
+            }
+            Kind::Number
+        } else if is_ident_start(src, i) || (c == b'#' && is_ident_start(src, i + 1)) {
+            i += if c == b'#' { 1 } else { 0 };
+            while i < bytes.len() && is_ident_part(src, i) {
+                i += src[i..].chars().next().map_or(1, char::len_utf8);
+            }
+            if c == b'#' {
+                Kind::PrivateName
+            } else {
+                Kind::Ident
+            }
+        } else if tsx && c == b'<' && regex_allowed(src, tokens.last()) && is_jsx(src, i) {
+            i = jsx::element_end(src, i)?;
+            Kind::Jsx
+        } else if c == b'/' && regex_allowed(src, tokens.last()) {
+            i = skip_regex(src, i)?;
+            Kind::Regex
+        } else {
+            let punct = PUNCTUATORS
+                .iter()
+                .find(|p| src[i..].starts_with(**p))
+                .ok_or_else(|| error_at(src, i, "Unexpected character"))?;
+            // `a?.5:b` is a conditional, not optional chaining
+            let punct = if *punct == "?." && bytes.get(i + 2).is_some_and(u8::is_ascii_digit) {
+                "?"
+            } else {
+                punct
+            };
+            match punct {
+                "{" => braces.push(false),
+                "}" => {
+                    braces.pop();
+                }
+                _ => {}
+            }
+            i += punct.len();
+            Kind::Punct
+        };
+
+        tokens.push(Token {
+            kind,
+            start,
+            end: i,
+            newline_before,
+        });
+        newline_before = false;
+    }
+
+    if nested {
+        return Err(error_at(src, start - 1, "Unterminated JSX expression"));
+    }
+    tokens.push(Token {
+        kind: Kind::Eof,
+        start: src.len(),
+        end: src.len(),
+        newline_before,
+    });
+    Ok((tokens, src.len()))
+}
+
+/// Whether a `<` at the start of an expression opens a JSX element rather than the
+/// type parameters of a generic arrow function, which need a `,` or `extends` in TSX.
+fn is_jsx(src: &str, i: usize) -> bool {
+    let rest = src[i + 1..].trim_start();
+    if rest.starts_with('>') {
+        return true;
+    }
+    if !is_ident_start(rest, 0) {
+        return false;
+    }
+    let name_len = rest
+        .find(|c: char| {
+            !(c == '$' || c == '_' || c == '-' || c == '.' || c == ':' || c.is_alphanumeric())
+        })
+        .unwrap_or(rest.len());
+    let after = rest[name_len..].trim_start();
+    !(after.starts_with(',') || after.starts_with("extends") || after.starts_with('='))
+}
+
+fn is_ident_start(src: &str, i: usize) -> bool {
+    src[i..]
+        .chars()
+        .next()
+        .is_some_and(|c| c == '$' || c == '_' || c == '\\' || c.is_alphabetic())
+}
+
+fn is_ident_part(src: &str, i: usize) -> bool {
+    src[i..].chars().next().is_some_and(|c| {
+        c == '$'
+            || c == '_'
+            || c == '\\'
+            || c == '\u{200c}'
+            || c == '\u{200d}'
+            || c.is_alphanumeric()
+    })
+}
+
+/// Whether a `/` after `prev` starts a regular expression rather than a division.
+fn regex_allowed(src: &str, prev: Option<&Token>) -> bool {
+    let Some(prev) = prev else {
+        return true;
+    };
+    let text = &src[prev.start..prev.end];
+    match prev.kind {
+        Kind::Ident => EXPRESSION_KEYWORDS.contains(&text),
+        Kind::Punct => !matches!(text, ")" | "]" | "}"),
+        Kind::TemplateHead | Kind::TemplateMiddle => true,
+        _ => false,
+    }
+}
+
+fn skip_string(src: &str, start: usize) -> Result<usize, TypeStripError> {
+    let bytes = src.as_bytes();
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => break,
+            b if b == quote => return Ok(i + 1),
+            _ => i += 1,
+        }
+    }
+    Err(error_at(src, start, "Unterminated string literal"))
+}
+
+/// Scans template characters from `i` up to the closing backtick (`end_kind`) or the
+/// next `${` (`open_kind`).
+fn skip_template(
+    src: &str,
+    mut i: usize,
+    end_kind: Kind,
+    open_kind: Kind,
+) -> Result<(usize, Kind), TypeStripError> {
+    let bytes = src.as_bytes();
+    let start = i;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => return Ok((i + 1, end_kind)),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => return Ok((i + 2, open_kind)),
+            _ => i += 1,
+        }
+    }
+    Err(error_at(src, start, "Unterminated template literal"))
+}
+
+fn skip_regex(src: &str, start: usize) -> Result<usize, TypeStripError> {
+    let bytes = src.as_bytes();
+    let mut i = start + 1;
+    let mut in_class = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'[' => {
+                in_class = true;
+                i += 1;
+            }
+            b']' => {
+                in_class = false;
+                i += 1;
+            }
+            b'/' if !in_class => {
+                i += 1;
+                while i < bytes.len() && is_ident_part(src, i) {
+                    i += 1;
+                }
+                return Ok(i);
+            }
+            b'\n' => break,
+            _ => i += 1,
+        }
+    }
+    Err(error_at(src, start, "Unterminated regular expression"))
+}
+
+/// For every bracket and template part, the index of the token that closes it. Template
+/// heads and middles point at the next middle or tail.
+fn match_brackets(src: &str, tokens: &[Token]) -> Result<Vec<usize>, TypeStripError> {
+    let mut matching = vec![usize::MAX; tokens.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let text = &src[token.start..token.end];
+        let closes = match token.kind {
+            Kind::Punct => matches!(text, ")" | "]" | "}"),
+            Kind::TemplateMiddle | Kind::TemplateTail => true,
+            _ => false,
+        };
+        if closes {
+            let open = stack
+                .pop()
+                .ok_or_else(|| error_at(src, token.start, &format!("Unexpected '{}'", text)))?;
+            matching[open] = i;
+            matching[i] = open;
+        }
+        let opens = match token.kind {
+            Kind::Punct => matches!(text, "(" | "[" | "{"),
+            Kind::TemplateHead | Kind::TemplateMiddle => true,
+            _ => false,
+        };
+        if opens {
+            stack.push(i);
+        }
+    }
+    match stack.pop() {
+        Some(open) => Err(error_at(src, tokens[open].start, "Unclosed bracket")),
+        None => Ok(matching),
+    }
+}
+
+pub(super) fn error_at(src: &str, offset: usize, message: &str) -> TypeStripError {
+    let before = &src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    TypeStripError {
+        message: message.to_string(),
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    /// Replacement text, or `None` to blank the range out.
+    text: Option<String>,
+}
+
+fn apply_edits(src: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|edit| (edit.start, edit.end));
+    let mut out = String::with_capacity(src.len());
+    let mut cursor = 0;
+    for edit in edits {
+        // Already covered by an enclosing edit
+        if edit.start < cursor {
+            continue;
+        }
+        out.push_str(&src[cursor..edit.start]);
+        match edit.text {
+            Some(text) => out.push_str(&text),
+            None => out.extend(src[edit.start..edit.end].chars().map(|c| match c {
+                '\n' | '\r' | '\u{2028}' | '\u{2029}' => c,
+                _ => ' ',
+            })),
+        }
+        cursor = edit.end;
+    }
+    out.push_str(&src[cursor..]);
+    out
+}
+
+/// What kind of code a bracketed range holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ctx {
+    /// Statements: the top level, function bodies and blocks.
+    Block,
+    /// An object literal or destructuring pattern.
+    Object,
+    /// Parentheses, array literals and template substitutions.
+    Expr,
+}
+
+type Stop<'s> = dyn Fn(&Stripper, usize, Option<usize>) -> bool + 's;
+
+struct Stripper<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    matching: Vec<usize>,
+    edits: Vec<Edit>,
+    tsx: bool,
+    pragma: &'a Pragma,
+}
+
+impl Stripper<'_> {
+    fn tok(&self, i: usize) -> &Token {
+        &self.tokens[i.min(self.tokens.len() - 1)]
+    }
+
+    fn text(&self, i: usize) -> &str {
+        let token = self.tok(i);
+        &self.src[token.start..token.end]
+    }
+
+    /// Whether token `i` is the punctuator or keyword `text`.
+    fn is(&self, i: usize, text: &str) -> bool {
+        matches!(self.tok(i).kind, Kind::Punct | Kind::Ident) && self.text(i) == text
+    }
+
+    fn is_ident(&self, i: usize) -> bool {
+        self.tok(i).kind == Kind::Ident
+    }
+
+    fn same_line(&self, i: usize) -> bool {
+        !self.tok(i).newline_before
+    }
+
+    fn matching(&self, i: usize) -> usize {
+        self.matching[i.min(self.tokens.len() - 1)]
+    }
+
+    fn is_opener(&self, i: usize) -> bool {
+        self.tok(i).kind == Kind::TemplateHead
+            || self.is(i, "(")
+            || self.is(i, "[")
+            || self.is(i, "{")
+    }
+
+    /// The index after a bracketed range or template starting at `i`.
+    fn skip_brackets(&self, i: usize) -> usize {
+        let mut j = self.matching(i);
+        while self.tok(j).kind == Kind::TemplateMiddle {
+            j = self.matching(j);
+        }
+        j + 1
+    }
+
+    fn error(&self, i: usize, message: &str) -> TypeStripError {
+        error_at(self.src, self.tok(i).start, message)
+    }
+
+    /// Blanks tokens `i..j` and everything between them.
+    fn blank(&mut self, i: usize, j: usize) {
+        if i < j {
+            self.edits.push(Edit {
+                start: self.tok(i).start,
+                end: self.tok(j - 1).end,
+                text: None,
+            });
+        }
+    }
+
+    fn insert(&mut self, offset: usize, text: String) {
+        self.edits.push(Edit {
+            start: offset,
+            end: offset,
+            text: Some(text),
+        });
+    }
+
+    /// Whether token `i` can be the last token of an expression.
+    fn ends_expr(&self, i: usize) -> bool {
+        match self.tok(i).kind {
+            Kind::Ident => !EXPRESSION_KEYWORDS.contains(&self.text(i)),
+            Kind::Number
+            | Kind::String
+            | Kind::Regex
+            | Kind::Template
+            | Kind::TemplateTail
+            | Kind::PrivateName
+            | Kind::Jsx => true,
+            Kind::Punct => matches!(self.text(i), ")" | "]" | "}"),
+            _ => false,
+        }
+    }
+
+    /// Whether a line break before token `i` ends the statement after `prev`.
+    fn asi_before(&self, i: usize, prev: Option<usize>) -> bool {
+        if self.same_line(i) || !prev.is_some_and(|prev| self.ends_expr(prev)) {
+            return false;
+        }
+        match self.tok(i).kind {
+            Kind::Ident => !matches!(self.text(i), "in" | "instanceof" | "as" | "satisfies"),
+            Kind::Number
+            | Kind::String
+            | Kind::Template
+            | Kind::TemplateHead
+            | Kind::PrivateName
+            | Kind::Jsx => true,
+            Kind::Punct => matches!(self.text(i), "++" | "--" | "@"),
+            _ => false,
+        }
+    }
+
+    /// Extends a declaration at `i` back over `export`, `default`, `declare` and `async`.
+    fn declaration_start(&self, mut i: usize) -> usize {
+        for keyword in ["async", "declare", "default", "export"] {
+            if i > 0 && self.is(i - 1, keyword) {
+                i -= 1;
+            }
+        }
+        i
+    }
+
+    /// Walks tokens `i..end`, stripping TypeScript syntax. Returns at `end` or at the
+    /// first token at this level that `stop` accepts.
+    fn walk(
+        &mut self,
+        mut i: usize,
+        end: usize,
+        ctx: Ctx,
+        stop: &Stop,
+    ) -> Result<usize, TypeStripError> {
+        let mut prev: Option<usize> = None;
+        while i < end {
+            if stop(self, i, prev) {
+                return Ok(i);
+            }
+            let next = self.token(i, end, ctx, prev)?;
+            prev = Some(next - 1);
+            i = next;
+        }
+        Ok(end)
+    }
+
+    /// Handles the construct starting at token `i` and returns the index after it.
+    fn token(
+        &mut self,
+        i: usize,
+        end: usize,
+        ctx: Ctx,
+        prev: Option<usize>,
+    ) -> Result<usize, TypeStripError> {
+        let after_dot = prev.is_some_and(|prev| self.is(prev, ".") || self.is(prev, "?."));
+        match self.tok(i).kind {
+            Kind::TemplateHead => {
+                let mut j = i;
+                while matches!(self.tok(j).kind, Kind::TemplateHead | Kind::TemplateMiddle) {
+                    let close = self.matching(j);
+                    self.walk(j + 1, close, Ctx::Expr, &|_, _, _| false)?;
+                    j = close;
+                }
+                Ok(j + 1)
+            }
+            Kind::Jsx => {
+                let token = *self.tok(i);
+                let text = jsx::compile(self.src, token.start, self.pragma)?;
+                self.edits.push(Edit {
+                    start: token.start,
+                    end: token.end,
+                    text: Some(text),
+                });
+                Ok(i + 1)
+            }
+            Kind::Punct => match self.text(i) {
+                "(" => self.paren(i, ctx, prev),
+                "[" => {
+                    let close = self.matching(i);
+                    self.walk(i + 1, close, Ctx::Expr, &|_, _, _| false)?;
+                    Ok(close + 1)
+                }
+                "{" => {
+                    let inner = if self.is_block(ctx, prev) {
+                        Ctx::Block
+                    } else {
+                        Ctx::Object
+                    };
+                    let close = self.matching(i);
+                    self.walk(i + 1, close, inner, &|_, _, _| false)?;
+                    Ok(close + 1)
+                }
+                "<" => self.angle(i, ctx, prev),
+                "!" if prev.is_some_and(|prev| self.ends_expr(prev)) && self.same_line(i) => {
+                    // Non-null assertion
+                    self.blank(i, i + 1);
+                    Ok(i + 1)
+                }
+                _ => Ok(i + 1),
+            },
+            Kind::Ident if !after_dot => self.keyword(i, end, ctx, prev),
+            _ => Ok(i + 1),
+        }
+    }
+
+    fn is_block(&self, ctx: Ctx, prev: Option<usize>) -> bool {
+        let Some(prev) = prev else {
+            return ctx == Ctx::Block;
+        };
+        match self.tok(prev).kind {
+            Kind::Punct => match self.text(prev) {
+                ")" | "=>" | ";" | "{" | "}" => true,
+                ":" => ctx == Ctx::Block,
+                _ => false,
+            },
+            Kind::Ident => !matches!(
+                self.text(prev),
+                "return"
+                    | "yield"
+                    | "await"
+                    | "typeof"
+                    | "case"
+                    | "in"
+                    | "of"
+                    | "new"
+                    | "void"
+                    | "delete"
+                    | "throw"
+            ),
+            _ => false,
+        }
+    }
+
+    fn keyword(
+        &mut self,
+        i: usize,
+        end: usize,
+        ctx: Ctx,
+        prev: Option<usize>,
+    ) -> Result<usize, TypeStripError> {
+        let next_is_name = self.is_ident(i + 1) && self.same_line(i + 1);
+        let in_block = ctx == Ctx::Block;
+        match self.text(i) {
+            "function" => self.function(i),
+            "class" => self.class(i),
+            "let" | "var" if self.is_ident(i + 1) || self.is(i + 1, "{") || self.is(i + 1, "[") => {
+                self.declaration(i, end)
+            }
+            "const" if self.is(i + 1, "enum") => self.enumeration(i),
+            "const" => self.declaration(i, end),
+            "enum" if next_is_name && self.is(i + 2, "{") => self.enumeration(i),
+            "interface" if in_block && next_is_name => {
+                let end = self.skip_interface(i)?;
+                self.blank(self.declaration_start(i), end);
+                Ok(end)
+            }
+            "type" if in_block && next_is_name && (self.is(i + 2, "=") || self.is(i + 2, "<")) => {
+                let end = self.skip_type_alias(i)?;
+                self.blank(self.declaration_start(i), end);
+                Ok(end)
+            }
+            "type"
+                if prev.is_some_and(|prev| self.is(prev, "export"))
+                    && (self.is(i + 1, "{") || self.is(i + 1, "*")) =>
+            {
+                // `export type { A } from "./a"`
+                let end = self.skip_module_statement(i + 1);
+                self.blank(i - 1, end);
+                Ok(end)
+            }
+            "declare" if in_block && next_is_name => match self.skip_declare(i)? {
+                Some(end) => {
+                    self.blank(self.declaration_start(i), end);
+                    Ok(end)
+                }
+                None => Ok(i + 1),
+            },
+            "abstract" if self.is(i + 1, "class") && self.same_line(i + 1) => {
+                self.blank(i, i + 1);
+                Ok(i + 1)
+            }
+            "namespace" | "module"
+                if in_block
+                    && self.same_line(i + 1)
+                    && (next_is_name || self.tok(i + 1).kind == Kind::String)
+                    && (self.is(i + 2, "{") || self.is(i + 2, ".")) =>
+            {
+                Err(self.error(
+                    i,
+                    "TypeScript namespaces are not supported when stripping types",
+                ))
+            }
+            "import" if !self.is(i + 1, "(") && !self.is(i + 1, ".") => self.import(i),
+            "export" => self.export(i),
+            "as" | "satisfies"
+                if prev.is_some_and(|prev| self.ends_expr(prev)) && self.same_line(i) =>
+            {
+                let end = self.skip_type(i + 1)?;
+                self.blank(i, end);
+                Ok(end)
+            }
+            "catch" if self.is(i + 1, "(") => {
+                // `catch (e: unknown)`
+                let close = self.matching(i + 1);
+                if self.is(i + 3, ":") {
+                    self.blank(i + 3, close);
+                }
+                self.walk(i + 2, close, Ctx::Expr, &|_, _, _| false)?;
+                Ok(close + 1)
+            }
+            _ => Ok(i + 1),
+        }
+    }
+
+    /// `(` in an expression: arrow function parameters, method parameters in an object
+    /// literal, or a call or parenthesized expression.
+    fn paren(&mut self, i: usize, ctx: Ctx, prev: Option<usize>) -> Result<usize, TypeStripError> {
+        let close = self.matching(i);
+        let after_name = prev.is_some_and(|prev| {
+            matches!(
+                self.tok(prev).kind,
+                Kind::Ident | Kind::String | Kind::Number | Kind::PrivateName
+            ) || self.is(prev, "]")
+        });
+        let is_method = ctx == Ctx::Object && after_name;
+
+        if self.is(close + 1, "=>") {
+            self.params(i, close, None)?;
+            return Ok(close + 1);
+        }
+        if self.is(close + 1, ":")
+            && (is_method
+                || !prev.is_some_and(|prev| self.ends_expr(prev))
+                || prev.is_some_and(|prev| self.is(prev, "async")))
+        {
+            // Return type of an arrow function or object literal method
+            if let Ok(end) = self.skip_type(close + 2)
+                && (self.is(end, "=>") || (is_method && self.is(end, "{")))
+            {
+                self.params(i, close, None)?;
+                self.blank(close + 1, end);
+                return Ok(end);
+            }
+        }
+        if is_method && self.is(close + 1, "{") {
+            self.params(i, close, None)?;
+            return Ok(close + 1);
+        }
+
+        self.walk(i + 1, close, Ctx::Expr, &|_, _, _| false)?;
+        Ok(close + 1)
+    }
+
+    /// `<` in an expression: type arguments, generic arrow function parameters or a type
+    /// assertion. Anything else is a comparison.
+    fn angle(&mut self, i: usize, ctx: Ctx, prev: Option<usize>) -> Result<usize, TypeStripError> {
+        let after_expr = prev.is_some_and(|prev| self.ends_expr(prev));
+        let Some(end) = self.skip_angle(i) else {
+            return self.not_a_type_at(i, after_expr);
+        };
+
+        if after_expr {
+            // `f<T>(x)`, `new Map<K, V>()` and tagged templates
+            let is_call = self.same_line(i)
+                && (self.is(end, "(")
+                    || matches!(self.tok(end).kind, Kind::Template | Kind::TemplateHead));
+            if is_call {
+                self.blank(i, end);
+                if self.is(end, "(") {
+                    // Parameters of a generic object literal method
+                    return self.paren(end, ctx, prev);
+                }
+                return Ok(end);
+            }
+            return Ok(i + 1);
+        }
+
+        if self.is(end, "(") && self.is_arrow_params(end) {
+            self.blank(i, end);
+            return Ok(end);
+        }
+        if self.tsx {
+            return Err(self.error(i, TSX_ASSERTION));
+        }
+        // `<T>value` type assertion
+        self.blank(i, end);
+        Ok(end)
+    }
+
+    fn not_a_type_at(&self, i: usize, after_expr: bool) -> Result<usize, TypeStripError> {
+        if self.tsx && !after_expr {
+            return Err(self.error(i, TSX_ASSERTION));
+        }
+        Ok(i + 1)
+    }
+
+    fn is_arrow_params(&self, open: usize) -> bool {
+        let close = self.matching(open);
+        self.is(close + 1, "=>")
+            || (self.is(close + 1, ":")
+                && self
+                    .skip_type(close + 2)
+                    .is_ok_and(|end| self.is(end, "=>")))
+    }
+
+    /// Function and method parameters between `open` and `close`. For constructors,
+    /// `properties` collects the names of parameter properties.
+    fn params(
+        &mut self,
+        open: usize,
+        close: usize,
+        mut properties: Option<&mut Vec<String>>,
+    ) -> Result<(), TypeStripError> {
+        let mut j = open + 1;
+        while j < close {
+            let start = j;
+
+            let mut has_modifier = false;
+            while TS_MODIFIERS.contains(&self.text(j))
+                && (self.is_ident(j + 1) || self.is(j + 1, "{") || self.is(j + 1, "["))
+            {
+                if properties.is_none() {
+                    return Err(
+                        self.error(j, "Parameter properties are only allowed in constructors")
+                    );
+                }
+                self.blank(j, j + 1);
+                has_modifier = true;
+                j += 1;
+            }
+            if has_modifier && let Some(properties) = properties.as_deref_mut() {
+                if !self.is_ident(j) {
+                    return Err(self.error(j, "Parameter properties must be plain identifiers"));
+                }
+                properties.push(self.text(j).to_string());
+            }
+
+            if self.is(j, "this") && self.is(j + 1, ":") {
+                // `this` parameter, with its comma
+                let end = self.skip_type(j + 2)?;
+                let end = if self.is(end, ",") { end + 1 } else { end };
+                self.blank(start, end);
+                j = end;
+                continue;
+            }
+
+            if self.is(j, "...") {
+                j += 1;
+            }
+            if self.is(j, "{") || self.is(j, "[") {
+                let inner = if self.is(j, "{") {
+                    Ctx::Object
+                } else {
+                    Ctx::Expr
+                };
+                let pattern_close = self.matching(j);
+                self.walk(j + 1, pattern_close, inner, &|_, _, _| false)?;
+                j = pattern_close + 1;
+            } else {
+                j += 1;
+            }
+
+            if self.is(j, "?") {
+                self.blank(j, j + 1);
+                j += 1;
+            }
+            if self.is(j, ":") {
+                let end = self.skip_type(j + 1)?;
+                self.blank(j, end);
+                j = end;
+            }
+            if self.is(j, "=") {
+                j = self.walk(j + 1, close, Ctx::Expr, &|s, k, _| s.is(k, ","))?;
+            }
+            if self.is(j, ",") {
+                j += 1;
+            } else if j < close {
+                return Err(self.error(j, "Unexpected token in parameter list"));
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, i: usize) -> Result<usize, TypeStripError> {
+        let start = self.declaration_start(i);
+        let mut j = i + 1;
+        if self.is(j, "*") {
+            j += 1;
+        }
+        if self.is_ident(j) {
+            j += 1;
+        }
+        if self.is(j, "<") {
+            let end = self
+                .skip_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+            self.blank(j, end);
+            j = end;
+        }
+        if !self.is(j, "(") {
+            return Ok(j);
+        }
+        let close = self.matching(j);
+        self.params(j, close, None)?;
+        j = close + 1;
+        if self.is(j, ":") {
+            let end = self.skip_type(j + 1)?;
+            self.blank(j, end);
+            j = end;
+        }
+
+        if self.is(j, "{") {
+            let body_close = self.matching(j);
+            self.walk(j + 1, body_close, Ctx::Block, &|_, _, _| false)?;
+            return Ok(body_close + 1);
+        }
+        // An overload or `declare`d signature without a body
+        let end = if self.is(j, ";") { j + 1 } else { j };
+        self.blank(start, end);
+        Ok(end)
+    }
+
+    fn class(&mut self, i: usize) -> Result<usize, TypeStripError> {
+        let mut j = i + 1;
+        if self.is_ident(j) && !self.is(j, "extends") && !self.is(j, "implements") {
+            j += 1;
+        }
+        if self.is(j, "<") {
+            let end = self
+                .skip_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+            self.blank(j, end);
+            j = end;
+        }
+
+        let has_extends = self.is(j, "extends");
+        if has_extends {
+            j += 1;
+            while !self.is(j, "{") && !self.is(j, "implements") && self.tok(j).kind != Kind::Eof {
+                if self.is(j, "<")
+                    && let Some(end) = self.skip_angle(j)
+                {
+                    self.blank(j, end);
+                    j = end;
+                } else if self.is_opener(j) {
+                    let close = self.matching(j);
+                    self.walk(j + 1, close, Ctx::Expr, &|_, _, _| false)?;
+                    j = close + 1;
+                } else {
+                    j += 1;
+                }
+            }
+        }
+        if self.is(j, "implements") {
+            let start = j;
+            j += 1;
+            loop {
+                j = self.skip_type(j)?;
+                if !self.is(j, ",") {
+                    break;
+                }
+                j += 1;
+            }
+            self.blank(start, j);
+        }
+
+        if !self.is(j, "{") {
+            return Err(self.error(j, "Expected class body"));
+        }
+        let close = self.matching(j);
+        self.class_body(j, close, has_extends)?;
+        Ok(close + 1)
+    }
+
+    fn class_body(
+        &mut self,
+        open: usize,
+        close: usize,
+        has_extends: bool,
+    ) -> Result<(), TypeStripError> {
+        let mut j = open + 1;
+        while j < close {
+            if self.is(j, ";") {
+                j += 1;
+                continue;
+            }
+            let start = j;
+
+            while self.is(j, "@") {
+                j += 1;
+                while self.is_ident(j) || self.is(j, ".") {
+                    j += 1;
+                }
+                if self.is(j, "(") {
+                    j = self.skip_brackets(j);
+                }
+            }
+
+            let mut declaration_only = false;
+            while MODIFIERS.contains(&self.text(j)) && self.is_ident(j) && self.is_modifier(j) {
+                let modifier = self.text(j).to_string();
+                if TS_MODIFIERS.contains(&modifier.as_str()) {
+                    self.blank(j, j + 1);
+                }
+                declaration_only |= modifier == "abstract" || modifier == "declare";
+                j += 1;
+            }
+            if self.is(j - 1, "static") && self.is(j, "{") {
+                let block_close = self.matching(j);
+                self.walk(j + 1, block_close, Ctx::Block, &|_, _, _| false)?;
+                j = block_close + 1;
+                continue;
+            }
+            if self.is(j, "*") {
+                j += 1;
+            }
+
+            // Index signature: `[key: string]: T;`
+            if self.is(j, "[") && self.is_ident(j + 1) && self.is(j + 2, ":") {
+                let mut end = self.matching(j) + 1;
+                if self.is(end, ":") {
+                    end = self.skip_type(end + 1)?;
+                }
+                if self.is(end, ";") {
+                    end += 1;
+                }
+                self.blank(start, end);
+                j = end;
+                continue;
+            }
+
+            let name = j;
+            if self.is(j, "[") {
+                let key_close = self.matching(j);
+                self.walk(j + 1, key_close, Ctx::Expr, &|_, _, _| false)?;
+                j = key_close + 1;
+            } else {
+                j += 1;
+            }
+            if self.is(j, "?") || self.is(j, "!") {
+                self.blank(j, j + 1);
+                j += 1;
+            }
+
+            if self.is(j, "<") || self.is(j, "(") {
+                j = self.method(start, name, j, has_extends, declaration_only)?;
+                continue;
+            }
+
+            // Property
+            if self.is(j, ":") {
+                let end = self.skip_type(j + 1)?;
+                self.blank(j, end);
+                j = end;
+            }
+            if self.is(j, "=") {
+                j = self.walk(j + 1, close, Ctx::Expr, &|s, k, prev| {
+                    s.is(k, ";") || s.asi_before(k, prev)
+                })?;
+            }
+            if self.is(j, ";") {
+                j += 1;
+            }
+            if declaration_only {
+                self.blank(start, j);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the modifier keyword at `i` modifies a member rather than naming one.
+    fn is_modifier(&self, i: usize) -> bool {
+        let next = i + 1;
+        self.same_line(next)
+            && (matches!(
+                self.tok(next).kind,
+                Kind::Ident | Kind::String | Kind::Number | Kind::PrivateName
+            ) || self.is(next, "[")
+                || self.is(next, "*")
+                || (self.is(i, "static") && self.is(next, "{")))
+    }
+
+    /// A class method from its type parameters or parameter list at `j` to the end of
+    /// its body.
+    fn method(
+        &mut self,
+        start: usize,
+        name: usize,
+        mut j: usize,
+        has_extends: bool,
+        declaration_only: bool,
+    ) -> Result<usize, TypeStripError> {
+        if self.is(j, "<") {
+            let end = self
+                .skip_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+            self.blank(j, end);
+            j = end;
+        }
+        if !self.is(j, "(") {
+            return Err(self.error(j, "Expected parameter list"));
+        }
+
+        let is_constructor = self.is(name, "constructor");
+        let mut properties = Vec::new();
+        let close = self.matching(j);
+        self.params(j, close, is_constructor.then_some(&mut properties))?;
+        j = close + 1;
+        if self.is(j, ":") {
+            let end = self.skip_type(j + 1)?;
+            self.blank(j, end);
+            j = end;
+        }
+
+        if !self.is(j, "{") || declaration_only {
+            // Overload signature or abstract method
+            let end = if self.is(j, ";") { j + 1 } else { j };
+            self.blank(start, end);
+            return Ok(end);
+        }
+
+        let body_close = self.matching(j);
+        if !properties.is_empty() {
+            let assignments: Vec<String> = properties
+                .iter()
+                .map(|name| format!("this.{0} = {0};", name))
+                .collect();
+            let offset = self.property_assignment_offset(j, body_close, has_extends);
+            self.insert(offset, format!(" {}", assignments.join(" ")));
+        }
+        self.walk(j + 1, body_close, Ctx::Block, &|_, _, _| false)?;
+        Ok(body_close + 1)
+    }
+
+    /// Where parameter properties get assigned: at the start of the constructor body, or
+    /// after the `super()` call in derived classes, since `this` isn't usable before it.
+    fn property_assignment_offset(&self, open: usize, close: usize, has_extends: bool) -> usize {
+        let mut j = open + 1;
+        while has_extends && j < close {
+            if self.is(j, "super") && self.is(j + 1, "(") {
+                let call_close = self.matching(j + 1);
+                let end = if self.is(call_close + 1, ";") {
+                    call_close + 1
+                } else {
+                    call_close
+                };
+                return self.tok(end).end;
+            }
+            j = if self.is_opener(j) {
+                self.skip_brackets(j)
+            } else {
+                j + 1
+            };
+        }
+        self.tok(open).end
+    }
+
+    /// `let`, `const` and `var` declarations, from the keyword to the end of the last
+    /// declarator.
+    fn declaration(&mut self, i: usize, end: usize) -> Result<usize, TypeStripError> {
+        let mut j = i + 1;
+        loop {
+            if self.is(j, "{") || self.is(j, "[") {
+                let inner = if self.is(j, "{") {
+                    Ctx::Object
+                } else {
+                    Ctx::Expr
+                };
+                let close = self.matching(j);
+                self.walk(j + 1, close, inner, &|_, _, _| false)?;
+                j = close + 1;
+            } else if self.is_ident(j) {
+                j += 1;
+            } else {
+                return Ok(j);
+            }
+
+            if self.is(j, "!") && self.is(j + 1, ":") {
+                self.blank(j, j + 1);
+                j += 1;
+            }
+            if self.is(j, ":") {
+                let type_end = self.skip_type(j + 1)?;
+                self.blank(j, type_end);
+                j = type_end;
+            }
+            if self.is(j, "=") {
+                j = self.walk(j + 1, end, Ctx::Expr, &|s, k, prev| {
+                    s.is(k, ",") || s.is(k, ";") || s.asi_before(k, prev)
+                })?;
+            }
+            if !self.is(j, ",") {
+                return Ok(j);
+            }
+            j += 1;
+        }
+    }
+
+    /// Rewrites an enum into the object TypeScript compiles it to, keeping each member
+    /// on its original line.
+    fn enumeration(&mut self, i: usize) -> Result<usize, TypeStripError> {
+        let keyword = if self.is(i, "const") { i + 1 } else { i };
+        let name = self.text(keyword + 1).to_string();
+        let open = keyword + 2;
+        if !self.is_ident(keyword + 1) || !self.is(open, "{") {
+            return Err(self.error(i, "Invalid enum declaration"));
+        }
+        let close = self.matching(open);
+
+        let mut out = format!("var {0}; (function ({0}) {{", name);
+        let mut position = self.tok(open).end;
+        let mut previous: Option<(String, Option<f64>)> = None;
+        let mut j = open + 1;
+        while j < close {
+            let key = match self.tok(j).kind {
+                Kind::Ident => format!("\"{}\"", self.text(j)),
+                Kind::String => self.text(j).to_string(),
+                _ => return Err(self.error(j, "Invalid enum member name")),
+            };
+            let member_start = self.tok(j).start;
+            out.extend(std::iter::repeat_n(
+                '\n',
+                self.src[position..member_start].matches('\n').count(),
+            ));
+
+            let mut member_end = j + 1;
+            let (value, number, is_string) = if self.is(j + 1, "=") {
+                let mut k = j + 2;
+                while k < close && !self.is(k, ",") {
+                    k = if self.is_opener(k) {
+                        self.skip_brackets(k)
+                    } else {
+                        k + 1
+                    };
+                }
+                member_end = k;
+                let initializer = &self.src[self.tok(j + 2).start..self.tok(k - 1).end];
+                let is_string =
+                    k == j + 3 && matches!(self.tok(j + 2).kind, Kind::String | Kind::Template);
+                (
+                    initializer.to_string(),
+                    initializer.parse::<f64>().ok(),
+                    is_string,
+                )
+            } else {
+                match &previous {
+                    None => ("0".to_string(), Some(0.0), false),
+                    Some((_, Some(n))) => ((n + 1.0).to_string(), Some(n + 1.0), false),
+                    Some((previous_key, None)) => {
+                        (format!("{}[{}] + 1", name, previous_key), None, false)
+                    }
+                }
+            };
+
+            if is_string {
+                out.push_str(&format!(" {}[{}] = {};", name, key, value));
+            } else {
+                out.push_str(&format!(" {0}[{0}[{1}] = {2}] = {1};", name, key, value));
+            }
+            position = self.tok(member_end - 1).end;
+            previous = Some((key, number));
+            j = if self.is(member_end, ",") {
+                member_end + 1
+            } else {
+                member_end
+            };
+        }
+
+        out.extend(std::iter::repeat_n(
+            '\n',
+            self.src[position..self.tok(close).start]
+                .matches('\n')
+                .count(),
+        ));
+        out.push_str(&format!(" }})({0} || ({0} = {{}}));", name));
+        self.edits.push(Edit {
+            start: self.tok(i).start,
+            end: self.tok(close).end,
+            text: Some(out),
+        });
+        Ok(close + 1)
+    }
+
+    fn import(&mut self, i: usize) -> Result<usize, TypeStripError> {
+        if self.is_ident(i + 1) && self.is(i + 2, "=")
+            || self.is(i + 1, "type") && self.is(i + 3, "=")
+        {
+            return Err(self.error(i, "`import x = ...` is not supported when stripping types"));
+        }
+        let type_only = self.is(i + 1, "type")
+            && !self.is(i + 2, "from")
+            && !self.is(i + 2, ",")
+            && !self.is(i + 2, "=");
+        if type_only {
+            let end = self.skip_module_statement(i + 1);
+            self.blank(i, end);
+            return Ok(end);
+        }
+
+        let mut j = i + 1;
+        while self.tok(j).kind != Kind::String && self.tok(j).kind != Kind::Eof {
+            if self.is(j, "{") {
+                let close = self.matching(j);
+                self.type_specifiers(j, close);
+                j = close;
+            }
+            j += 1;
+        }
+        Ok(self.skip_module_statement(j))
+    }
+
+    fn export(&mut self, i: usize) -> Result<usize, TypeStripError> {
+        let j = i + 1;
+        if self.is(j, "=") || self.is(j, "import") {
+            return Err(self.error(
+                i,
+                "`export =` and `export import` are not supported when stripping types",
+            ));
+        }
+        if self.is(j, "as") && self.is(j + 1, "namespace") {
+            let end = self.skip_module_statement(j);
+            self.blank(i, end);
+            return Ok(end);
+        }
+        if self.is(j, "{") {
+            let close = self.matching(j);
+            self.type_specifiers(j, close);
+            return Ok(self.skip_module_statement(close + 1));
+        }
+        Ok(j)
+    }
+
+    /// Blanks `type` specifiers in an import or export clause like `{ type A, b }`.
+    fn type_specifiers(&mut self, open: usize, close: usize) {
+        let mut start = open + 1;
+        while start < close {
+            let mut end = start;
+            while end < close && !self.is(end, ",") {
+                end += 1;
+            }
+            let len = end - start;
+            // `type as X` imports a value named `type`
+            let type_only = self.is(start, "type")
+                && (len == 2 || len == 4 || (len == 3 && !self.is(start + 1, "as")));
+            if type_only {
+                let with_comma = if self.is(end, ",") { end + 1 } else { end };
+                self.blank(start, with_comma);
+            }
+            start = end + 1;
+        }
+    }
+
+    /// The index after the rest of an import or export statement starting at `j`: up to
+    /// the module specifier and its attributes, or the closing brace, plus a semicolon.
+    fn skip_module_statement(&self, mut j: usize) -> usize {
+        while !matches!(self.tok(j).kind, Kind::String | Kind::Eof) && !self.is(j, ";") {
+            if self.is(j, "{") {
+                j = self.matching(j);
+                if !self.is(j + 1, "from") {
+                    break;
+                }
+            }
+            if j > 0
+                && self.tok(j).newline_before
+                && self.ends_expr(j - 1)
+                && !self.is(j, "from")
+                && !self.is(j, "{")
+            {
+                return j;
+            }
+            j += 1;
+        }
+        if self.tok(j).kind == Kind::String {
+            j += 1;
+            if (self.is(j, "with") || self.is(j, "assert")) && self.is(j + 1, "{") {
+                j = self.matching(j + 1) + 1;
+            }
+        } else if self.is(j, "}") {
+            j += 1;
+        }
+        if self.is(j, ";") {
+            j += 1;
+        }
+        j
+    }
+
+    fn skip_interface(&self, i: usize) -> Result<usize, TypeStripError> {
+        let mut j = i + 2;
+        if self.is(j, "<") {
+            j = self
+                .skip_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+        }
+        if self.is(j, "extends") {
+            j += 1;
+            loop {
+                j = self.skip_type(j)?;
+                if !self.is(j, ",") {
+                    break;
+                }
+                j += 1;
+            }
+        }
+        if !self.is(j, "{") {
+            return Err(self.error(j, "Expected interface body"));
+        }
+        Ok(self.matching(j) + 1)
+    }
+
+    fn skip_type_alias(&self, i: usize) -> Result<usize, TypeStripError> {
+        let mut j = i + 2;
+        if self.is(j, "<") {
+            j = self
+                .skip_angle(j)
+                .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+        }
+        if !self.is(j, "=") {
+            return Err(self.error(j, "Expected '=' in type alias"));
+        }
+        let end = self.skip_type(j + 1)?;
+        Ok(if self.is(end, ";") { end + 1 } else { end })
+    }
+
+    /// The end of a `declare` statement, or `None` if `declare` is just an identifier.
+    fn skip_declare(&self, i: usize) -> Result<Option<usize>, TypeStripError> {
+        let j = i + 1;
+        let end = match self.text(j) {
+            "const" if self.is(j + 1, "enum") => self.skip_to_block_end(j),
+            "const" | "let" | "var" => {
+                let mut k = j + 1;
+                loop {
+                    k += 1;
+                    if self.is(k, ":") {
+                        k = self.skip_type(k + 1)?;
+                    }
+                    if !self.is(k, ",") {
+                        break k;
+                    }
+                }
+            }
+            "function" | "async" => {
+                let mut k = j + 1;
+                while !self.is(k, "(") && self.tok(k).kind != Kind::Eof {
+                    k = if self.is(k, "<") {
+                        self.skip_angle(k).unwrap_or(k + 1)
+                    } else {
+                        k + 1
+                    };
+                }
+                k = self.matching(k) + 1;
+                if self.is(k, ":") {
+                    k = self.skip_type(k + 1)?;
+                }
+                k
+            }
+            "type" => self.skip_type_alias(j)?,
+            "class" | "abstract" | "enum" | "namespace" | "module" | "global" | "interface" => {
+                self.skip_to_block_end(j)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(if self.is(end, ";") { end + 1 } else { end }))
+    }
+
+    fn skip_to_block_end(&self, mut j: usize) -> usize {
+        while !self.is(j, "{") && self.tok(j).kind != Kind::Eof {
+            j = if self.is(j, "<") {
+                self.skip_angle(j).unwrap_or(j + 1)
+            } else {
+                j + 1
+            };
+        }
+        self.matching(j) + 1
+    }
+
+    /// Skips a `<...>` list of type parameters or arguments, returning the index after
+    /// the closing `>`, or `None` if the tokens can't be one.
+    fn skip_angle(&self, i: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut j = i;
+        loop {
+            let token = self.tok(j);
+            if token.kind == Kind::Eof {
+                return None;
+            }
+            if self.is_opener(j) {
+                j = self.skip_brackets(j);
+                continue;
+            }
+            if token.kind == Kind::Punct {
+                match self.text(j) {
+                    "<" => depth += 1,
+                    ">" => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(j + 1);
+                        }
+                    }
+                    ")" | "]" | "}" | ";" | ":" | "&&" | "||" | "==" | "===" | "!=" | "!=="
+                    | "+" | "++" | "--" | "+=" | "-=" | "*=" | "/=" | "%" | "/" | "*" | "**"
+                    | "!" | "<<" | "<=" | "??" => return None,
+                    _ => {}
+                }
+            }
+            j += 1;
+        }
+    }
+
+    fn skip_type(&self, i: usize) -> Result<usize, TypeStripError> {
+        self.skip_type_with(i, true)
+    }
+
+    fn skip_type_with(&self, i: usize, allow_conditional: bool) -> Result<usize, TypeStripError> {
+        let mut j = i;
+        if self.is(j, "|") || self.is(j, "&") {
+            j += 1;
+        }
+        j = self.skip_type_operand(j)?;
+        while self.is(j, "|") || self.is(j, "&") {
+            j = self.skip_type_operand(j + 1)?;
+        }
+        if allow_conditional && self.is(j, "extends") {
+            j = self.skip_type_with(j + 1, false)?;
+            if !self.is(j, "?") {
+                return Err(self.error(j, "Expected '?' in conditional type"));
+            }
+            j = self.skip_type(j + 1)?;
+            if !self.is(j, ":") {
+                return Err(self.error(j, "Expected ':' in conditional type"));
+            }
+            j = self.skip_type(j + 1)?;
+        }
+        Ok(j)
+    }
+
+    fn skip_type_operand(&self, i: usize) -> Result<usize, TypeStripError> {
+        let mut j = i;
+        if matches!(self.text(j), "keyof" | "unique" | "readonly" | "infer")
+            && self.is_ident(j)
+            && self.same_line(j + 1)
+        {
+            let is_operator = self.is_ident(j + 1)
+                || self.is_opener(j + 1)
+                || matches!(self.tok(j + 1).kind, Kind::String | Kind::Number);
+            if is_operator {
+                return self.skip_type_operand(j + 1);
+            }
+        }
+        if self.is(j, "asserts") && self.is_ident(j + 1) && self.same_line(j + 1) {
+            j += 2;
+            if self.is(j, "is") {
+                return self.skip_type(j + 1);
+            }
+            return Ok(j);
+        }
+
+        j = self.skip_type_primary(j)?;
+        while self.is(j, "[") && self.same_line(j) {
+            j = self.matching(j) + 1;
+        }
+        // Type predicate: `value is string`
+        if self.is(j, "is") && self.same_line(j) {
+            return self.skip_type(j + 1);
+        }
+        Ok(j)
+    }
+
+    fn skip_type_primary(&self, j: usize) -> Result<usize, TypeStripError> {
+        let token = self.tok(j);
+        match token.kind {
+            Kind::String | Kind::Number | Kind::Template => return Ok(j + 1),
+            Kind::TemplateHead => return Ok(self.skip_brackets(j)),
+            _ => {}
+        }
+
+        match self.text(j) {
+            "(" => {
+                let close = self.matching(j);
+                if self.is(close + 1, "=>") {
+                    // Function type
+                    return self.skip_type(close + 2);
+                }
+                Ok(close + 1)
+            }
+            "<" => {
+                // Generic function type
+                let open = self
+                    .skip_angle(j)
+                    .ok_or_else(|| self.error(j, "Invalid type parameters"))?;
+                if !self.is(open, "(") || !self.is(self.matching(open) + 1, "=>") {
+                    return Err(self.error(j, "Expected function type"));
+                }
+                self.skip_type(self.matching(open) + 2)
+            }
+            "{" | "[" => Ok(self.matching(j) + 1),
+            "-" if self.tok(j + 1).kind == Kind::Number => Ok(j + 2),
+            "abstract" if self.is(j + 1, "new") => self.skip_type_primary(j + 1),
+            "new" if self.is(j + 1, "(") || self.is(j + 1, "<") => self.skip_type_primary(j + 1),
+            "typeof" => {
+                let mut k = j + 1;
+                if self.is(k, "import") {
+                    return self.skip_type_primary(k);
+                }
+                k += 1;
+                while (self.is(k, ".") || self.is(k, "?."))
+                    && matches!(self.tok(k + 1).kind, Kind::Ident | Kind::PrivateName)
+                {
+                    k += 2;
+                }
+                Ok(self.skip_type_arguments(k))
+            }
+            "import" if self.is(j + 1, "(") => {
+                let mut k = self.matching(j + 1) + 1;
+                while self.is(k, ".") && self.is_ident(k + 1) {
+                    k += 2;
+                }
+                Ok(self.skip_type_arguments(k))
+            }
+            _ if token.kind == Kind::Ident => {
+                let mut k = j + 1;
+                while self.is(k, ".") && self.is_ident(k + 1) {
+                    k += 2;
+                }
+                Ok(self.skip_type_arguments(k))
+            }
+            _ => Err(self.error(j, "Expected a type")),
+        }
+    }
+
+    fn skip_type_arguments(&self, j: usize) -> usize {
+        if self.is(j, "<") && self.same_line(j) {
+            return self.skip_angle(j).unwrap_or(j);
+        }
+        j
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strips `source` and checks every line kept its length, so positions still match.
+    fn strip(source: &str) -> String {
+        let stripped = strip_types(source, false).unwrap();
+        let lengths = |text: &str| text.lines().map(str::len).collect::<Vec<_>>();
+        assert_eq!(lengths(&stripped), lengths(source), "{:?}", stripped);
+        stripped
+    }
+
+    fn error(source: &str) -> TypeStripError {
+        strip_types(source, false).unwrap_err()
+    }
+
+    #[test]
+    fn strips_annotations_and_generics() {
+        assert_eq!(strip("let x: number = 1;"), "let x         = 1;");
+        assert_eq!(
+            strip("function f<T>(a: T, b?: string): T { return a; }"),
+            "function f   (a   , b         )    { return a; }"
+        );
+        assert_eq!(strip("const f = <T,>(x: T) => x;"), "const f =     (x   ) => x;");
+        assert_eq!(strip("let a = b<c>(d);"), "let a = b   (d);");
+    }
+
+    #[test]
+    fn strips_type_declarations_and_type_only_imports() {
+        assert_eq!(
+            strip("interface A { x: number }\ntype B = A | null;\nexport const y = 1;"),
+            "                         \n                  \nexport const y = 1;"
+        );
+        assert_eq!(
+            strip("import type { A } from './a';\nimport { type B, c } from './b';\nexport type { A };"),
+            "                             \nimport {         c } from './b';\n                  "
+        );
+        assert_eq!(
+            strip("declare const g: number;\nfunction o(a: string): void;\nfunction o(a: any) {}"),
+            "                        \n                            \nfunction o(a     ) {}"
+        );
+    }
+
+    #[test]
+    fn strips_assertions() {
+        assert_eq!(
+            strip("const v = x as unknown as string;\nconst w = y!;\nconst z = { a: 1 } satisfies Record<string, number>;"),
+            "const v = x                     ;\nconst w = y ;\nconst z = { a: 1 }                                 ;"
+        );
+    }
+
+    #[test]
+    fn strips_class_member_modifiers() {
+        assert_eq!(
+            strip("class C { private x?: number; static y: string = 'a'; abstract m(): void; }"),
+            "class C {         x         ; static y         = 'a';                     }"
+        );
+    }
+
+    #[test]
+    fn generates_enums_on_their_own_lines() {
+        let stripped = strip_types("enum Color { Red, Green = 5, Blue }\nconst enum E { A = 'a' }", false).unwrap();
+        let lines: Vec<_> = stripped.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"var Color; (function (Color) { Color[Color["Red"] = 0] = "Red"; Color[Color["Green"] = 5] = "Green"; Color[Color["Blue"] = 6] = "Blue"; })(Color || (Color = {}));"#,
+                r#"var E; (function (E) { E["A"] = 'a'; })(E || (E = {}));"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn assigns_parameter_properties() {
+        assert_eq!(
+            strip_types("class P { constructor(private name: string, public readonly age = 1) {} }", false).unwrap(),
+            "class P { constructor(        name        ,                 age = 1) { this.name = name; this.age = age;} }"
+        );
+        // After super() in derived classes
+        assert_eq!(
+            strip_types("class Q extends P { constructor(public id: number) { super('x'); } }", false).unwrap(),
+            "class Q extends P { constructor(       id        ) { super('x'); this.id = id; } }"
+        );
+    }
+
+    #[test]
+    fn rejects_syntax_without_a_type_free_equivalent() {
+        let namespace = error("let a = 1;\nnamespace N { }");
+        assert!(namespace.message.contains("namespaces are not supported"), "{}", namespace);
+        assert_eq!((namespace.line, namespace.column), (2, 1));
+
+        assert!(error("import fs = require('fs');").message.contains("import x = "));
+        // In TSX, `<number>(x)` opens an element that never closes
+        let assertion = strip_types("const n = <number>(x);", true).unwrap_err();
+        assert_eq!(assertion.message, "Unterminated JSX contents");
+        assert_eq!((assertion.line, assertion.column), (1, 11));
+        let generic = strip_types("const n = <T extends object>(x);", true).unwrap_err();
+        assert_eq!(generic.message, TSX_ASSERTION);
+    }
+
+    #[test]
+    fn compiles_jsx_in_tsx() {
+        assert_eq!(
+            strip_types("const el = <div id=\"a\" hidden>Hi &amp; bye</div>;", true).unwrap(),
+            r#"const el = React.createElement("div", { id: "a", hidden: true, }, "Hi & bye");"#
+        );
+        assert_eq!(
+            strip_types("const el = <List items={xs as string[]} {...rest}><Item />{n!}</List>;", true).unwrap(),
+            "const el = React.createElement(List, { items: xs            , ...rest, }, \
+             React.createElement(Item, null), n );"
+        );
+        assert_eq!(
+            strip_types("/** @jsx h */\n/** @jsxFrag Frag */\nconst f = <><b>{/* none */}</b>text</>;", true)
+                .unwrap(),
+            "/** @jsx h */\n/** @jsxFrag Frag */\nconst f = h(Frag, null, h(\"b\", null), \"text\");"
+        );
+        // Generic arrow functions still need a `,` to tell them from JSX
+        assert_eq!(strip_types("const id = <T,>(x: T) => x;", true).unwrap(), "const id =     (x   ) => x;");
+    }
+
+    #[test]
+    fn keeps_the_lines_of_compiled_jsx() {
+        let stripped = strip_types(
+            "const el = (\n  <ul className='list'>\n    {items.map((item: Item) => (\n      <li key={item.id}>{item.name}</li>\n    ))}\n  </ul>\n);\nlet after: number;",
+            true,
+        )
+        .unwrap();
+        assert_eq!(stripped.lines().count(), 8);
+        assert_eq!(stripped.lines().last(), Some("let after        ;"));
+        assert!(stripped.contains(r#"React.createElement("li", { key: item.id, }, item.name)"#), "{}", stripped);
+    }
+
+    #[test]
+    fn reports_jsx_errors_where_they_are() {
+        let mismatched = strip_types("const el = <a>\n  <b></c>\n</a>;", true).unwrap_err();
+        assert_eq!(mismatched.message, "Expected corresponding JSX closing tag for 'b'");
+        assert_eq!((mismatched.line, mismatched.column), (2, 6));
+
+        let nested = strip_types("const el = <a>\n  {(() => { namespace N {} })()}</a>;", true).unwrap_err();
+        assert!(nested.message.contains("namespaces are not supported"), "{}", nested);
+        assert_eq!((nested.line, nested.column), (2, 13));
+    }
+
+    #[test]
+    fn strips_only_typescript_modules() {
+        assert!(matches!(strip_if_typescript("/app/main.js", "let x: number;"), Ok(Cow::Borrowed(_))));
+        assert_eq!(strip_if_typescript("/app/main.ts", "let x: number;").unwrap(), "let x        ;");
+        assert_eq!(strip_if_typescript("/app/main.mts", "let x: number;").unwrap(), "let x        ;");
+        assert_eq!(
+            strip_if_typescript("/app/App.tsx", "let x: number = <br />;").unwrap(),
+            r#"let x         = React.createElement("br", null);"#
+        );
+    }
+}
@@ -1,12 +1,32 @@
-use std::sync::Once;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Once};
+use std::time::Duration;
 use tokio::sync::mpsc;
 use v8;
 use bindings::{print_cb, add_cb};
 
+use crate::modules::{ModuleLoader, ModuleMap, ModuleType};
+
+pub use error::{JsError, JsErrorKind, StackFrame};
+pub use ops::{Extension, OpDecl, OpError, OpFuture, OpResult, OpValue};
+
+mod abort;
 mod bindings;
+mod console;
+mod error;
 mod timers;
 mod fetch;
+mod internals;
+mod events;
 mod event_loop;
+mod inspect;
+mod limits;
+mod ops;
+mod rejections;
+mod snapshot;
+mod streams;
+mod workers;
 
 static INIT: Once = Once::new();
 
@@ -20,19 +40,97 @@ pub fn init_v8() {
 
 pub type CallbackId = u64;
 
+/// Modules built into the runtime, by specifier. Each exports the properties of the
+/// internals value named next to it, which the bootstrap scripts set up.
+const BUILTIN_MODULES: &[(&str, &str)] = &[("node:timers/promises", "timersPromises")];
+
+fn builtin_module_internal(specifier: &str) -> Option<&'static str> {
+    BUILTIN_MODULES
+        .iter()
+        .find(|(name, _)| *name == specifier)
+        .map(|(_, internal)| *internal)
+}
+
 pub enum SchedulerMessage {
     ScheduleTimeout(CallbackId, u64),
     ScheduleInterval(CallbackId, u64),
     ClearTimer(CallbackId),
-    Fetch(CallbackId, String), // Simple fetch with URL
+    Op(CallbackId, OpFuture), // Async op future to drive to completion
+    CancelOp(CallbackId),     // Drop an async op's future before it completes
     Shutdown,
 }
 
 pub enum CallbackMessage {
     ExecuteTimeout(CallbackId),
     ExecuteInterval(CallbackId),
-    FetchSuccess(CallbackId, String), // Returns body as string
-    FetchError(CallbackId, String),
+    OpResult(CallbackId, OpResult),
+}
+
+/// Work that will eventually call back into JavaScript. Stored in an isolate
+/// slot so the native timer and op bindings can update it as they schedule.
+#[derive(Default)]
+pub(crate) struct PendingOps {
+    pub(crate) timeouts: HashSet<CallbackId>,
+    pub(crate) intervals: HashSet<CallbackId>,
+    pub(crate) ops: HashSet<CallbackId>,
+}
+
+impl PendingOps {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.timeouts.is_empty() && self.intervals.is_empty() && self.ops.is_empty()
+    }
+}
+
+/// What to do with a promise rejection that is still unhandled after a microtask checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnhandledRejectionPolicy {
+    /// Print the rejection to stderr and keep running.
+    Warn,
+    /// Stop processing callbacks and return the rejection as an error. Other unhandled
+    /// rejections stay queued, and the following calls return them one at a time.
+    #[default]
+    Throw,
+    /// Dispatch an `unhandledrejection` event on `globalThis` first. If no listener
+    /// calls `preventDefault()`, behave like `Throw`.
+    Dispatch,
+}
+
+#[derive(Default)]
+pub struct RuntimeOptions {
+    pub unhandled_rejection: UnhandledRejectionPolicy,
+    /// Extra ops and JS bindings, installed after the built-in ones.
+    pub extensions: Vec<Extension>,
+    /// Run timers on a virtual clock instead of real time. Timers only fire when
+    /// `advance_time()` or `run_all_timers()` moves the clock, and `Date.now()`,
+    /// `new Date()` and `performance.now()` report virtual time.
+    pub virtual_time: bool,
+    /// Maximum V8 heap size in bytes. Reaching it terminates the running script with a
+    /// `JsErrorKind::HeapLimitExceeded` error instead of aborting the process.
+    pub max_heap_size: Option<usize>,
+    /// Wall-clock budget for each `execute_*` call and each timer or op callback.
+    /// Running past it terminates the script with a `JsErrorKind::TimeLimitExceeded` error.
+    pub execution_timeout: Option<Duration>,
+    /// A blob from `JsRuntime::create_snapshot()` to start from instead of running the
+    /// built-in bootstrap. It must come from the same build of toyjs and V8. `None` uses
+    /// the snapshot embedded by the `startup-snapshot` feature, if enabled, and an empty
+    /// blob always runs the bootstrap.
+    pub startup_snapshot: Option<&'static [u8]>,
+    /// Directory for the V8 code cache of compiled modules. Modules whose source is
+    /// unchanged since an earlier run skip compilation. Disabled when `None`.
+    pub code_cache_dir: Option<PathBuf>,
+    /// Resolves and loads modules instead of the default `FsModuleLoader`. Workers
+    /// created by the runtime share it.
+    pub module_loader: Option<Arc<dyn ModuleLoader>>,
+    /// Import map applied to static and dynamic imports before the usual resolution.
+    /// Only used by the default module loader.
+    pub import_map: Option<crate::modules::ImportMap>,
+    /// Directory where modules imported from `http:` and `https:` URLs are kept after
+    /// their first download, so later runs work offline. Without it, remote modules are
+    /// downloaded on every run. Only used by the default module loader.
+    pub remote_module_dir: Option<PathBuf>,
+    /// Download remote modules again even if they are cached. Only used by the default
+    /// module loader.
+    pub reload_remote_modules: bool,
 }
 
 pub struct JsRuntime {
@@ -42,18 +140,64 @@ pub struct JsRuntime {
     scheduler_rx: Option<mpsc::UnboundedReceiver<SchedulerMessage>>,
     callback_tx: Option<mpsc::UnboundedSender<CallbackMessage>>,
     callback_rx: mpsc::UnboundedReceiver<CallbackMessage>,
+    unhandled_rejection: UnhandledRejectionPolicy,
+    // Declared after `isolate` so it is dropped after it: V8 holds a pointer into it
+    limits: limits::Limits,
+}
+
+impl Default for JsRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl JsRuntime {
     pub fn new() -> Self {
+        Self::with_options(RuntimeOptions::default())
+    }
+
+    pub fn with_options(options: RuntimeOptions) -> Self {
         init_v8();
 
         let (scheduler_tx, scheduler_rx) = mpsc::unbounded_channel();
         let (callback_tx, callback_rx) = mpsc::unbounded_channel();
 
-        let params = v8::CreateParams::default();
+        let startup_snapshot = snapshot::startup_snapshot(options.startup_snapshot);
+        let mut params = v8::CreateParams::default();
+        if let Some(snapshot) = startup_snapshot {
+            params = params
+                .snapshot_blob(snapshot.into())
+                .external_references(snapshot::external_references());
+        }
+        if let Some(max_heap_size) = options.max_heap_size {
+            params = params.heap_limits(0, max_heap_size);
+        }
         let mut isolate = v8::Isolate::new(params);
+        let limits = limits::Limits::new(&mut isolate, options.max_heap_size, options.execution_timeout);
         isolate.set_host_import_module_dynamically_callback(bindings::host_import_module_dynamically_callback);
+        isolate.set_host_initialize_import_meta_object_callback(bindings::host_initialize_import_meta_object_callback);
+        isolate.set_promise_reject_callback(rejections::promise_reject_callback);
+        // Lets JsError report structured stack frames for thrown errors
+        isolate.set_capture_stack_trace_for_uncaught_exceptions(true, 10);
+        isolate.set_slot(PendingOps::default());
+        isolate.set_slot(bindings::PendingImports::default());
+        isolate.set_slot(rejections::PendingRejections::default());
+        let module_loader = options.module_loader.clone().unwrap_or_else(|| {
+            Arc::new(crate::modules::FsModuleLoader {
+                import_map: options.import_map.clone(),
+                remote_modules: crate::modules::RemoteModules::new(
+                    options.remote_module_dir.clone(),
+                    options.reload_remote_modules,
+                ),
+            })
+        });
+        let mut module_map = ModuleMap::new(module_loader.clone());
+        module_map.code_cache = options.code_cache_dir.clone().map(crate::modules::CodeCache::new);
+        isolate.set_slot(module_map);
+        isolate.set_slot(timers::TimerState::new(scheduler_tx.clone()));
+        if options.virtual_time {
+            isolate.set_slot(timers::VirtualClock::new());
+        }
 
         let context = {
             let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
@@ -61,16 +205,26 @@ impl JsRuntime {
             let context = v8::Context::new(&scope, Default::default());
 
             let scope = &mut v8::ContextScope::new(&mut scope, context);
-            Self::setup_bindings(scope);
+            let builtins = Self::builtin_extensions(workers::WorkerDefaults {
+                startup_snapshot,
+                code_cache_dir: options.code_cache_dir.clone(),
+                module_loader: Some(module_loader),
+            });
+            if startup_snapshot.is_some() {
+                internals::restore_internals(scope);
+            } else {
+                Self::bootstrap(scope, &builtins);
+            }
+            if options.virtual_time {
+                timers::setup_virtual_date(scope);
+            }
 
-            timers::setup_timers(scope, scheduler_tx.clone());
-            fetch::setup_fetch(scope, scheduler_tx.clone());
+            ops::install_extensions(scope, &options.extensions, ops::op_count(&builtins));
+            ops::init_op_state(scope, builtins.iter().chain(&options.extensions), scheduler_tx.clone());
 
             v8::Global::new(scope, context)
         };
 
-        let _ = crate::modules::FsModuleLoader::global();
-
         Self {
             isolate,
             context,
@@ -78,50 +232,124 @@ impl JsRuntime {
             scheduler_rx: Some(scheduler_rx),
             callback_tx: Some(callback_tx),
             callback_rx,
+            unhandled_rejection: options.unhandled_rejection,
+            limits,
+        }
+    }
+
+    fn builtin_extensions(worker_defaults: workers::WorkerDefaults) -> Vec<Extension> {
+        vec![fetch::extension(), workers::extension(worker_defaults)]
+    }
+
+    /// Installs the built-in globals into a fresh context. This is the state a startup
+    /// snapshot captures, so it must not depend on per-runtime options.
+    fn bootstrap(scope: &mut v8::PinScope, builtins: &[Extension]) {
+        internals::setup_internals(scope);
+        internals::setup_primordials(scope);
+        Self::setup_bindings(scope);
+
+        events::setup_events(scope);
+        abort::setup_abort(scope);
+        timers::setup_timers(scope);
+        console::setup_console(scope);
+        streams::setup_streams(scope);
+        workers::setup_worker_internals(scope);
+
+        ops::install_extensions(scope, builtins, 0);
+    }
+
+    /// Creates a startup snapshot of the bootstrapped context, for
+    /// `RuntimeOptions::startup_snapshot`. Runtimes created from it skip compiling and
+    /// running the built-in bootstrap scripts.
+    pub fn create_snapshot() -> Vec<u8> {
+        init_v8();
+
+        let mut isolate = v8::Isolate::snapshot_creator(Some(snapshot::external_references()), None);
+        {
+            let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
+            let mut scope = handle_scope.init();
+            let context = v8::Context::new(&scope, Default::default());
+            let scope = &mut v8::ContextScope::new(&mut scope, context);
+
+            Self::bootstrap(scope, &Self::builtin_extensions(Default::default()));
+            scope.set_default_context(context);
         }
+
+        isolate
+            .create_blob(v8::FunctionCodeHandling::Keep)
+            .expect("Failed to create startup snapshot")
+            .to_vec()
     }
 
     fn setup_bindings(scope: &mut v8::PinScope) {
         let global = scope.get_current_context().global(scope);
 
+        // Not part of any web spec, so they can be locked down
+        let read_only = || v8::PropertyAttribute::READ_ONLY | v8::PropertyAttribute::DONT_DELETE;
+
         let name = v8::String::new(scope, "print").unwrap();
 
         let func = v8::FunctionTemplate::new(scope, print_cb);
         let func = func.get_function(scope).unwrap();
-        global.set(scope, name.into(), func.into());
+        global.define_own_property(scope, name.into(), func.into(), read_only());
 
         let name = v8::String::new(scope, "add").unwrap();
         let func = v8::FunctionTemplate::new(scope, add_cb);
         let func = func.get_function(scope).unwrap();
-        global.set(scope, name.into(), func.into());
+        global.define_own_property(scope, name.into(), func.into(), read_only());
     }
 
     // This is faster to run one off scripts without any imports. Cannot use fetch bindings either.
-    pub fn execute_script(&mut self, code: &str) -> String {
+    pub fn execute_script(&mut self, code: &str) -> Result<String, JsError> {
+        let limits = &self.limits;
+        let _budget = limits.start_budget();
         let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut self.isolate));
         let scope = &mut handle_scope.init();
         let context = v8::Local::new(scope, &self.context);
         let scope = &mut v8::ContextScope::new(scope, context);
+        let tc_scope_storage = std::pin::pin!(v8::TryCatch::new(scope));
+        let tc_scope = &mut tc_scope_storage.init();
 
-        let source = v8::String::new(scope, code).unwrap();
-        let script = match v8::Script::compile(scope, source, None) {
+        let source = v8::String::new(tc_scope, code).unwrap();
+        let script = match v8::Script::compile(tc_scope, source, None) {
             Some(script) => script,
-            None => return "Error: Compilation failed".to_string(),
+            None => return Err(Self::caught_error(limits, tc_scope, "Compilation failed")),
         };
 
-        let result = match script.run(scope) {
+        let result = match script.run(tc_scope) {
             Some(result) => result,
-            None => return "Error: Execution failed".to_string(),
+            None => return Err(Self::caught_error(limits, tc_scope, "Execution failed")),
         };
 
-        let result = result.to_string(scope).unwrap();
-        result.to_rust_string_lossy(scope)
+        Ok(result.to_rust_string_lossy(tc_scope))
+    }
+
+    /// Builds a `JsError` from the exception caught by `tc_scope`, from the limit that
+    /// terminated execution, or from `fallback` when V8 failed without throwing.
+    fn caught_error(
+        limits: &limits::Limits,
+        tc_scope: &mut v8::PinnedRef<v8::TryCatch<v8::HandleScope>>,
+        fallback: &str,
+    ) -> JsError {
+        if tc_scope.has_terminated() {
+            return limits.termination_error(tc_scope);
+        }
+        match tc_scope.exception() {
+            Some(exception) => JsError::from_exception(tc_scope, exception),
+            None => JsError::new(fallback),
+        }
+    }
+
+    fn throw_error(scope: &mut v8::PinScope, message: &str) {
+        let message = v8::String::new(scope, message).unwrap();
+        let exception = v8::Exception::error(scope, message);
+        scope.throw_exception(exception);
     }
 
     fn module_resolver<'a>(
         context: v8::Local<'a, v8::Context>,
         specifier: v8::Local<'a, v8::String>,
-        _import_attributes: v8::Local<'a, v8::FixedArray>,
+        import_attributes: v8::Local<'a, v8::FixedArray>,
         referrer: v8::Local<'a, v8::Module>,
     ) -> Option<v8::Local<'a, v8::Module>> {
         let scope_storage = std::pin::pin!(unsafe { v8::CallbackScope::new(context) });
@@ -129,56 +357,268 @@ impl JsRuntime {
         let specifier_str = specifier.to_rust_string_lossy(scope);
         let referrer_hash = referrer.get_identity_hash();
 
-        println!("Module resolver called:");
-        println!("  Specifier: {}", specifier_str);
-        println!("  Referrer hash: {}", referrer_hash);
-
-        let loader = crate::modules::FsModuleLoader::global();
-
-        let base_path = {
-            let loader_guard = loader.lock().unwrap();
-            loader_guard.get_path_by_hash(referrer_hash).cloned()
-        };
+        let base_path = scope
+            .get_slot::<ModuleMap>()
+            .and_then(|module_map| module_map.get_path_by_hash(referrer_hash).cloned());
 
         let base_path = match base_path {
             Some(path) => path,
             None => {
-                println!("  -> Could not find referrer path");
+                Self::throw_error(scope, &format!("Cannot resolve '{}': unknown referrer module", specifier_str));
                 return None;
             }
         };
 
-        println!("  Referrer path: {}", base_path);
+        // Static imports list each attribute as key, value and source offset
+        let type_attribute = Self::type_attribute(scope, import_attributes, 3);
+        Self::load_module(scope, &base_path, &specifier_str, type_attribute.as_deref())
+    }
 
-        let mut resolved_path = crate::modules::FsModuleLoader::resolve_path(&base_path, &specifier_str)?;
+    /// Finds the `type` import attribute in `attributes`, a flat list of entries
+    /// `stride` items long that each start with a key and a value.
+    pub(crate) fn type_attribute(
+        scope: &mut v8::PinScope,
+        attributes: v8::Local<v8::FixedArray>,
+        stride: usize,
+    ) -> Option<String> {
+        (0..attributes.length()).step_by(stride).find_map(|i| {
+            let key = v8::Local::<v8::String>::try_from(attributes.get(scope, i)?).ok()?;
+            if key.to_rust_string_lossy(scope) != "type" {
+                return None;
+            }
+            let value = v8::Local::<v8::String>::try_from(attributes.get(scope, i + 1)?).ok()?;
+            Some(value.to_rust_string_lossy(scope))
+        })
+    }
 
-        if !std::path::Path::new(&resolved_path).exists() {
-            resolved_path = format!("{}.js", resolved_path);
+    /// Resolves `specifier` against the module or script at `base_path` to the path of the
+    /// module it refers to, through the runtime's import map if it has one. Shared by
+    /// static imports, `import()` and `import.meta.resolve()`.
+    pub(crate) fn resolve_module(scope: &mut v8::PinScope, base_path: &str, specifier: &str) -> Result<String, String> {
+        if builtin_module_internal(specifier).is_some() {
+            return Ok(specifier.to_string());
+        }
+        match scope.get_slot::<ModuleMap>() {
+            Some(module_map) => module_map.loader.resolve(specifier, base_path),
+            None => crate::modules::FsModuleLoader::resolve_path(base_path, specifier).map_err(|e| e.to_string()),
         }
+    }
 
-        println!("  Resolved path: {}", resolved_path);
+    /// Resolves `specifier` against the module or script at `base_path` and returns the
+    /// module, compiling it on first use. `type_attribute` is the `type` import attribute,
+    /// which decides how the file is loaded. Throws a JS error if the module cannot be
+    /// found or read, or the type is not supported.
+    pub(crate) fn load_module<'s>(
+        scope: &mut v8::PinScope<'s, '_>,
+        base_path: &str,
+        specifier_str: &str,
+        type_attribute: Option<&str>,
+    ) -> Option<v8::Local<'s, v8::Module>> {
+        let _span = tracing::debug_span!(target: "toyjs::modules", "load_module", specifier = specifier_str, referrer = base_path).entered();
+        let resolved = Self::resolve_module(scope, base_path, specifier_str).and_then(|path| {
+            let module_type = ModuleType::from_attribute(&path, type_attribute)?;
+            Ok((path, module_type))
+        });
+        let (resolved_path, module_type) = match resolved {
+            Ok(resolved) => resolved,
+            Err(message) => {
+                tracing::debug!(target: "toyjs::modules", error = %message, "resolution failed");
+                Self::throw_error(scope, &message);
+                return None;
+            }
+        };
 
-        {
-            let loader_guard = loader.lock().unwrap();
-            if let Some(global_module) = loader_guard.get_module(&resolved_path) {
-                println!("  -> Returning cached module");
-                return Some(v8::Local::new(scope, global_module));
+        tracing::debug!(target: "toyjs::modules", path = %resolved_path, ?module_type, "resolved");
+
+        let cached = scope
+            .get_slot::<ModuleMap>()
+            .and_then(|module_map| module_map.get_module(&resolved_path, module_type).cloned());
+        if let Some(global_module) = cached {
+            tracing::trace!(target: "toyjs::modules", "already loaded");
+            return Some(v8::Local::new(scope, global_module));
+        }
+
+        if let Some(internal) = builtin_module_internal(&resolved_path) {
+            let module = Self::builtin_module(scope, &resolved_path, internal)?;
+            let global_module = v8::Global::new(scope, module);
+            if let Some(module_map) = scope.get_slot_mut::<ModuleMap>() {
+                module_map.store_module(resolved_path, module_type, global_module, module.get_identity_hash());
             }
+            return Some(module);
         }
 
-        println!("  -> Loading module from file");
-        let code = match std::fs::read_to_string(&resolved_path) {
-            Ok(code) => code,
-            Err(e) => {
-                println!("  -> Failed to read file: {}", e);
+        let (module_path, code) = match Self::read_module_source(scope, &resolved_path) {
+            Ok(source) => source,
+            Err(message) => {
+                tracing::debug!(target: "toyjs::modules", error = %message, "loading failed");
+                Self::throw_error(scope, &message);
                 return None;
             }
         };
 
-        let source_str = v8::String::new(scope, &code)?;
+        // A redirect can end at a module that is already loaded under its final URL
+        let redirected = (module_path != resolved_path)
+            .then(|| scope.get_slot::<ModuleMap>()?.get_module(&module_path, module_type).cloned())
+            .flatten();
+        if let Some(global_module) = redirected {
+            if let Some(module_map) = scope.get_slot_mut::<ModuleMap>() {
+                module_map.modules.insert((resolved_path, module_type), global_module.clone());
+            }
+            return Some(v8::Local::new(scope, global_module));
+        }
+
+        let module = match module_type {
+            ModuleType::JavaScript => {
+                let code = match crate::modules::strip_if_typescript(&module_path, &code) {
+                    Ok(code) => code,
+                    Err(e) => {
+                        let message = format!("{} in '{}'", e, module_path);
+                        let message = v8::String::new(scope, &message).unwrap();
+                        let exception = v8::Exception::syntax_error(scope, message);
+                        scope.throw_exception(exception);
+                        return None;
+                    }
+                };
+                Self::compile_module(scope, &module_path, &module_path, &code)?
+            }
+            ModuleType::Json => Self::json_module(scope, &module_path, &code)?,
+        };
+        tracing::debug!(target: "toyjs::modules", path = %module_path, "compiled");
+        let module_hash = module.get_identity_hash();
+
+        // Store in the runtime's module map, under the requested URL too if it redirected.
+        // The final one is stored last, so relative imports resolve against it.
+        let global_module = v8::Global::new(scope, module);
+        if let Some(module_map) = scope.get_slot_mut::<ModuleMap>() {
+            if module_path != resolved_path {
+                module_map.store_module(resolved_path, module_type, global_module.clone(), module_hash);
+            }
+            module_map.store_module(module_path, module_type, global_module, module_hash);
+        }
+
+        Some(module)
+    }
+
+    /// Loads the source of a resolved module through the runtime's `ModuleLoader`.
+    /// Returns the name the source was finally loaded under, which differs from `path`
+    /// when a download was redirected.
+    fn read_module_source(scope: &mut v8::PinScope, path: &str) -> Result<(String, String), String> {
+        tracing::trace!(target: "toyjs::modules", path, "loading source");
+        let source = match scope.get_slot::<ModuleMap>() {
+            Some(module_map) => module_map.loader.load(path),
+            None => crate::modules::FsModuleLoader::new().load(path),
+        }?;
+        Ok((source.name, source.code))
+    }
+
+    /// Parses a JSON file into a synthetic module whose default export is the value.
+    /// Parsing happens here, so invalid JSON fails the import with a `SyntaxError`.
+    fn json_module<'s>(
+        scope: &mut v8::PinScope<'s, '_>,
+        path: &str,
+        code: &str,
+    ) -> Option<v8::Local<'s, v8::Module>> {
+        let json = v8::String::new(scope, code)?;
+        let value = v8::json::parse(scope, json)?;
+
+        let name = v8::String::new(scope, path)?;
+        let default_export = v8::String::new(scope, "default")?;
+        let module = v8::Module::create_synthetic_module(
+            scope,
+            name,
+            &[default_export],
+            Self::json_module_evaluation_steps,
+        );
+
+        let value = v8::Global::new(scope, value);
+        if let Some(module_map) = scope.get_slot_mut::<ModuleMap>() {
+            module_map.json_values.insert(module.get_identity_hash(), value);
+        }
+        Some(module)
+    }
+
+    fn json_module_evaluation_steps<'a>(
+        context: v8::Local<'a, v8::Context>,
+        module: v8::Local<'a, v8::Module>,
+    ) -> Option<v8::Local<'a, v8::Value>> {
+        let scope_storage = std::pin::pin!(unsafe { v8::CallbackScope::new(context) });
+        let scope = &mut scope_storage.init();
+
+        let value = scope
+            .get_slot_mut::<ModuleMap>()
+            .and_then(|module_map| module_map.json_values.remove(&module.get_identity_hash()))?;
+        let value = v8::Local::new(scope, value);
+        let default_export = v8::String::new(scope, "default")?;
+        module.set_synthetic_module_export(scope, default_export, value)?;
+
+        // With top-level await, evaluation steps return a promise
+        let resolver = v8::PromiseResolver::new(scope)?;
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        Some(resolver.get_promise(scope).into())
+    }
+
+    /// Creates a synthetic module exporting each property of the internals value
+    /// `internal`. Exports are filled in when the module is evaluated.
+    fn builtin_module<'s>(
+        scope: &mut v8::PinScope<'s, '_>,
+        specifier: &str,
+        internal: &str,
+    ) -> Option<v8::Local<'s, v8::Module>> {
+        let exports = internals::get(scope, internal)
+            .and_then(|value| v8::Local::<v8::Object>::try_from(value).ok())?;
+        let names = exports.get_own_property_names(scope, Default::default())?;
+        let export_names: Vec<_> = (0..names.length())
+            .filter_map(|i| names.get_index(scope, i)?.to_string(scope))
+            .collect();
+
+        let name = v8::String::new(scope, specifier)?;
+        Some(v8::Module::create_synthetic_module(
+            scope,
+            name,
+            &export_names,
+            Self::builtin_module_evaluation_steps,
+        ))
+    }
+
+    fn builtin_module_evaluation_steps<'a>(
+        context: v8::Local<'a, v8::Context>,
+        module: v8::Local<'a, v8::Module>,
+    ) -> Option<v8::Local<'a, v8::Value>> {
+        let scope_storage = std::pin::pin!(unsafe { v8::CallbackScope::new(context) });
+        let scope = &mut scope_storage.init();
+
+        let specifier = scope
+            .get_slot::<ModuleMap>()
+            .and_then(|module_map| module_map.get_path_by_hash(module.get_identity_hash()).cloned())?;
+        let exports = internals::get(scope, builtin_module_internal(&specifier)?)
+            .and_then(|value| v8::Local::<v8::Object>::try_from(value).ok())?;
+        let names = exports.get_own_property_names(scope, Default::default())?;
+        for i in 0..names.length() {
+            let name = names.get_index(scope, i)?.to_string(scope)?;
+            let value = exports.get(scope, name.into())?;
+            module.set_synthetic_module_export(scope, name, value)?;
+        }
+
+        let resolver = v8::PromiseResolver::new(scope)?;
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        Some(resolver.get_promise(scope).into())
+    }
+
+    /// Compiles module source, consuming the code cache entry for `path` if there is
+    /// one. Fresh compilations, including ones whose cached data V8 rejected, write a new
+    /// entry back.
+    fn compile_module<'s>(
+        scope: &mut v8::PinScope<'s, '_>,
+        resource_name: &str,
+        path: &str,
+        code: &str,
+    ) -> Option<v8::Local<'s, v8::Module>> {
+        let source_str = v8::String::new(scope, code)?;
         let origin = v8::ScriptOrigin::new(
             scope,
-            v8::String::new(scope, &resolved_path)?.into(),
+            v8::String::new(scope, resource_name)?.into(),
             0,
             0,
             false,
@@ -189,33 +629,100 @@ impl JsRuntime {
             true, // is_module
             None,
         );
-        let mut source = v8::script_compiler::Source::new(source_str, Some(&origin));
 
-        let module = v8::script_compiler::compile_module(scope, &mut source)?;
-        let module_hash = module.get_identity_hash();
+        let cached_data = scope
+            .get_slot::<ModuleMap>()
+            .and_then(|module_map| module_map.code_cache.as_ref())
+            .and_then(|cache| cache.get(path, code));
 
-        // Store in global cache
-        let global_module = v8::Global::new(scope, module);
+        let (module, rejected) = match &cached_data {
+            Some(data) => {
+                let cached_data = v8::script_compiler::CachedData::new(data);
+                let mut source =
+                    v8::script_compiler::Source::new_with_cached_data(source_str, Some(&origin), cached_data);
+                let module = v8::script_compiler::compile_module2(
+                    scope,
+                    &mut source,
+                    v8::script_compiler::CompileOptions::ConsumeCodeCache,
+                    v8::script_compiler::NoCacheReason::NoReason,
+                )?;
+                let rejected = source.get_cached_data().is_some_and(|data| data.rejected());
+                (module, rejected)
+            }
+            None => {
+                let mut source = v8::script_compiler::Source::new(source_str, Some(&origin));
+                (v8::script_compiler::compile_module(scope, &mut source)?, false)
+            }
+        };
+
+        let Some(cache) = scope
+            .get_slot_mut::<ModuleMap>()
+            .and_then(|module_map| module_map.code_cache.as_mut())
+        else {
+            return Some(module);
+        };
+        cache.record(cached_data.is_some(), rejected);
+        if cached_data.is_some() && !rejected {
+            tracing::debug!(target: "toyjs::modules", path, "loaded from code cache");
+            return Some(module);
+        }
+        if rejected {
+            tracing::debug!(target: "toyjs::modules", path, "code cache rejected, recompiled");
+        }
+
+        let code_cache = module.get_unbound_module_script(scope).create_code_cache();
+        if let Some(code_cache) = code_cache
+            && let Some(cache) = scope
+                .get_slot::<ModuleMap>()
+                .and_then(|module_map| module_map.code_cache.as_ref())
+            && let Err(e) = cache.set(path, code, &code_cache)
         {
-            let mut loader_guard = loader.lock().unwrap();
-            loader_guard.store_module(resolved_path.clone(), global_module, module_hash);
+            tracing::warn!(target: "toyjs::modules", path, error = %e, "failed to write code cache");
         }
 
-        println!("  -> Compiled and cached module");
         Some(module)
     }
 
-    pub fn execute_script_module(&mut self, code: &str) -> String {
-        self.execute_module_inner(code, "main.js")
+    /// How module compilations used the code cache so far, if one is configured.
+    pub fn code_cache_stats(&mut self) -> Option<crate::modules::CodeCacheStats> {
+        self.isolate
+            .get_slot::<ModuleMap>()
+            .and_then(|module_map| module_map.code_cache.as_ref())
+            .map(|cache| cache.stats())
+    }
+
+    pub async fn execute_script_module(&mut self, code: &str) -> Result<String, JsError> {
+        let name = std::env::current_dir().unwrap_or_default().join("main.js");
+        let promise = self.execute_module_inner(code, "main.js", &name.to_string_lossy())?;
+        self.resolve_promise(promise).await
     }
 
-    pub fn execute_module(&mut self, path: &std::path::Path) -> anyhow::Result<String> {
-        let code = std::fs::read_to_string(path)?;
+    /// Loads the module `path` through the runtime's `ModuleLoader` and runs it. With a
+    /// custom loader, `path` is the module's name in that loader.
+    pub async fn execute_module(&mut self, path: &std::path::Path) -> Result<String, JsError> {
         let path_str = path.to_str().unwrap_or("main.js");
-        Ok(self.execute_module_inner(&code, path_str))
+        let source = match self.isolate.get_slot::<ModuleMap>() {
+            Some(module_map) => module_map.loader.load(path_str),
+            None => crate::modules::FsModuleLoader::new().load(path_str),
+        }
+        .map_err(JsError::new)?;
+        let promise = self.execute_module_inner(&source.code, &source.name, &source.name)?;
+        self.resolve_promise(promise).await
     }
 
-    fn execute_module_inner(&mut self, code: &str, filename: &str) -> String {
+    /// Compiles, instantiates and evaluates a module, returning the promise produced by
+    /// evaluation. With top-level await it only settles once the event loop has run.
+    /// `name` is the module's name in the loader, which its imports resolve against, and
+    /// `filename` is what stack traces show.
+    fn execute_module_inner(
+        &mut self,
+        code: &str,
+        filename: &str,
+        name: &str,
+    ) -> Result<v8::Global<v8::Promise>, JsError> {
+        let limits = &self.limits;
+        let _budget = limits.start_budget();
+        let _span = tracing::debug_span!(target: "toyjs::modules", "execute_module", module = filename).entered();
         let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut self.isolate));
         let scope = &mut handle_scope.init();
         let context = v8::Local::new(scope, &self.context);
@@ -223,160 +730,316 @@ impl JsRuntime {
         let tc_scope_storage = std::pin::pin!(v8::TryCatch::new(scope));
         let tc_scope = &mut tc_scope_storage.init();
 
-        let source_str = v8::String::new(tc_scope, code).unwrap();
-        let origin = v8::ScriptOrigin::new(
-            tc_scope,
-            v8::String::new(tc_scope, filename).unwrap().into(),
-            0,
-            0,
-            false,
-            123,
-            None,
-            false,
-            false,
-            true, // is_module
-            None, // host_defined_options
-        );
-        let mut source = v8::script_compiler::Source::new(source_str, Some(&origin));
+        // TypeScript is stripped in place, so V8's line numbers match the original file
+        let code = crate::modules::strip_if_typescript(filename, code)
+            .map_err(|e| JsError::syntax_error(e.message, filename, code, e.line, e.column))?;
 
-        println!("Compiling module {}...", filename);
-        let module = match v8::script_compiler::compile_module(tc_scope, &mut source) {
-            Some(module) => {
-                println!("Module compiled successfully");
-                module
-            }
-            None => {
-                let exception = tc_scope.exception().unwrap();
-                let exception_str = exception.to_string(tc_scope).unwrap();
-                return format!("Error: Module compilation failed: {}", exception_str.to_rust_string_lossy(tc_scope));
-            }
+        tracing::debug!(target: "toyjs::modules", path = name, "compiling");
+        let module = match Self::compile_module(tc_scope, filename, name, &code) {
+            Some(module) => module,
+            None => return Err(Self::caught_error(limits, tc_scope, "Module compilation failed")),
         };
 
         let module_hash = module.get_identity_hash();
         let global_module = v8::Global::new(tc_scope, module);
-        {
-            let loader = crate::modules::FsModuleLoader::global();
-            let mut loader_guard = loader.lock().unwrap();
-            
-            let full_path = if std::path::Path::new(filename).is_absolute() {
-                filename.to_string()
-            } else {
-                let cwd = std::env::current_dir().unwrap().to_string_lossy().to_string();
-                format!("{}/{}", cwd, filename)
-            };
-            
-            // Canonicalize the path if possible
-            let stored_path = match std::path::Path::new(&full_path).canonicalize() {
-                Ok(p) => p.to_string_lossy().to_string(),
-                Err(_) => full_path,
-            };
-
-            loader_guard.store_module(stored_path, global_module, module_hash);
+        if let Some(module_map) = tc_scope.get_slot_mut::<ModuleMap>() {
+            module_map.store_module(name.to_string(), ModuleType::JavaScript, global_module, module_hash);
+            // The first module the runtime executes is the one `import.meta.main` is true for
+            module_map.main_module.get_or_insert(module_hash);
         }
 
-        println!("Instantiating module...");
+        tracing::debug!(target: "toyjs::modules", "instantiating");
         let status = module.instantiate_module(tc_scope, Self::module_resolver);
         if status.is_none() {
-            let msg = if tc_scope.has_caught() {
-                let exception = tc_scope.exception().unwrap();
-                let exception_str = exception.to_string(tc_scope).unwrap();
-                format!("Exception: {}", exception_str.to_rust_string_lossy(tc_scope))
-            } else {
-                "Unknown error (no exception caught)".to_string()
-            };
-            return format!("Error: Module instantiation failed - {}", msg);
+            return Err(Self::caught_error(limits, tc_scope, "Module instantiation failed"));
         }
-        println!("Module instantiated successfully");
 
-        println!("Evaluating module...");
+        tracing::debug!(target: "toyjs::modules", "evaluating");
         let result = match module.evaluate(tc_scope) {
-            Some(result) => {
-                println!("Module evaluated successfully");
-                result
-            }
-            None => {
-                let msg = if tc_scope.has_caught() {
-                    let exception = tc_scope.exception().unwrap();
-                    let exception_str = exception.to_string(tc_scope).unwrap();
-                    format!("Exception: {}", exception_str.to_rust_string_lossy(tc_scope))
-                } else {
-                    "Unknown error (no exception caught)".to_string()
-                };
-                return format!("Error: Module execution failed - {}", msg);
+            Some(result) => result,
+            None => return Err(Self::caught_error(limits, tc_scope, "Module execution failed")),
+        };
+
+        // Modules are evaluated asynchronously, so the result is always a promise
+        let promise = match v8::Local::<v8::Promise>::try_from(result) {
+            Ok(promise) => promise,
+            Err(_) => {
+                let resolver = v8::PromiseResolver::new(tc_scope).unwrap();
+                resolver.resolve(tc_scope, result);
+                resolver.get_promise(tc_scope)
             }
         };
 
-        let result = result.to_string(tc_scope).unwrap();
-        result.to_rust_string_lossy(tc_scope)
+        // A rejection here is reported by `resolve_promise`, so keep it out of the
+        // unhandled rejection tracking
+        let noop = v8::Function::new(
+            tc_scope,
+            |_: &mut v8::PinScope, _: v8::FunctionCallbackArguments, _: v8::ReturnValue| {},
+        )
+        .unwrap();
+        promise.catch(tc_scope, noop);
+
+        Ok(v8::Global::new(tc_scope, promise))
+    }
+
+    /// Runs the event loop until `promise` settles. Fails if it rejects, or if it is still
+    /// pending once nothing is left that could settle it.
+    async fn resolve_promise(
+        &mut self,
+        promise: v8::Global<v8::Promise>,
+    ) -> Result<String, JsError> {
+        self.process_callbacks()?;
+        let mut budget = timers::TimerBudget::new("execute_module");
+        loop {
+            if let Some(result) = self.promise_result(&promise) {
+                return result;
+            }
+
+            if !self.has_pending_ops() || !self.poll_pending_ops(&mut budget).await? {
+                return Err(JsError::new("Top-level await promise never resolved"));
+            }
+        }
     }
 
-    pub fn process_callbacks(&mut self) {
+    fn promise_result(
+        &mut self,
+        promise: &v8::Global<v8::Promise>,
+    ) -> Option<Result<String, JsError>> {
+        let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut self.isolate));
+        let scope = &mut handle_scope.init();
+        let context = v8::Local::new(scope, &self.context);
+        let scope = &mut v8::ContextScope::new(scope, context);
+
+        let promise = v8::Local::new(scope, promise);
+        match promise.state() {
+            v8::PromiseState::Pending => None,
+            v8::PromiseState::Fulfilled => {
+                let value = promise.result(scope);
+                Some(Ok(value.to_rust_string_lossy(scope)))
+            }
+            v8::PromiseState::Rejected => {
+                let reason = promise.result(scope);
+                Some(Err(JsError::from_exception(scope, reason)))
+            }
+        }
+    }
+
+    /// Runs every callback that is ready, then the microtask checkpoint. Fails if a
+    /// promise rejection is left unhandled and the policy says to throw.
+    pub fn process_callbacks(&mut self) -> Result<(), JsError> {
+        self.dispatch_callbacks(None)
+    }
+
+    /// Returns true while timers, intervals, async ops or dynamic imports are still outstanding.
+    pub fn has_pending_ops(&self) -> bool {
+        self.isolate
+            .get_slot::<PendingOps>()
+            .is_some_and(|pending| !pending.is_empty())
+            || self.has_pending_imports()
+    }
+
+    fn has_pending_imports(&self) -> bool {
+        self.isolate
+            .get_slot::<bindings::PendingImports>()
+            .is_some_and(|imports| !imports.is_empty())
+    }
+
+    /// Processes callbacks until no timers, intervals or async ops are outstanding.
+    /// `run_event_loop()` must have been started, otherwise pending work never completes.
+    /// On virtual time, timers are fired by skipping the clock ahead once no ops are left,
+    /// giving up with an error after too many of them like `run_all_timers()`.
+    pub async fn run_until_idle(&mut self) -> Result<(), JsError> {
+        self.process_callbacks()?;
+        let mut budget = timers::TimerBudget::new("run_until_idle");
+        while self.has_pending_ops() {
+            if !self.poll_pending_ops(&mut budget).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the virtual clock forward by `ms`, firing every timer that falls due on the
+    /// way in order. Timers scheduled by those callbacks fire too if they are due in time.
+    /// Fails if the runtime was not created with `RuntimeOptions::virtual_time`.
+    pub fn advance_time(&mut self, ms: u64) -> Result<(), JsError> {
+        let target = self.virtual_clock()?.now() + ms;
+        while let Some(msg) = self.virtual_clock()?.pop_due(target) {
+            self.dispatch_callbacks(Some(msg))?;
+        }
+        self.virtual_clock()?.set_now(target);
+        Ok(())
+    }
+
+    /// Fires virtual timers in order, advancing the clock as it goes, until none are left.
+    /// Gives up with an error after 10,000 callbacks, which usually means an interval is
+    /// never cleared.
+    pub fn run_all_timers(&mut self) -> Result<(), JsError> {
+        let mut budget = timers::TimerBudget::new("run_all_timers");
+        while self.fire_next_timer(&mut budget)? {}
+        Ok(())
+    }
+
+    /// Fires the next virtual timer, however far ahead it is. Returns false if none are scheduled.
+    fn fire_next_timer(&mut self, budget: &mut timers::TimerBudget) -> Result<bool, JsError> {
+        match self.virtual_clock()?.pop_due(u64::MAX) {
+            Some(msg) => {
+                budget.spend()?;
+                self.dispatch_callbacks(Some(msg))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn virtual_clock(&mut self) -> Result<&mut timers::VirtualClock, JsError> {
+        self.isolate
+            .get_slot_mut::<timers::VirtualClock>()
+            .ok_or_else(|| JsError::new("Virtual time is not enabled for this runtime"))
+    }
+
+    /// Makes progress on pending work. Queued dynamic imports are loaded first. On virtual
+    /// time, once only timers are left the clock skips ahead to the next one; otherwise
+    /// this waits for the event loop. Returns false if nothing can make progress.
+    async fn poll_pending_ops(&mut self, budget: &mut timers::TimerBudget) -> Result<bool, JsError> {
+        // Imports queued by a script run outside the event loop, e.g. `execute_script`
+        if self.has_pending_imports() {
+            self.process_callbacks()?;
+            return Ok(true);
+        }
+        let only_timers_left = self
+            .isolate
+            .get_slot::<PendingOps>()
+            .is_some_and(|pending| pending.ops.is_empty());
+        if only_timers_left && self.isolate.get_slot::<timers::VirtualClock>().is_some() {
+            return self.fire_next_timer(budget);
+        }
+        self.wait_for_callbacks().await
+    }
+
+    /// Waits for the next callback message and processes it along with anything queued
+    /// behind it. Returns false if the event loop has gone away and nothing will arrive.
+    async fn wait_for_callbacks(&mut self) -> Result<bool, JsError> {
+        match self.callback_rx.recv().await {
+            Some(msg) => {
+                self.dispatch_callbacks(Some(msg))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn dispatch_callbacks(&mut self, first: Option<CallbackMessage>) -> Result<(), JsError> {
+        let policy = self.unhandled_rejection;
+        let limits = &self.limits;
         let scope = std::pin::pin!(v8::HandleScope::new(&mut self.isolate));
         let mut scope = scope.init();
         let context = v8::Local::new(&scope, &self.context);
         let scope = &mut v8::ContextScope::new(&mut scope, context);
+        let tc_scope = std::pin::pin!(v8::TryCatch::new(scope));
+        let scope = &mut tc_scope.init();
+
+        let callback_rx = &mut self.callback_rx;
+        let messages = first
+            .into_iter()
+            .chain(std::iter::from_fn(|| callback_rx.try_recv().ok()));
 
-        while let Ok(msg) = self.callback_rx.try_recv() {
+        for msg in messages {
+            let budget = limits.start_budget();
             match msg {
                 CallbackMessage::ExecuteTimeout(id) | CallbackMessage::ExecuteInterval(id) => {
-                    println!("Executing timer callback: id={}", id);
-                    // Call JavaScript __executeTimer(id)
-                    let global = context.global(scope);
-                    let execute_timer_key = v8::String::new(scope, "__executeTimer").unwrap();
-
-                    if let Some(execute_fn_val) = global.get(scope, execute_timer_key.into()) {
-                        if execute_fn_val.is_function() {
-                            let execute_fn: v8::Local<v8::Function> =
-                                execute_fn_val.try_into().unwrap();
-                            let id_val = v8::Number::new(scope, id as f64);
-                            execute_fn.call(scope, global.into(), &[id_val.into()]);
-                        }
+                    tracing::trace!(id, "running timer callback");
+                    if matches!(msg, CallbackMessage::ExecuteTimeout(_))
+                        && let Some(pending) = scope.get_slot_mut::<PendingOps>()
+                    {
+                        pending.timeouts.remove(&id);
                     }
+
+                    let id_val = v8::Number::new(scope, id as f64);
+                    internals::call(scope, "executeTimer", &[id_val.into()]);
                 }
-                CallbackMessage::FetchSuccess(id, body) => {
-                    println!("Executing fetch success callback: id={}", id);
-                    let global = context.global(scope);
-                    let execute_fn_key = v8::String::new(scope, "__executeFetchSuccess").unwrap();
-
-                    if let Some(execute_fn_val) = global.get(scope, execute_fn_key.into()) {
-                        if execute_fn_val.is_function() {
-                            let execute_fn: v8::Local<v8::Function> =
-                                execute_fn_val.try_into().unwrap();
-                            let id_val = v8::Number::new(scope, id as f64);
-                            let body_val = v8::String::new(scope, &body).unwrap();
-                            execute_fn.call(scope, global.into(), &[id_val.into(), body_val.into()]);
-                        }
+                CallbackMessage::OpResult(id, result) => {
+                    tracing::trace!(id, "settling async op");
+                    if let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+                        pending.ops.remove(&id);
                     }
+
+                    ops::resolve_op(scope, id, result);
                 }
-                CallbackMessage::FetchError(id, error) => {
-                    println!("Executing fetch error callback: id={}, error={}", id, error);
-                    let global = context.global(scope);
-                    let execute_fn_key = v8::String::new(scope, "__executeFetchError").unwrap();
-
-                    if let Some(execute_fn_val) = global.get(scope, execute_fn_key.into()) {
-                        if execute_fn_val.is_function() {
-                            let execute_fn: v8::Local<v8::Function> =
-                                execute_fn_val.try_into().unwrap();
-                            let id_val = v8::Number::new(scope, id as f64);
-                            let error_val = v8::String::new(scope, &error).unwrap();
-                            execute_fn.call(scope, global.into(), &[id_val.into(), error_val.into()]);
-                        }
+            }
+
+            if scope.has_terminated() {
+                return Err(limits.termination_error(scope));
+            }
+            // An exception escaping a callback is uncaught, just like in the browser
+            if let Some(exception) = scope.exception() {
+                return Err(JsError::from_exception(scope, exception));
+            }
+            drop(budget);
+        }
+
+        let budget = limits.start_budget();
+        // Tasks V8 posted for the isolate, such as FinalizationRegistry cleanups
+        let platform = v8::V8::get_current_platform();
+        while v8::Platform::pump_message_loop(&platform, scope, false) {}
+        scope.perform_microtask_checkpoint();
+        // Dynamic imports load once the code that requested them has finished, then
+        // their promises settle in the checkpoint that follows
+        while !scope.has_terminated() && bindings::run_pending_imports(scope) {
+            scope.perform_microtask_checkpoint();
+        }
+        if scope.has_terminated() {
+            let error = limits.termination_error(scope);
+            bindings::reject_interrupted_import(scope, &error);
+            return Err(error);
+        }
+        drop(budget);
+
+        if let Some(exception) = scope.exception() {
+            return Err(JsError::from_exception(scope, exception));
+        }
+
+        let mut rejections = scope
+            .get_slot_mut::<rejections::PendingRejections>()
+            .map(|pending| pending.take())
+            .unwrap_or_default()
+            .into_iter();
+
+        while let Some((promise, reason)) = rejections.next() {
+            let promise = v8::Local::new(scope, promise);
+            let reason = v8::Local::new(scope, reason);
+
+            if policy == UnhandledRejectionPolicy::Dispatch
+                && Self::dispatch_unhandled_rejection(scope, promise, reason)
+            {
+                continue;
+            }
+
+            let rejection = JsError::from_exception(scope, reason);
+            match policy {
+                UnhandledRejectionPolicy::Warn => eprintln!("{}", rejection),
+                UnhandledRejectionPolicy::Throw | UnhandledRejectionPolicy::Dispatch => {
+                    // The rest are reported by later calls rather than dropped
+                    if let Some(pending) = scope.get_slot_mut::<rejections::PendingRejections>() {
+                        pending.requeue(rejections);
                     }
+                    return Err(rejection);
                 }
             }
         }
 
-        let tc_scope = std::pin::pin!(v8::TryCatch::new(scope));
-        let mut tc_scope = tc_scope.init();
-        tc_scope.perform_microtask_checkpoint();
+        Ok(())
+    }
 
-        if let Some(exception) = tc_scope.exception() {
-            let exception_string = exception
-                .to_string(&tc_scope)
-                .map(|s| s.to_rust_string_lossy(&*tc_scope))
-                .unwrap_or_else(|| "Unknown exception".to_string());
-            eprintln!("Exception during microtask processing: {}", exception_string);
-        }
+    /// Fires an `unhandledrejection` event on `globalThis`. Returns true if a listener
+    /// called `preventDefault()`.
+    fn dispatch_unhandled_rejection(
+        scope: &mut v8::PinScope,
+        promise: v8::Local<v8::Promise>,
+        reason: v8::Local<v8::Value>,
+    ) -> bool {
+        internals::call(scope, "dispatchUnhandledRejection", &[promise.into(), reason])
+            .is_some_and(|handled| handled.is_true())
     }
 
     pub fn run_event_loop(&mut self) -> tokio::task::JoinHandle<()> {
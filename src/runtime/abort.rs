@@ -0,0 +1,209 @@
+use super::internals;
+use v8;
+
+/// Installs `DOMException`, `AbortSignal` and `AbortController`. Runs after
+/// `setup_events`, since signals are event targets.
+pub fn setup_abort(scope: &mut v8::PinScope) {
+    let js_code = r#"
+        const { Event, EventTarget } = globalThis;
+
+        // Legacy numeric codes, for the names that have one
+        const legacyCodes = {
+            IndexSizeError: 1,
+            HierarchyRequestError: 3,
+            WrongDocumentError: 4,
+            InvalidCharacterError: 5,
+            NoModificationAllowedError: 7,
+            NotFoundError: 8,
+            NotSupportedError: 9,
+            InvalidStateError: 11,
+            SyntaxError: 12,
+            InvalidModificationError: 13,
+            NamespaceError: 14,
+            InvalidAccessError: 15,
+            TypeMismatchError: 17,
+            SecurityError: 18,
+            NetworkError: 19,
+            AbortError: 20,
+            URLMismatchError: 21,
+            QuotaExceededError: 22,
+            TimeoutError: 23,
+            InvalidNodeTypeError: 24,
+            DataCloneError: 25,
+        };
+
+        class DOMException extends Error {
+            #name;
+
+            constructor(message = "", options = "Error") {
+                if (options !== null && typeof options === "object") {
+                    super(String(message), "cause" in options ? { cause: options.cause } : undefined);
+                    this.#name = options.name === undefined ? "Error" : String(options.name);
+                } else {
+                    super(String(message));
+                    this.#name = String(options);
+                }
+            }
+
+            get name() {
+                return this.#name;
+            }
+
+            get code() {
+                return legacyCodes[this.#name] ?? 0;
+            }
+        }
+
+        globalThis.DOMException = DOMException;
+        // For errors the runtime raises natively, whatever user code does to the global
+        internals.DOMException = DOMException;
+
+        const { SafeSet, SafeWeakRef } = internals.primordials;
+
+        // Signals are only created by controllers and the static factories
+        const constructing = Symbol("constructing");
+        let signalAbort;
+        let addAlgorithm;
+        let isSignal;
+
+        class AbortSignal extends EventTarget {
+            #aborted = false;
+            #reason = undefined;
+            // Steps the runtime runs before the abort event, so fetch and timers react
+            // even if a listener stops the event
+            #algorithms = new SafeSet();
+            // Signals from AbortSignal.any() that follow this one. Held weakly, so a
+            // long-lived signal doesn't keep every combination made from it alive
+            #dependents = new SafeSet();
+            // The signals an AbortSignal.any() signal follows
+            #sources = null;
+
+            constructor(token) {
+                if (token !== constructing) {
+                    throw new TypeError("Illegal constructor");
+                }
+                super();
+                this.onabort = null;
+            }
+
+            static {
+                isSignal = (value) => typeof value === "object" && value !== null && #aborted in value;
+
+                signalAbort = (signal, reason) => {
+                    if (signal.#aborted) {
+                        return;
+                    }
+                    signal.#aborted = true;
+                    signal.#reason = reason === undefined
+                        ? new DOMException("The operation was aborted.", "AbortError")
+                        : reason;
+
+                    const dependents = [...signal.#dependents].map((ref) => ref.deref()).filter(Boolean);
+                    signal.#dependents.clear();
+                    const algorithms = [...signal.#algorithms];
+                    signal.#algorithms.clear();
+                    for (const algorithm of algorithms) {
+                        algorithm();
+                    }
+                    signal.dispatchEvent(new Event("abort"));
+                    for (const dependent of dependents) {
+                        signalAbort(dependent, signal.#reason);
+                    }
+                };
+
+                addAlgorithm = (signal, algorithm) => {
+                    if (!isSignal(signal)) {
+                        throw new TypeError("signal must be an AbortSignal");
+                    }
+                    if (signal.#aborted) {
+                        return null;
+                    }
+                    signal.#algorithms.add(algorithm);
+                    return () => signal.#algorithms.delete(algorithm);
+                };
+            }
+
+            static abort(reason = undefined) {
+                const signal = new AbortSignal(constructing);
+                signalAbort(signal, reason);
+                return signal;
+            }
+
+            // The timer doesn't keep the runtime alive, so a timeout nobody waits for
+            // anymore doesn't delay exit
+            static timeout(milliseconds) {
+                const delay = Number(milliseconds);
+                if (!Number.isFinite(delay) || delay < 0) {
+                    throw new TypeError("timeout must be a non-negative number");
+                }
+                const signal = new AbortSignal(constructing);
+                internals.setUnrefTimeout(() => {
+                    signalAbort(signal, new DOMException("The operation timed out.", "TimeoutError"));
+                }, delay);
+                return signal;
+            }
+
+            static any(signals) {
+                const signal = new AbortSignal(constructing);
+                const list = [...signals];
+                for (const source of list) {
+                    if (!isSignal(source)) {
+                        throw new TypeError("AbortSignal.any requires an iterable of AbortSignals");
+                    }
+                    if (source.#aborted) {
+                        signalAbort(signal, source.#reason);
+                        return signal;
+                    }
+                }
+                // Follow the original signals rather than other combinations, so chains
+                // of any() don't grow
+                signal.#sources = new SafeSet();
+                for (const source of list) {
+                    for (const original of source.#sources ?? [source]) {
+                        if (!signal.#sources.has(original)) {
+                            signal.#sources.add(original);
+                            original.#dependents.add(new SafeWeakRef(signal));
+                        }
+                    }
+                }
+                return signal;
+            }
+
+            get aborted() {
+                return this.#aborted;
+            }
+
+            get reason() {
+                return this.#reason;
+            }
+
+            throwIfAborted() {
+                if (this.#aborted) {
+                    throw this.#reason;
+                }
+            }
+        }
+
+        class AbortController {
+            #signal = new AbortSignal(constructing);
+
+            get signal() {
+                return this.#signal;
+            }
+
+            abort(reason = undefined) {
+                signalAbort(this.#signal, reason);
+            }
+        }
+
+        globalThis.AbortSignal = AbortSignal;
+        globalThis.AbortController = AbortController;
+
+        // Runs `algorithm` when `signal` aborts and returns a function that unregisters
+        // it, or null if the signal has already aborted
+        internals.onAbort = addAlgorithm;
+    "#;
+
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:abort.js", js_code, &[("internals", internals_obj.into())]);
+}
@@ -1,26 +1,253 @@
+use super::{JsError, JsRuntime};
+use crate::modules::{ModuleMap, module_url};
+use std::collections::VecDeque;
+use std::path::Path;
+use v8::MapFnTo;
 
+/// A dynamic import waiting for the event loop to load it.
+struct PendingImport {
+    resolver: v8::Global<v8::PromiseResolver>,
+    base_path: String,
+    specifier: String,
+    type_attribute: Option<String>,
+}
+
+/// Dynamic imports queued by `host_import_module_dynamically_callback`, stored in an
+/// isolate slot until `run_pending_imports` loads them.
+#[derive(Default)]
+pub(crate) struct PendingImports {
+    queue: VecDeque<PendingImport>,
+    /// The import whose evaluation was terminated, rejected by `reject_interrupted_import`
+    /// once the termination has been lifted.
+    interrupted: Option<v8::Global<v8::PromiseResolver>>,
+}
+
+impl PendingImports {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.interrupted.is_none()
+    }
+}
+
+/// Handles `import()`. The specifier is resolved against the importing module or script
+/// the same way static imports are. Nothing is loaded here: the import is queued and
+/// the event loop loads, instantiates and evaluates it on its next turn, so the
+/// importing code always runs to completion first. The returned promise resolves with
+/// the module namespace once evaluation, including any top-level await, finishes, and
+/// rejects with the error otherwise.
 pub fn host_import_module_dynamically_callback<'s>(
-    scope: &mut v8::PinScope,
+    scope: &mut v8::PinScope<'s, '_>,
     _host_defined_options: v8::Local<'s, v8::Data>,
     resource_name: v8::Local<'s, v8::Value>,
     specifier: v8::Local<'s, v8::String>,
-    _import_attributes: v8::Local<'s, v8::FixedArray>,
+    import_attributes: v8::Local<'s, v8::FixedArray>,
 ) -> Option<v8::Local<'s, v8::Promise>> {
-    println!(
-        "Dynamic import requested: resource_name = {:?}, specifier = {:?}",
-        resource_name.to_rust_string_lossy(scope),
-        specifier.to_rust_string_lossy(scope)
-    );
-    None
+    let resolver = v8::PromiseResolver::new(scope)?;
+    let promise = resolver.get_promise(scope);
+
+    // Scripts run through `execute_script` have no resource name, so their imports
+    // resolve against the current directory
+    let base_path = if resource_name.is_string() {
+        resource_name.to_rust_string_lossy(scope)
+    } else {
+        std::env::current_dir().unwrap_or_default().to_string_lossy().to_string()
+    };
+    let specifier = specifier.to_rust_string_lossy(scope);
+    // Unlike static imports, the attributes here are plain key/value pairs
+    let type_attribute = JsRuntime::type_attribute(scope, import_attributes, 2);
+    tracing::debug!(target: "toyjs::modules", specifier, referrer = base_path, "dynamic import");
+
+    let import = PendingImport {
+        resolver: v8::Global::new(scope, resolver),
+        base_path,
+        specifier,
+        type_attribute,
+    };
+    scope.get_slot_mut::<PendingImports>()?.queue.push_back(import);
+    Some(promise)
 }
 
+/// Loads every queued dynamic import and settles its promise. Imports queued while
+/// these modules evaluate wait for the next call. Returns false if the queue was empty.
+/// Stops early if execution was terminated, leaving the termination for the caller:
+/// the imports not started yet go back to the front of the queue, and the one that was
+/// interrupted waits for `reject_interrupted_import`.
+pub(crate) fn run_pending_imports(scope: &mut v8::PinScope) -> bool {
+    let mut imports = scope
+        .get_slot_mut::<PendingImports>()
+        .map(|pending| std::mem::take(&mut pending.queue))
+        .unwrap_or_default();
+    if imports.is_empty() {
+        return false;
+    }
+
+    while let Some(import) = imports.pop_front() {
+        let tc_scope_storage = std::pin::pin!(v8::TryCatch::new(scope));
+        let tc_scope = &mut tc_scope_storage.init();
+        let resolver = v8::Local::new(tc_scope, &import.resolver);
+        match import_module(tc_scope, &import.base_path, &import.specifier, import.type_attribute.as_deref()) {
+            Some(namespace) => {
+                resolver.resolve(tc_scope, namespace.into());
+            }
+            None if tc_scope.has_terminated() => {
+                tc_scope.rethrow();
+                if let Some(pending) = tc_scope.get_slot_mut::<PendingImports>() {
+                    imports.append(&mut pending.queue);
+                    pending.queue = imports;
+                    pending.interrupted = Some(import.resolver);
+                }
+                break;
+            }
+            None => {
+                let exception = match tc_scope.exception() {
+                    Some(exception) => exception,
+                    None => {
+                        let message = format!("Failed to import '{}'", import.specifier);
+                        let message = v8::String::new(tc_scope, &message).unwrap();
+                        v8::Exception::error(tc_scope, message)
+                    }
+                };
+                resolver.reject(tc_scope, exception);
+            }
+        }
+    }
+    true
+}
+
+/// Rejects the dynamic import whose evaluation was terminated with `error`. Called once
+/// the termination has been lifted, since no promise can settle before that.
+pub(crate) fn reject_interrupted_import(scope: &mut v8::PinScope, error: &JsError) {
+    let Some(resolver) = scope
+        .get_slot_mut::<PendingImports>()
+        .and_then(|pending| pending.interrupted.take())
+    else {
+        return;
+    };
+    let resolver = v8::Local::new(scope, resolver);
+    let message = v8::String::new(scope, error.message()).unwrap();
+    let exception = v8::Exception::error(scope, message);
+    resolver.reject(scope, exception);
+}
+
+/// Loads, instantiates and evaluates a module, returning a promise for its namespace.
+fn import_module<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    base_path: &str,
+    specifier: &str,
+    type_attribute: Option<&str>,
+) -> Option<v8::Local<'s, v8::Promise>> {
+    let module = JsRuntime::load_module(scope, base_path, specifier, type_attribute)?;
+    module.instantiate_module(scope, JsRuntime::module_resolver)?;
+    let result = module.evaluate(scope)?;
+
+    let evaluated = match v8::Local::<v8::Promise>::try_from(result) {
+        Ok(promise) => promise,
+        Err(_) => {
+            let resolver = v8::PromiseResolver::new(scope)?;
+            resolver.resolve(scope, result);
+            resolver.get_promise(scope)
+        }
+    };
+
+    // Settle with the namespace rather than the evaluation result. A rejected
+    // evaluation passes through `then` unchanged.
+    let namespace = module.get_module_namespace();
+    let to_namespace = v8::Function::builder(return_data).data(namespace).build(scope)?;
+    evaluated.then(scope, to_namespace)
+}
+
+/// Fills in `import.meta` the first time a module accesses it:
+///
+/// * `url`: the `file://` URL of the module's canonical path.
+/// * `main`: whether this is the entry module of the runtime.
+/// * `filename` and `dirname`: the module's path and directory, for modules read from a file.
+/// * `resolve(specifier)`: the URL an import of `specifier` from this module would load.
+pub extern "C" fn host_initialize_import_meta_object_callback(
+    context: v8::Local<v8::Context>,
+    module: v8::Local<v8::Module>,
+    meta: v8::Local<v8::Object>,
+) {
+    let scope_storage = std::pin::pin!(unsafe { v8::CallbackScope::new(context) });
+    let scope = &mut scope_storage.init();
+
+    let hash = module.get_identity_hash();
+    let Some((path, main)) = scope
+        .get_slot::<ModuleMap>()
+        .and_then(|module_map| Some((module_map.get_path_by_hash(hash)?.clone(), module_map.main_module == Some(hash))))
+    else {
+        return;
+    };
+
+    let set = |scope: &mut v8::PinScope, name: &str, value: v8::Local<v8::Value>| {
+        let key = v8::String::new(scope, name).unwrap();
+        meta.create_data_property(scope, key.into(), value);
+    };
+
+    let url = v8::String::new(scope, &module_url(&path)).unwrap();
+    set(scope, "url", url.into());
+    let main = v8::Boolean::new(scope, main);
+    set(scope, "main", main.into());
+
+    let file = Path::new(&path);
+    if file.is_file() {
+        let filename = v8::String::new(scope, &path).unwrap();
+        set(scope, "filename", filename.into());
+        let dirname = file.parent().unwrap_or(Path::new("/")).to_string_lossy();
+        let dirname = v8::String::new(scope, &dirname).unwrap();
+        set(scope, "dirname", dirname.into());
+    }
+
+    let base_path = v8::String::new(scope, &path).unwrap();
+    let resolve = v8::Function::builder(import_meta_resolve)
+        .data(base_path.into())
+        .build(scope)
+        .unwrap();
+    set(scope, "resolve", resolve.into());
+}
+
+fn import_meta_resolve(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let base_path = args.data().to_rust_string_lossy(scope);
+    let Some(specifier) = args.get(0).to_string(scope) else {
+        return;
+    };
+    let specifier = specifier.to_rust_string_lossy(scope);
+
+    match JsRuntime::resolve_module(scope, &base_path, &specifier) {
+        Ok(path) => {
+            let url = v8::String::new(scope, &module_url(&path)).unwrap();
+            retval.set(url.into());
+        }
+        Err(message) => {
+            let message = v8::String::new(scope, &message).unwrap();
+            let exception = v8::Exception::type_error(scope, message);
+            scope.throw_exception(exception);
+        }
+    }
+}
+
+fn return_data(
+    _scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    retval.set(args.data());
+}
+
+/// `print(...values)`: writes the values, converted to strings and separated by spaces,
+/// to stdout through the same writer as `console.log`.
 pub fn print_cb(
     scope: &mut v8::PinScope,
     args: v8::FunctionCallbackArguments,
     mut _retval: v8::ReturnValue,
 ) {
-    let val = args.get(0).to_string(scope).unwrap();
-    println!("JS: {}", val.to_rust_string_lossy(scope));
+    let text = (0..args.length())
+        .map(|i| args.get(i).to_rust_string_lossy(scope))
+        .collect::<Vec<_>>()
+        .join(" ");
+    super::console::write_line(false, text);
 }
 
 pub fn add_cb(
@@ -32,3 +259,16 @@ pub fn add_cb(
     let b = args.get(1).number_value(scope).unwrap_or(0.0);
     retval.set(v8::Number::new(scope, a + b).into());
 }
+
+/// The native callbacks installed by `JsRuntime::setup_bindings`, for the snapshot's
+/// external references.
+pub fn external_references() -> Vec<v8::ExternalReference> {
+    vec![
+        v8::ExternalReference {
+            function: print_cb.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: add_cb.map_fn_to(),
+        },
+    ]
+}
@@ -0,0 +1,483 @@
+use super::inspect::{InspectOptions, inspect, number_to_string};
+use super::internals;
+use std::io::{IsTerminal, Write};
+use v8::{self, MapFnTo};
+
+pub fn setup_console(scope: &mut v8::PinScope) {
+    internals::set_function(scope, "consoleColors", console_colors);
+    internals::set_function(scope, "consoleFormat", console_format);
+    internals::set_function(scope, "consoleInspect", console_inspect);
+    internals::set_function(scope, "consoleWrite", console_write);
+
+    let js_code = r#"
+        const { SafeMap } = internals.primordials;
+
+        // Console state, private to this closure
+        const counts = new SafeMap();
+        const timers = new SafeMap();
+        let groupIndent = "";
+
+        function write(stderr, text) {
+            if (groupIndent) {
+                text = groupIndent + text.replaceAll("\n", "\n" + groupIndent);
+            }
+            internals.consoleWrite(stderr, text);
+        }
+
+        function format(stderr, args) {
+            return internals.consoleFormat(internals.consoleColors(stderr), ...args);
+        }
+
+        function print(stderr, args) {
+            write(stderr, format(stderr, args));
+        }
+
+        function elapsed(label) {
+            const ms = internals.performanceNow() - timers.get(label);
+            return ms < 1000 ? `${ms.toFixed(3)}ms` : `${(ms / 1000).toFixed(3)}s`;
+        }
+
+        function log(...args) {
+            print(false, args);
+        }
+
+        function error(...args) {
+            print(true, args);
+        }
+
+        function trace(...args) {
+            const holder = {};
+            Error.captureStackTrace(holder, trace);
+            const message = format(true, args);
+            // Drop the "Error" line the stack starts with
+            const frames = holder.stack.split("\n").slice(1).join("\n");
+            write(true, `Trace${message ? `: ${message}` : ""}${frames ? `\n${frames}` : ""}`);
+        }
+
+        function assert(condition, ...args) {
+            if (condition) {
+                return;
+            }
+            if (typeof args[0] === "string") {
+                args[0] = `Assertion failed: ${args[0]}`;
+            } else {
+                args.unshift("Assertion failed");
+            }
+            print(true, args);
+        }
+
+        function dir(value, options) {
+            const depth = options?.depth === undefined ? 2 : options.depth;
+            const colors = options?.colors ?? internals.consoleColors(false);
+            write(false, internals.consoleInspect(value, depth, colors));
+        }
+
+        function table(data, properties) {
+            if (data === null || typeof data !== "object") {
+                return log(data);
+            }
+
+            const cell = (value) => internals.consoleInspect(value, 0, false);
+            const indexHeader = "(index)";
+            const valuesHeader = "Values";
+            const columns = properties ? [...properties].map(String) : [];
+            let hasValues = false;
+
+            const entries = data instanceof Map ? [...data]
+                : data instanceof Set ? [...data].map((value, i) => [i, value])
+                : Object.entries(data);
+            const rows = entries.map(([key, value]) => {
+                const row = new Map([[indexHeader, typeof key === "string" ? key : cell(key)]]);
+                if (value !== null && (typeof value === "object" || typeof value === "function")) {
+                    for (const [column, item] of Object.entries(value)) {
+                        if (properties && !columns.includes(column)) {
+                            continue;
+                        }
+                        if (!columns.includes(column)) {
+                            columns.push(column);
+                        }
+                        row.set(column, cell(item));
+                    }
+                } else {
+                    hasValues = true;
+                    row.set(valuesHeader, cell(value));
+                }
+                return row;
+            });
+
+            const headers = [indexHeader, ...columns, ...(hasValues ? [valuesHeader] : [])];
+            const widths = headers.map((header) =>
+                Math.max(header.length, ...rows.map((row) => (row.get(header) ?? "").length)));
+            const line = (left, middle, right) =>
+                left + widths.map((width) => "─".repeat(width + 2)).join(middle) + right;
+            const render = (cells) =>
+                "│" + cells.map((text, i) => ` ${text.padEnd(widths[i])} `).join("│") + "│";
+
+            write(false, [
+                line("┌", "┬", "┐"),
+                render(headers),
+                line("├", "┼", "┤"),
+                ...rows.map((row) => render(headers.map((header) => row.get(header) ?? ""))),
+                line("└", "┴", "┘"),
+            ].join("\n"));
+        }
+
+        function group(...label) {
+            if (label.length > 0) {
+                print(false, label);
+            }
+            groupIndent += "  ";
+        }
+
+        function groupEnd() {
+            groupIndent = groupIndent.slice(2);
+        }
+
+        function count(label = "default") {
+            label = String(label);
+            const value = (counts.get(label) ?? 0) + 1;
+            counts.set(label, value);
+            print(false, [`${label}: ${value}`]);
+        }
+
+        function countReset(label = "default") {
+            label = String(label);
+            if (!counts.delete(label)) {
+                print(true, [`Warning: Count for '${label}' does not exist`]);
+            }
+        }
+
+        function time(label = "default") {
+            label = String(label);
+            if (timers.has(label)) {
+                print(true, [`Warning: Label '${label}' already exists for console.time()`]);
+                return;
+            }
+            timers.set(label, internals.performanceNow());
+        }
+
+        function timeLog(label = "default", ...data) {
+            label = String(label);
+            if (!timers.has(label)) {
+                print(true, [`Warning: No such label '${label}' for console.timeLog()`]);
+                return;
+            }
+            print(false, [`${label}: ${elapsed(label)}`, ...data]);
+        }
+
+        function timeEnd(label = "default") {
+            label = String(label);
+            if (!timers.has(label)) {
+                print(true, [`Warning: No such label '${label}' for console.timeEnd()`]);
+                return;
+            }
+            print(false, [`${label}: ${elapsed(label)}`]);
+            timers.delete(label);
+        }
+
+        // Replaces V8's own console, whose methods do nothing without an inspector
+        globalThis.console = {
+            log,
+            info: log,
+            debug: log,
+            warn: error,
+            error,
+            trace,
+            assert,
+            dir,
+            dirxml: log,
+            table,
+            group,
+            groupCollapsed: group,
+            groupEnd,
+            count,
+            countReset,
+            time,
+            timeLog,
+            timeEnd,
+        };
+    "#;
+
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:console.js", js_code, &[("internals", internals_obj.into())]);
+}
+
+/// The native callbacks behind `console`, for the snapshot's external references.
+pub(crate) fn external_references() -> Vec<v8::ExternalReference> {
+    [
+        console_colors.map_fn_to(),
+        console_format.map_fn_to(),
+        console_inspect.map_fn_to(),
+        console_write.map_fn_to(),
+    ]
+    .into_iter()
+    .map(|function| v8::ExternalReference { function })
+    .collect()
+}
+
+/// Formats console arguments like Node's `util.format`. A leading string can hold
+/// `%s`, `%d`, `%i`, `%f`, `%o`, `%O`, `%c` and `%%`, each taking the next argument.
+/// The arguments left over are appended with spaces, strings as they are and other
+/// values inspected.
+pub(crate) fn format<'s>(scope: &mut v8::PinScope<'s, '_>, args: &[v8::Local<'s, v8::Value>], colors: bool) -> String {
+    let options = InspectOptions { colors, ..Default::default() };
+    let mut parts = Vec::new();
+    let mut rest = args;
+
+    if let Some((first, remaining)) = args.split_first()
+        && first.is_string()
+    {
+        rest = remaining;
+        let template = first.to_rust_string_lossy(scope);
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            let Some(&specifier) = chars.peek().filter(|_| c == '%') else {
+                text.push(c);
+                continue;
+            };
+            if specifier == '%' {
+                chars.next();
+                text.push('%');
+                continue;
+            }
+            // Unknown specifiers, and specifiers without an argument left, stay as they are
+            let Some((&arg, remaining)) = rest.split_first().filter(|_| "sdifoOc".contains(specifier)) else {
+                text.push('%');
+                continue;
+            };
+            chars.next();
+            rest = remaining;
+            match specifier {
+                's' => text.push_str(&format_string(scope, arg)),
+                'd' | 'f' => text.push_str(&format_number(scope, arg, |n| n)),
+                'i' => text.push_str(&format_number(scope, arg, f64::trunc)),
+                'o' => text.push_str(&inspect(scope, arg, InspectOptions { depth: Some(4), ..options })),
+                'O' => text.push_str(&inspect(scope, arg, options)),
+                // CSS styling, which a terminal has no use for
+                _ => {}
+            }
+        }
+        parts.push(text);
+    }
+
+    for &arg in rest {
+        if arg.is_string() {
+            parts.push(arg.to_rust_string_lossy(scope));
+        } else {
+            parts.push(inspect(scope, arg, options));
+        }
+    }
+    parts.join(" ")
+}
+
+/// `%s`: primitives converted to strings, objects inspected one level deep.
+fn format_string<'s>(scope: &mut v8::PinScope<'s, '_>, value: v8::Local<'s, v8::Value>) -> String {
+    if value.is_big_int() {
+        format!("{}n", value.to_rust_string_lossy(scope))
+    } else if value.is_number() {
+        number_to_string(scope, value.number_value(scope).unwrap_or(f64::NAN))
+    } else if value.is_object() || value.is_symbol() {
+        inspect(scope, value, InspectOptions { depth: Some(0), colors: false })
+    } else {
+        value.to_rust_string_lossy(scope)
+    }
+}
+
+/// `%d`, `%i` and `%f`: the argument as a number, with `convert` applied. Strings are
+/// read up to the first character that can't be part of a number, like `parseFloat`.
+fn format_number<'s>(scope: &mut v8::PinScope<'s, '_>, value: v8::Local<'s, v8::Value>, convert: fn(f64) -> f64) -> String {
+    if value.is_big_int() {
+        return format!("{}n", value.to_rust_string_lossy(scope));
+    }
+    let number = if value.is_symbol() {
+        f64::NAN
+    } else if value.is_string() {
+        parse_leading_number(&value.to_rust_string_lossy(scope))
+    } else {
+        value.number_value(scope).unwrap_or(f64::NAN)
+    };
+    number_to_string(scope, convert(number))
+}
+
+fn parse_leading_number(text: &str) -> f64 {
+    let text = text.trim_start();
+    let unsigned = text.trim_start_matches(['+', '-']);
+    if unsigned.starts_with("Infinity") {
+        return if text.starts_with('-') { f64::NEG_INFINITY } else { f64::INFINITY };
+    }
+
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let digits_start = end;
+    while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+        end += 1;
+    }
+    if bytes.get(end) == Some(&b'.') {
+        end += 1;
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+    }
+    if end == digits_start || &text[digits_start..end] == "." {
+        return f64::NAN;
+    }
+    // Only take an exponent that has digits
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent_end = end + 1;
+        if matches!(bytes.get(exponent_end), Some(b'+' | b'-')) {
+            exponent_end += 1;
+        }
+        let exponent_digits = exponent_end;
+        while bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+            exponent_end += 1;
+        }
+        if exponent_end > exponent_digits {
+            end = exponent_end;
+        }
+    }
+    text[..end].parse().unwrap_or(f64::NAN)
+}
+
+/// Whether output to stderr (or stdout) should be colored: only on a terminal, and
+/// never when `NO_COLOR` is set to anything.
+fn colors_enabled(stderr: bool) -> bool {
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
+    }
+    if stderr {
+        std::io::stderr().is_terminal()
+    } else {
+        std::io::stdout().is_terminal()
+    }
+}
+
+fn console_colors(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let stderr = args.get(0).boolean_value(scope);
+    retval.set(v8::Boolean::new(scope, colors_enabled(stderr)).into());
+}
+
+fn console_format<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    args: v8::FunctionCallbackArguments<'s>,
+    mut retval: v8::ReturnValue,
+) {
+    let colors = args.get(0).boolean_value(scope);
+    let values: Vec<_> = (1..args.length()).map(|i| args.get(i)).collect();
+    let text = format(scope, &values, colors);
+    retval.set(v8::String::new(scope, &text).unwrap().into());
+}
+
+fn console_inspect<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    args: v8::FunctionCallbackArguments<'s>,
+    mut retval: v8::ReturnValue,
+) {
+    // A `null` or infinite depth shows everything
+    let depth = args.get(1);
+    let depth = if depth.is_null() {
+        None
+    } else {
+        let depth = depth.number_value(scope).unwrap_or(2.0);
+        depth.is_finite().then(|| depth.max(0.0) as usize)
+    };
+    let options = InspectOptions {
+        depth,
+        colors: args.get(2).boolean_value(scope),
+    };
+    let text = inspect(scope, args.get(0), options);
+    retval.set(v8::String::new(scope, &text).unwrap().into());
+}
+
+fn console_write(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    let stderr = args.get(0).boolean_value(scope);
+    let text = args.get(1).to_rust_string_lossy(scope);
+    write_line(stderr, text);
+}
+
+/// Writes `text` and a newline to stdout or stderr, for the console and `print`.
+pub(crate) fn write_line(stderr: bool, mut text: String) {
+    text.push('\n');
+    // A closed pipe is not the script's problem, so write errors are ignored
+    let _ = if stderr {
+        std::io::stderr().lock().write_all(text.as_bytes())
+    } else {
+        std::io::stdout().lock().write_all(text.as_bytes())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::JsRuntime;
+
+    /// Formats the comma-separated JavaScript expressions in `args` like `console.log`.
+    fn format_args(args: &str) -> String {
+        let mut runtime = JsRuntime::new();
+        let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut runtime.isolate));
+        let scope = &mut handle_scope.init();
+        let context = v8::Local::new(scope, &runtime.context);
+        let scope = &mut v8::ContextScope::new(scope, context);
+
+        let source = v8::String::new(scope, &format!("[{}]", args)).unwrap();
+        let script = v8::Script::compile(scope, source, None).unwrap();
+        let array = v8::Local::<v8::Array>::try_from(script.run(scope).unwrap()).unwrap();
+        let values: Vec<_> = (0..array.length()).map(|i| array.get_index(scope, i).unwrap()).collect();
+        format(scope, &values, false)
+    }
+
+    #[test]
+    fn substitutes_format_specifiers() {
+        assert_eq!(format_args("'%s is %d years', 'Bob', 42"), "Bob is 42 years");
+        assert_eq!(format_args("'%i|%f', 42.9, '1.5e3x'"), "42|1500");
+        assert_eq!(format_args("'%d %d %d', '42px', -0, 10n"), "42 -0 10n");
+        assert_eq!(format_args("'%d', Symbol('x')"), "NaN");
+        assert_eq!(format_args("'%s %s', 1.5, {a: {b: 1}}"), "1.5 { a: [Object] }");
+        assert_eq!(format_args("'%o', {a: {b: 1}}"), "{ a: { b: 1 } }");
+        assert_eq!(format_args("'%c styled', 'color: red'"), " styled");
+    }
+
+    #[test]
+    fn keeps_unknown_and_unmatched_specifiers() {
+        assert_eq!(format_args("'100%%', 1"), "100% 1");
+        assert_eq!(format_args("'%s %s', 'a'"), "a %s");
+        assert_eq!(format_args("'%x', 1"), "%x 1");
+        assert_eq!(format_args("'trailing %'"), "trailing %");
+    }
+
+    #[test]
+    fn appends_leftover_arguments() {
+        assert_eq!(format_args("1, 'two', {three: 3}"), "1 two { three: 3 }");
+        assert_eq!(format_args("'%s', 'a', 'b'"), "a b");
+        assert_eq!(format_args(""), "");
+    }
+
+    #[test]
+    fn parses_leading_numbers_like_parse_float() {
+        assert_eq!(parse_leading_number("42px"), 42.0);
+        assert_eq!(parse_leading_number("  -3.5e2x"), -350.0);
+        assert_eq!(parse_leading_number("1.2.3"), 1.2);
+        assert_eq!(parse_leading_number(".5"), 0.5);
+        assert_eq!(parse_leading_number("5."), 5.0);
+        assert_eq!(parse_leading_number("0x10"), 0.0);
+        // An exponent without digits isn't part of the number
+        assert_eq!(parse_leading_number("1e"), 1.0);
+        assert_eq!(parse_leading_number("1e+x"), 1.0);
+        assert_eq!(parse_leading_number("+Infinity"), f64::INFINITY);
+        assert_eq!(parse_leading_number("-Infinityx"), f64::NEG_INFINITY);
+        for text in ["", ".", "abc", "--1", "-", "e5"] {
+            assert!(parse_leading_number(text).is_nan(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt;
+use v8;
+
+/// A single frame of a JavaScript stack trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    pub function_name: Option<String>,
+    pub script_name: Option<String>,
+    /// 1-based, like `JsError::line()`.
+    pub line: usize,
+    /// 1-based, like `JsError::column()`.
+    pub column: usize,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = format!(
+            "{}:{}:{}",
+            self.script_name.as_deref().unwrap_or("<anonymous>"),
+            self.line,
+            self.column
+        );
+        match &self.function_name {
+            Some(name) => write!(f, "{} ({})", name, location),
+            None => write!(f, "{}", location),
+        }
+    }
+}
+
+/// Why execution stopped with an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsErrorKind {
+    /// A JavaScript exception, or an error raised by the runtime while loading code.
+    #[default]
+    Exception,
+    /// The isolate reached `RuntimeOptions::max_heap_size` and was terminated.
+    HeapLimitExceeded,
+    /// A script or callback ran past `RuntimeOptions::execution_timeout` and was terminated.
+    TimeLimitExceeded,
+    /// Execution was terminated from outside, e.g. by `Worker.terminate()`.
+    Terminated,
+}
+
+/// An error raised by JavaScript code, or by the runtime while loading or running it.
+///
+/// Errors built from a V8 exception carry its class name, location and stack frames.
+/// Errors raised by the runtime itself (for example an unreadable module file) only
+/// have a name and a message. Line and column numbers are 1-based throughout, in the
+/// error itself and in its stack frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsError(Box<Details>);
+
+// Boxed so `Result<_, JsError>` stays small
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Details {
+    kind: JsErrorKind,
+    message: String,
+    name: String,
+    resource_name: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
+    source_line: Option<String>,
+    frames: Vec<StackFrame>,
+}
+
+impl Details {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            kind: JsErrorKind::Exception,
+            message: message.into(),
+            name: "Error".to_string(),
+            resource_name: None,
+            line: None,
+            column: None,
+            source_line: None,
+            frames: Vec::new(),
+        }
+    }
+}
+
+impl From<Details> for JsError {
+    fn from(details: Details) -> Self {
+        Self(Box::new(details))
+    }
+}
+
+impl JsError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Details::new(message).into()
+    }
+
+    pub fn heap_limit_exceeded(max_heap_size: usize) -> Self {
+        Details {
+            kind: JsErrorKind::HeapLimitExceeded,
+            name: "HeapLimitError".to_string(),
+            ..Details::new(format!(
+                "JavaScript heap limit of {} bytes exceeded",
+                max_heap_size
+            ))
+        }
+        .into()
+    }
+
+    pub fn time_limit_exceeded(timeout: std::time::Duration) -> Self {
+        Details {
+            kind: JsErrorKind::TimeLimitExceeded,
+            name: "TimeoutError".to_string(),
+            ..Details::new(format!("Execution timed out after {}ms", timeout.as_millis()))
+        }
+        .into()
+    }
+
+    pub fn terminated() -> Self {
+        Details {
+            kind: JsErrorKind::Terminated,
+            ..Details::new("Execution terminated")
+        }
+        .into()
+    }
+
+    /// A `SyntaxError` the runtime found in `source` before handing it to V8, e.g.
+    /// while stripping TypeScript types.
+    pub(crate) fn syntax_error(
+        message: impl Into<String>,
+        resource_name: &str,
+        source: &str,
+        line: usize,
+        column: usize,
+    ) -> Self {
+        Details {
+            name: "SyntaxError".to_string(),
+            resource_name: Some(resource_name.to_string()),
+            line: Some(line),
+            column: Some(column),
+            source_line: source.lines().nth(line - 1).map(|line| line.to_string()),
+            ..Details::new(message)
+        }
+        .into()
+    }
+
+    pub fn kind(&self) -> JsErrorKind {
+        self.0.kind
+    }
+
+    /// The exception message, e.g. `"x is not defined"`.
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// The exception class name, e.g. `"ReferenceError"`. Empty when a non-object was thrown.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The script or module the exception was thrown from.
+    pub fn resource_name(&self) -> Option<&str> {
+        self.0.resource_name.as_deref()
+    }
+
+    /// Line number of the throw site.
+    pub fn line(&self) -> Option<usize> {
+        self.0.line
+    }
+
+    /// Column of the throw site.
+    pub fn column(&self) -> Option<usize> {
+        self.0.column
+    }
+
+    /// The source line of the throw site.
+    pub fn source_line(&self) -> Option<&str> {
+        self.0.source_line.as_deref()
+    }
+
+    /// The stack trace, innermost frame first.
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.0.frames
+    }
+
+    pub fn from_exception(scope: &mut v8::PinScope, exception: v8::Local<v8::Value>) -> Self {
+        let (name, message) = match v8::Local::<v8::Object>::try_from(exception) {
+            Ok(obj) => {
+                let name = get_string_property(scope, obj, "name")
+                    .unwrap_or_else(|| obj.get_constructor_name().to_rust_string_lossy(scope));
+                let message = get_string_property(scope, obj, "message")
+                    .unwrap_or_else(|| exception.to_rust_string_lossy(scope));
+                (name, message)
+            }
+            Err(_) => (String::new(), exception.to_rust_string_lossy(scope)),
+        };
+
+        let v8_message = v8::Exception::create_message(scope, exception);
+        let resource_name = v8_message
+            .get_script_resource_name(scope)
+            .filter(|name| !name.is_undefined())
+            .map(|name| name.to_rust_string_lossy(scope));
+        let line = v8_message.get_line_number(scope);
+        let column = line.map(|_| v8_message.get_start_column() + 1);
+        let source_line = v8_message
+            .get_source_line(scope)
+            .map(|line| line.to_rust_string_lossy(scope));
+
+        let stack_trace = v8::Exception::get_stack_trace(scope, exception)
+            .or_else(|| v8_message.get_stack_trace(scope));
+        let frames = match stack_trace {
+            Some(stack_trace) => (0..stack_trace.get_frame_count())
+                .filter_map(|i| stack_trace.get_frame(scope, i))
+                .map(|frame| StackFrame {
+                    function_name: frame
+                        .get_function_name(scope)
+                        .map(|name| name.to_rust_string_lossy(scope))
+                        .filter(|name| !name.is_empty()),
+                    script_name: frame
+                        .get_script_name_or_source_url(scope)
+                        .map(|name| name.to_rust_string_lossy(scope)),
+                    line: frame.get_line_number(),
+                    column: frame.get_column(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Details {
+            kind: JsErrorKind::Exception,
+            message,
+            name,
+            resource_name,
+            line,
+            column,
+            source_line,
+            frames,
+        }
+        .into()
+    }
+}
+
+fn get_string_property(
+    scope: &mut v8::PinScope,
+    obj: v8::Local<v8::Object>,
+    key: &str,
+) -> Option<String> {
+    let key = v8::String::new(scope, key)?;
+    obj.get(scope, key.into())
+        .filter(|value| value.is_string())
+        .map(|value| value.to_rust_string_lossy(scope))
+}
+
+impl fmt::Display for JsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let details = &self.0;
+        if details.kind != JsErrorKind::Exception {
+            write!(f, "{}: {}", details.name, details.message)?;
+        } else if details.name.is_empty() {
+            write!(f, "Uncaught {}", details.message)?;
+        } else {
+            write!(f, "Uncaught {}: {}", details.name, details.message)?;
+        }
+
+        if !details.frames.is_empty() {
+            for frame in &details.frames {
+                write!(f, "\n    at {}", frame)?;
+            }
+        } else if let (Some(resource_name), Some(line)) = (&details.resource_name, details.line) {
+            write!(f, "\n    at {}:{}:{}", resource_name, line, details.column.unwrap_or(1))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for JsError {}
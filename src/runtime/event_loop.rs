@@ -11,13 +11,15 @@ pub async fn run_event_loop(
 ) {
     // Track running tasks so we can cancel them
     let mut running_tasks: HashMap<CallbackId, tokio::task::JoinHandle<()>> = HashMap::new();
+    // Op ids are numbered separately from timer ids, so ops are tracked apart
+    let mut running_ops: HashMap<CallbackId, tokio::task::JoinHandle<()>> = HashMap::new();
 
-    println!("Event loop started");
+    tracing::debug!("event loop started");
 
     while let Some(msg) = scheduler_rx.recv().await {
         match msg {
             SchedulerMessage::ScheduleTimeout(id, delay_ms) => {
-                println!("Scheduling timeout: id={}, delay={}ms", id, delay_ms);
+                tracing::trace!(id, delay_ms, "scheduling timeout");
                 let tx = callback_tx.clone();
                 let handle = tokio::spawn(async move {
                     tokio::time::sleep(Duration::from_millis(delay_ms)).await;
@@ -26,7 +28,7 @@ pub async fn run_event_loop(
                 running_tasks.insert(id, handle);
             }
             SchedulerMessage::ScheduleInterval(id, interval_ms) => {
-                println!("Scheduling interval: id={}, interval={}ms", id, interval_ms);
+                tracing::trace!(id, interval_ms, "scheduling interval");
                 let tx = callback_tx.clone();
                 let handle = tokio::spawn(async move {
                     let mut interval = tokio::time::interval(Duration::from_millis(interval_ms));
@@ -42,39 +44,33 @@ pub async fn run_event_loop(
                 running_tasks.insert(id, handle);
             }
             SchedulerMessage::ClearTimer(id) => {
-                println!("Clearing timer: id={}", id);
+                tracing::trace!(id, "clearing timer");
                 if let Some(handle) = running_tasks.remove(&id) {
                     handle.abort();
                 }
             }
-            SchedulerMessage::Fetch(id, url) => {
-                println!("Fetching: id={}, url={}", id, url);
+            SchedulerMessage::Op(id, future) => {
+                tracing::trace!(id, "running async op");
                 let tx = callback_tx.clone();
-                tokio::spawn(async move {
-                    match reqwest::get(&url).await {
-                        Ok(response) => {
-                            match response.text().await {
-                                Ok(body) => {
-                                    println!("Fetch success: id={}", id);
-                                    let _ = tx.send(CallbackMessage::FetchSuccess(id, body));
-                                }
-                                Err(e) => {
-                                    println!("Fetch error (text): id={}, error={}", id, e);
-                                    let _ = tx.send(CallbackMessage::FetchError(id, e.to_string()));
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            println!("Fetch error (request): id={}, error={}", id, e);
-                            let _ = tx.send(CallbackMessage::FetchError(id, e.to_string()));
-                        }
-                    }
+                let handle = tokio::spawn(async move {
+                    let result = future.await;
+                    tracing::trace!(id, ok = result.is_ok(), "async op completed");
+                    let _ = tx.send(CallbackMessage::OpResult(id, result));
                 });
+                // Forget ops that have finished, so only cancellable ones are kept
+                running_ops.retain(|_, handle| !handle.is_finished());
+                running_ops.insert(id, handle);
+            }
+            SchedulerMessage::CancelOp(id) => {
+                tracing::trace!(id, "cancelling async op");
+                if let Some(handle) = running_ops.remove(&id) {
+                    handle.abort();
+                }
             }
             SchedulerMessage::Shutdown => {
-                println!("Event loop shutting down");
+                tracing::debug!("event loop shutting down");
                 // Abort all running tasks
-                for (_, handle) in running_tasks.drain() {
+                for (_, handle) in running_tasks.drain().chain(running_ops.drain()) {
                     handle.abort();
                 }
                 break;
@@ -82,5 +78,5 @@ pub async fn run_event_loop(
         }
     }
 
-    println!("Event loop stopped");
+    tracing::debug!("event loop stopped");
 }
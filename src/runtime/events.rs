@@ -0,0 +1,137 @@
+use super::internals;
+use v8;
+
+pub fn setup_events(scope: &mut v8::PinScope) {
+    let js_code = r#"
+        const {
+            ArrayPrototypeFindIndex, ArrayPrototypePush, ArrayPrototypeSlice, ArrayPrototypeSome,
+            ArrayPrototypeSplice, SafeMap, SafeWeakMap,
+        } = internals.primordials;
+
+        // Listeners are kept in a private per-target map, created on first use
+        const listenersByTarget = new SafeWeakMap();
+
+        function listenersOf(target) {
+            let listeners = listenersByTarget.get(target);
+            if (!listeners) {
+                listeners = new SafeMap();
+                listenersByTarget.set(target, listeners);
+            }
+            return listeners;
+        }
+
+        globalThis.Event = class Event {
+            constructor(type, init = {}) {
+                this.type = String(type);
+                this.cancelable = !!init.cancelable;
+                this.defaultPrevented = false;
+                this.target = null;
+                this.timeStamp = Date.now();
+            }
+
+            preventDefault() {
+                if (this.cancelable) {
+                    this.defaultPrevented = true;
+                }
+            }
+        };
+
+        globalThis.PromiseRejectionEvent = class PromiseRejectionEvent extends Event {
+            constructor(type, init = {}) {
+                super(type, init);
+                this.promise = init.promise;
+                this.reason = init.reason;
+            }
+        };
+
+        globalThis.MessageEvent = class MessageEvent extends Event {
+            constructor(type, init = {}) {
+                super(type, init);
+                this.data = init.data;
+            }
+        };
+
+        globalThis.ErrorEvent = class ErrorEvent extends Event {
+            constructor(type, init = {}) {
+                super(type, init);
+                this.message = init.message ?? "";
+                this.filename = init.filename ?? "";
+                this.lineno = init.lineno ?? 0;
+                this.colno = init.colno ?? 0;
+                this.error = init.error;
+            }
+        };
+
+        globalThis.EventTarget = class EventTarget {
+            addEventListener(type, listener, options = {}) {
+                if (typeof listener !== "function" && typeof listener?.handleEvent !== "function") {
+                    return;
+                }
+                const listeners = listenersOf(this);
+                if (!listeners.has(type)) {
+                    listeners.set(type, []);
+                }
+                const entries = listeners.get(type);
+                if (!ArrayPrototypeSome(entries, (entry) => entry.listener === listener)) {
+                    ArrayPrototypePush(entries, { listener, once: typeof options === "object" && !!options.once });
+                }
+            }
+
+            removeEventListener(type, listener) {
+                const entries = listenersOf(this).get(type);
+                if (entries) {
+                    const index = ArrayPrototypeFindIndex(entries, (entry) => entry.listener === listener);
+                    if (index !== -1) {
+                        ArrayPrototypeSplice(entries, index, 1);
+                    }
+                }
+            }
+
+            dispatchEvent(event) {
+                event.target = this;
+
+                // `on<type>` handler properties run before registered listeners
+                const handler = this["on" + event.type];
+                if (typeof handler === "function") {
+                    handler.call(this, event);
+                }
+
+                const entries = ArrayPrototypeSlice(listenersOf(this).get(event.type) || []);
+                for (let i = 0; i < entries.length; i++) {
+                    const entry = entries[i];
+                    if (entry.once) {
+                        this.removeEventListener(event.type, entry.listener);
+                    }
+                    if (typeof entry.listener === "function") {
+                        entry.listener.call(this, event);
+                    } else {
+                        entry.listener.handleEvent(event);
+                    }
+                }
+                return !event.defaultPrevented;
+            }
+        };
+
+        // The global object is itself an event target
+        for (const method of ["addEventListener", "removeEventListener", "dispatchEvent"]) {
+            globalThis[method] = EventTarget.prototype[method].bind(globalThis);
+        }
+
+        // Kept for the runtime's own use, so user code replacing the global doesn't break it
+        internals.dispatchGlobalEvent = globalThis.dispatchEvent;
+
+        // Called from Rust for each unhandled rejection; returns true if a listener handled it
+        internals.dispatchUnhandledRejection = function(promise, reason) {
+            const event = new PromiseRejectionEvent("unhandledrejection", {
+                promise,
+                reason,
+                cancelable: true,
+            });
+            internals.dispatchGlobalEvent(event);
+            return event.defaultPrevented;
+        };
+    "#;
+
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:events.js", js_code, &[("internals", internals_obj.into())]);
+}
@@ -1,103 +1,925 @@
-use super::SchedulerMessage;
+use super::ops::{Extension, OpDecl, OpError, OpValue};
+use bytes::Bytes;
+use serde_json::json;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
 use tokio::sync::mpsc;
-use v8;
+use url::Url;
 
+pub fn extension() -> Extension {
+    let state = Arc::new(FetchState::default());
+    let (fetch_state, read_state, write_state, upload_state) = (state.clone(), state.clone(), state.clone(), state.clone());
+    Extension::new("fetch")
+        .op(OpDecl::new_async("op_fetch", move |args| op_fetch(fetch_state.clone(), args)))
+        .op(OpDecl::new_async("op_fetch_read", move |args| op_fetch_read(read_state.clone(), args)))
+        .op(OpDecl::new_async("op_fetch_write", move |args| op_fetch_write(write_state.clone(), args)))
+        .op(OpDecl::new_sync("op_fetch_upload", move |_| op_fetch_upload(&upload_state)))
+        .op(OpDecl::new_sync("op_fetch_close", move |args| op_fetch_close(&state, args)))
+        .op(OpDecl::new_sync("op_url_parse", op_url_parse))
+        .op(OpDecl::new_sync("op_utf8_encode", op_utf8_encode))
+        .op(OpDecl::new_sync("op_utf8_decode", op_utf8_decode))
+        .js(FETCH_JS)
+}
+
+/// The fetch state of one runtime, shared by its ops.
+#[derive(Default)]
 struct FetchState {
-    scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
+    /// reqwest sets the redirect policy per client, so there is one client per
+    /// `RedirectMode`, created on first use.
+    clients: [OnceLock<reqwest::Client>; 3],
+    next_rid: AtomicU32,
+    /// Bodies being streamed in either direction, by the resource id JavaScript holds.
+    resources: Mutex<HashMap<u32, Resource>>,
+}
+
+enum Resource {
+    /// A response whose body hasn't been read to the end. Reads lock it across the
+    /// wait for the next chunk, so closing it only drops it once a pending read is done.
+    Response(Arc<tokio::sync::Mutex<reqwest::Response>>),
+    /// A request body written from a `ReadableStream`. `body` is the reading end until
+    /// `op_fetch` sends it.
+    Upload {
+        chunks: mpsc::Sender<Bytes>,
+        error: Arc<Mutex<Option<String>>>,
+        body: Option<UploadBody>,
+    },
 }
 
-fn get_fetch_state<'a>(scope: &mut v8::PinScope) -> Option<&'a FetchState> {
-    let global = scope.get_current_context().global(scope);
-    let state_key = v8::String::new(scope, "__fetchState").unwrap();
-    let state_val = global.get(scope, state_key.into())?;
+impl FetchState {
+    fn client(&self, redirect: RedirectMode) -> Result<reqwest::Client, OpError> {
+        let slot = &self.clients[redirect as usize];
+        if let Some(client) = slot.get() {
+            return Ok(client.clone());
+        }
+        let client = reqwest::Client::builder()
+            .redirect(redirect.policy())
+            .build()
+            .map_err(|e| OpError::type_error(format!("fetch failed: {}", describe(&e))))?;
+        Ok(slot.get_or_init(|| client).clone())
+    }
 
-    if !state_val.is_external() {
-        return None;
+    fn resources(&self) -> std::sync::MutexGuard<'_, HashMap<u32, Resource>> {
+        self.resources.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    let external: v8::Local<v8::External> = state_val.try_into().ok()?;
-    let state_ptr = external.value() as *const FetchState;
-    Some(unsafe { &*state_ptr })
+    fn insert(&self, resource: Resource) -> u32 {
+        let rid = self.next_rid.fetch_add(1, Ordering::Relaxed);
+        self.resources().insert(rid, resource);
+        rid
+    }
 }
 
-pub fn setup_fetch(
-    scope: &mut v8::PinScope,
-    scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
-) {
-    let global = scope.get_current_context().global(scope);
+/// The `redirect` option of a request.
+#[derive(Debug, Clone, Copy)]
+enum RedirectMode {
+    Follow,
+    /// Fail the request instead of following a redirect.
+    Error,
+    /// Stop at a redirect, which `fetch` exposes as an opaque-redirect response.
+    Manual,
+}
+
+impl RedirectMode {
+    fn parse(mode: Option<&str>) -> Result<Self, OpError> {
+        match mode {
+            None | Some("follow") => Ok(Self::Follow),
+            Some("error") => Ok(Self::Error),
+            Some("manual") => Ok(Self::Manual),
+            Some(other) => Err(OpError::type_error(format!("Invalid redirect mode: '{}'", other))),
+        }
+    }
 
-    let state = FetchState { scheduler_tx };
-    let state_ptr = Box::into_raw(Box::new(state)) as *mut std::ffi::c_void;
-    let external = v8::External::new(scope, state_ptr);
-    let state_key = v8::String::new(scope, "__fetchState").unwrap();
-    global.set(scope, state_key.into(), external.into());
+    fn policy(self) -> reqwest::redirect::Policy {
+        match self {
+            Self::Follow => reqwest::redirect::Policy::limited(20),
+            Self::Error => reqwest::redirect::Policy::custom(|attempt| attempt.error("redirect mode is 'error'")),
+            Self::Manual => reqwest::redirect::Policy::none(),
+        }
+    }
+}
 
-    let native_fetch = v8::Function::new(
-        scope,
-        |scope: &mut v8::PinScope,
-         args: v8::FunctionCallbackArguments,
-         mut _retval: v8::ReturnValue| {
-            if args.length() < 2 {
+/// The reading end of a streamed request body. The channel holds one chunk, so
+/// `op_fetch_write` waits until the connection has taken the previous one.
+struct UploadBody {
+    chunks: mpsc::Receiver<Bytes>,
+    /// Set when the stream errored, so the request fails instead of sending a
+    /// truncated body.
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl http_body::Body for UploadBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>, Self::Error>>> {
+        match std::task::ready!(self.chunks.poll_recv(cx)) {
+            Some(chunk) => Poll::Ready(Some(Ok(http_body::Frame::data(chunk)))),
+            None => match self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                Some(error) => Poll::Ready(Some(Err(std::io::Error::other(error)))),
+                None => Poll::Ready(None),
+            },
+        }
+    }
+}
+
+/// Sends the request described by `args`: `{ url, method, headers, redirect }`, with
+/// `headers` a list of `[name, value]` pairs, followed by the body. The body is `null`,
+/// its bytes, or the resource id of an upload from `op_fetch_upload`. Resolves once the
+/// response head arrives, with its status, headers, final URL and the resource id
+/// `op_fetch_read` reads the body through.
+async fn op_fetch(state: Arc<FetchState>, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let mut args = args.into_iter();
+    let Some(OpValue::Json(request)) = args.next() else {
+        return Err(OpError::type_error("fetch requires a request"));
+    };
+    let body = match args.next() {
+        Some(OpValue::Bytes(bytes)) => Some(reqwest::Body::from(bytes)),
+        Some(OpValue::Json(rid)) if rid.is_u64() => {
+            let upload = match state.resources().get_mut(&(rid.as_u64().unwrap_or_default() as u32)) {
+                Some(Resource::Upload { body, .. }) => body.take(),
+                _ => None,
+            };
+            let upload = upload.ok_or_else(|| OpError::type_error("Request body has already been sent"))?;
+            Some(reqwest::Body::wrap(upload))
+        }
+        _ => None,
+    };
+
+    let url = request["url"]
+        .as_str()
+        .ok_or_else(|| OpError::type_error("fetch requires a URL"))?;
+    let url = Url::parse(url).map_err(|e| OpError::type_error(format!("Invalid URL '{}': {}", url, e)))?;
+    let method = request["method"].as_str().unwrap_or("GET");
+    let method = reqwest::Method::from_bytes(method.as_bytes())
+        .map_err(|_| OpError::type_error(format!("Invalid method: '{}'", method)))?;
+    let redirect = RedirectMode::parse(request["redirect"].as_str())?;
+
+    let mut builder = state.client(redirect)?.request(method.clone(), url.clone());
+    for header in request["headers"].as_array().into_iter().flatten() {
+        if let (Some(name), Some(value)) = (header[0].as_str(), header[1].as_str()) {
+            builder = builder.header(name, value);
+        }
+    }
+    if let Some(body) = body {
+        builder = builder.body(body);
+    }
+
+    tracing::debug!(%method, %url, "fetching");
+    let response = builder
+        .send()
+        .await
+        .map_err(|e| OpError::type_error(format!("fetch failed: {}", describe(&e))))?;
+
+    let status = response.status();
+    let headers: Vec<_> = response
+        .headers()
+        .iter()
+        .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+        .collect();
+    let final_url = response.url().clone();
+    let rid = state.insert(Resource::Response(Arc::new(tokio::sync::Mutex::new(response))));
+    Ok(json!({
+        "status": status.as_u16(),
+        "statusText": status.canonical_reason().unwrap_or(""),
+        "headers": headers,
+        "url": final_url.as_str(),
+        "redirected": final_url != url,
+        "rid": rid,
+    })
+    .into())
+}
+
+/// Reads the next chunk of the response body `rid`, as it arrives from the network.
+/// Resolves with `null` at the end of the body, after which the resource is gone.
+async fn op_fetch_read(state: Arc<FetchState>, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let rid = resource_id(&args)?;
+    let response = match state.resources().get(&rid) {
+        Some(Resource::Response(response)) => response.clone(),
+        _ => return Ok(serde_json::Value::Null.into()),
+    };
+    let chunk = response.lock().await.chunk().await;
+    match chunk {
+        Ok(Some(chunk)) => Ok(chunk.to_vec().into()),
+        Ok(None) => {
+            state.resources().remove(&rid);
+            Ok(serde_json::Value::Null.into())
+        }
+        Err(e) => {
+            state.resources().remove(&rid);
+            Err(OpError::type_error(format!("Failed to read response body: {}", describe(&e))))
+        }
+    }
+}
+
+/// Starts a streamed request body, to pass to `op_fetch` and write with `op_fetch_write`.
+fn op_fetch_upload(state: &FetchState) -> Result<OpValue, OpError> {
+    let (sender, receiver) = mpsc::channel(1);
+    let error = Arc::new(Mutex::new(None));
+    let rid = state.insert(Resource::Upload {
+        chunks: sender,
+        error: error.clone(),
+        body: Some(UploadBody { chunks: receiver, error }),
+    });
+    Ok(json!(rid).into())
+}
+
+/// Writes a chunk of the request body `rid`. Resolves once the connection has room for
+/// it, and rejects once the request is no longer being sent.
+async fn op_fetch_write(state: Arc<FetchState>, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let rid = resource_id(&args)?;
+    let Some(OpValue::Bytes(chunk)) = args.get(1) else {
+        return Err(OpError::type_error("Request body chunks must be Uint8Arrays"));
+    };
+    let sender = match state.resources().get(&rid) {
+        Some(Resource::Upload { chunks, .. }) => chunks.clone(),
+        _ => return Err(OpError::type_error("Request body has been closed")),
+    };
+    sender
+        .send(Bytes::copy_from_slice(chunk))
+        .await
+        .map_err(|_| OpError::type_error("Request body is no longer being sent"))?;
+    Ok(serde_json::Value::Null.into())
+}
+
+/// Drops the resource `rid`: a response body nobody will read, or an upload, which ends
+/// the request body. An error message as the second argument makes the request fail
+/// instead.
+fn op_fetch_close(state: &FetchState, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let rid = resource_id(&args)?;
+    if let Some(Resource::Upload { error, .. }) = state.resources().remove(&rid)
+        && let Some(message) = args.get(1).and_then(OpValue::as_str)
+    {
+        *error.lock().unwrap_or_else(|e| e.into_inner()) = Some(message.to_string());
+    }
+    Ok(serde_json::Value::Null.into())
+}
+
+fn resource_id(args: &[OpValue]) -> Result<u32, OpError> {
+    match args.first() {
+        Some(OpValue::Json(rid)) => rid.as_u64().map(|rid| rid as u32),
+        _ => None,
+    }
+    .ok_or_else(|| OpError::type_error("Invalid resource id"))
+}
+
+/// Parses an absolute URL and returns it serialized, or throws a `TypeError`.
+fn op_url_parse(args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let input = args.first().and_then(OpValue::as_str).unwrap_or_default();
+    Url::parse(input)
+        .map(|url| json!(url.as_str()).into())
+        .map_err(|e| OpError::type_error(format!("Invalid URL '{}': {}", input, e)))
+}
+
+fn op_utf8_encode(args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let text = args.first().and_then(OpValue::as_str).unwrap_or_default();
+    Ok(text.as_bytes().to_vec().into())
+}
+
+/// Decodes UTF-8 the way `Body.text()` does: a byte order mark is dropped and invalid
+/// sequences become U+FFFD.
+fn op_utf8_decode(args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let bytes = match args.first() {
+        Some(OpValue::Bytes(bytes)) => bytes.as_slice(),
+        _ => &[],
+    };
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    Ok(json!(String::from_utf8_lossy(bytes)).into())
+}
+
+/// An error followed by its causes, since reqwest's own message leaves out why a
+/// request failed.
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+const FETCH_JS: &str = r#"
+    const {
+        op_fetch, op_fetch_read, op_fetch_write, op_fetch_upload, op_fetch_close,
+        op_url_parse, op_utf8_encode, op_utf8_decode,
+    } = ops;
+    const { ReadableStream, AbortController, AbortSignal } = globalThis;
+    const { SafeFinalizationRegistry, SafeWeakMap, SafeWeakRef, SafeWeakSet } = internals.primordials;
+
+    // Header names are HTTP tokens, and values can't contain NUL, CR or LF
+    const tokenPattern = /^[!#$%&'*+\-.^_`|~0-9A-Za-z]+$/;
+
+    function normalizeHeaderName(name) {
+        name = String(name);
+        if (!tokenPattern.test(name)) {
+            throw new TypeError(`Invalid header name: '${name}'`);
+        }
+        return name.toLowerCase();
+    }
+
+    function normalizeHeaderValue(value) {
+        value = String(value).replace(/^[\t\n\r ]+|[\t\n\r ]+$/g, "");
+        if (/[\0\r\n]/.test(value)) {
+            throw new TypeError(`Invalid header value: '${value}'`);
+        }
+        return value;
+    }
+
+    // Headers of fetched responses, errors and redirects, which can't be changed
+    const immutableHeaders = new SafeWeakSet();
+
+    class Headers {
+        // [lowercased name, value] pairs, in the order they were added
+        #list = [];
+
+        constructor(init = undefined) {
+            if (init === undefined) {
+                return;
+            }
+            if (init === null || typeof init !== "object") {
+                throw new TypeError("Headers must be initialized with an object or an iterable of pairs");
+            }
+            if (typeof init[Symbol.iterator] === "function") {
+                for (const pair of init) {
+                    const items = [...pair];
+                    if (items.length !== 2) {
+                        throw new TypeError("Each header must be a [name, value] pair");
+                    }
+                    this.append(items[0], items[1]);
+                }
+            } else {
+                for (const name of Object.keys(init)) {
+                    this.append(name, init[name]);
+                }
+            }
+        }
+
+        #checkMutable() {
+            if (immutableHeaders.has(this)) {
+                throw new TypeError("Headers are immutable");
+            }
+        }
+
+        append(name, value) {
+            name = normalizeHeaderName(name);
+            value = normalizeHeaderValue(value);
+            this.#checkMutable();
+            this.#list.push([name, value]);
+        }
+
+        delete(name) {
+            name = normalizeHeaderName(name);
+            this.#checkMutable();
+            this.#list = this.#list.filter(([other]) => other !== name);
+        }
+
+        get(name) {
+            name = normalizeHeaderName(name);
+            const values = this.#list.filter(([other]) => other === name).map(([, value]) => value);
+            return values.length > 0 ? values.join(", ") : null;
+        }
+
+        getSetCookie() {
+            return this.#list.filter(([name]) => name === "set-cookie").map(([, value]) => value);
+        }
+
+        has(name) {
+            name = normalizeHeaderName(name);
+            return this.#list.some(([other]) => other === name);
+        }
+
+        set(name, value) {
+            name = normalizeHeaderName(name);
+            value = normalizeHeaderValue(value);
+            this.#checkMutable();
+            const index = this.#list.findIndex(([other]) => other === name);
+            if (index === -1) {
+                this.#list.push([name, value]);
                 return;
             }
+            this.#list[index] = [name, value];
+            this.#list = this.#list.filter(([other], i) => other !== name || i === index);
+        }
 
-            let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
-            let url = args
-                .get(1)
-                .to_string(scope)
-                .map(|s| s.to_rust_string_lossy(scope))
-                .unwrap_or_default();
+        forEach(callback, thisArg = undefined) {
+            for (const [name, value] of this) {
+                callback.call(thisArg, value, name, this);
+            }
+        }
 
-            if let Some(state) = get_fetch_state(scope) {
-                let _ = state.scheduler_tx.send(SchedulerMessage::Fetch(id, url));
+        // Sorted by name, with the values of repeated headers combined, except for
+        // Set-Cookie whose values can't be
+        *entries() {
+            const names = [...new Set(this.#list.map(([name]) => name))].sort();
+            for (const name of names) {
+                if (name === "set-cookie") {
+                    for (const value of this.getSetCookie()) {
+                        yield [name, value];
+                    }
+                } else {
+                    yield [name, this.get(name)];
+                }
             }
-        },
-    )
-    .unwrap();
-
-    let name = v8::String::new(scope, "__nativeFetch").unwrap();
-    global.set(scope, name.into(), native_fetch.into());
-
-    let js_code = r#"
-        // Fetch state
-        globalThis.__fetchCallbacks = new Map();
-        globalThis.__nextFetchId = 1;
-
-        // Simple fetch implementation that returns a Promise
-        globalThis.fetch = function(url) {
-            return new Promise((resolve, reject) => {
-                const id = globalThis.__nextFetchId++;
-                globalThis.__fetchCallbacks.set(id, { resolve, reject });
-                __nativeFetch(id, url);
-            });
-        };
+        }
 
-        // Success callback called from Rust
-        globalThis.__executeFetchSuccess = function(id, body) {
-            const callbacks = globalThis.__fetchCallbacks.get(id);
-            if (callbacks) {
-                globalThis.__fetchCallbacks.delete(id);
-                // Create a minimal Response-like object
-                callbacks.resolve({
-                    text: () => Promise.resolve(body),
-                    json: () => Promise.resolve(JSON.parse(body)),
-                    ok: true,
-                    status: 200
-                });
+        *keys() {
+            for (const [name] of this.entries()) {
+                yield name;
             }
-        };
+        }
 
-        // Error callback called from Rust
-        globalThis.__executeFetchError = function(id, error) {
-            const callbacks = globalThis.__fetchCallbacks.get(id);
-            if (callbacks) {
-                globalThis.__fetchCallbacks.delete(id);
-                callbacks.reject(new Error(error));
+        *values() {
+            for (const [, value] of this.entries()) {
+                yield value;
             }
+        }
+
+        [Symbol.iterator]() {
+            return this.entries();
+        }
+    }
+
+    // The body of each Request and Response: its stream, or null, and the bytes it was
+    // made from, so a request can send them with a Content-Length instead of streaming
+    const bodies = new SafeWeakMap();
+
+    function extractBody(body) {
+        if (typeof body === "string") {
+            return { bytes: op_utf8_encode(body), type: "text/plain;charset=UTF-8" };
+        }
+        if (body instanceof ArrayBuffer) {
+            return { bytes: new Uint8Array(body.slice(0)), type: null };
+        }
+        if (ArrayBuffer.isView(body)) {
+            const end = body.byteOffset + body.byteLength;
+            return { bytes: new Uint8Array(body.buffer.slice(body.byteOffset, end)), type: null };
+        }
+        if (body instanceof ReadableStream) {
+            if (isUnusable(body)) {
+                throw new TypeError("ReadableStream is locked or has already been read");
+            }
+            return { stream: body, type: null };
+        }
+        return extractBody(String(body));
+    }
+
+    function initBody(target, headers, body) {
+        if (body === undefined || body === null) {
+            bodies.set(target, { stream: null, bytes: null });
+            return;
+        }
+        const { bytes = null, stream = streamOf(bytes), type } = extractBody(body);
+        if (type !== null && !headers.has("content-type")) {
+            headers.set("content-type", type);
+        }
+        bodies.set(target, { stream, bytes });
+    }
+
+    function streamOf(bytes) {
+        return new ReadableStream({
+            start(controller) {
+                controller.enqueue(bytes);
+                controller.close();
+            },
+        });
+    }
+
+    // A body that is dropped without being read to the end or cancelled still holds its
+    // resource and connection, so it is closed once its stream is garbage collected
+    const unreadBodies = new SafeFinalizationRegistry((rid) => op_fetch_close(rid));
+
+    // Fetched bodies are pulled from the network one chunk per read, so nothing is
+    // downloaded ahead of the reader. Aborting the request's signal stops the download
+    // and errors the stream with the signal's reason
+    function networkStream(rid, signal) {
+        // Nothing the signal holds on to may keep the stream alive, or an unread body
+        // could never be collected while a long-lived signal is around
+        let controllerRef;
+        let pendingRead = null;
+        const token = {};
+        const finish = () => {
+            stopWatching?.();
+            unreadBodies.unregister(token);
         };
-    "#;
+        const stream = new ReadableStream({
+            start(controller) {
+                controllerRef = new SafeWeakRef(controller);
+            },
+            async pull(controller) {
+                let chunk;
+                try {
+                    pendingRead = op_fetch_read(rid);
+                    chunk = await pendingRead;
+                } catch (error) {
+                    finish();
+                    throw error;
+                } finally {
+                    pendingRead = null;
+                }
+                if (chunk === null) {
+                    finish();
+                    controller.close();
+                } else {
+                    controller.enqueue(chunk);
+                }
+            },
+            cancel() {
+                finish();
+                op_fetch_close(rid);
+            },
+        }, { highWaterMark: 0 });
+        unreadBodies.register(stream, rid, token);
+        const abort = () => {
+            unreadBodies.unregister(token);
+            if (pendingRead !== null) {
+                internals.cancelOp(pendingRead, signal.reason);
+            }
+            op_fetch_close(rid);
+            controllerRef.deref()?.error(signal.reason);
+        };
+        const stopWatching = internals.onAbort(signal, abort);
+        if (stopWatching === null) {
+            abort();
+        }
+        return stream;
+    }
 
-    let code_str = v8::String::new(scope, js_code).unwrap();
-    let script = v8::Script::compile(scope, code_str, None).unwrap();
-    script.run(scope).unwrap();
-}
+    function isUnusable(stream) {
+        return stream.locked || internals.isReadableStreamDisturbed(stream);
+    }
+
+    async function consumeBody(target) {
+        const { stream } = bodies.get(target);
+        if (stream === null) {
+            return new Uint8Array(0);
+        }
+        if (isUnusable(stream)) {
+            throw new TypeError("Body has already been used");
+        }
+        const reader = stream.getReader();
+        const chunks = [];
+        let length = 0;
+        while (true) {
+            const { value, done } = await reader.read();
+            if (done) {
+                break;
+            }
+            if (!(value instanceof Uint8Array)) {
+                const error = new TypeError("Body chunks must be Uint8Arrays");
+                reader.cancel(error).catch(() => {});
+                throw error;
+            }
+            chunks.push(value);
+            length += value.byteLength;
+        }
+        if (chunks.length === 1) {
+            return chunks[0];
+        }
+        const bytes = new Uint8Array(length);
+        let offset = 0;
+        for (const chunk of chunks) {
+            bytes.set(chunk, offset);
+            offset += chunk.byteLength;
+        }
+        return bytes;
+    }
+
+    function cloneBody(from, to) {
+        const body = bodies.get(from);
+        if (body.stream === null) {
+            bodies.set(to, { stream: null, bytes: null });
+            return;
+        }
+        if (isUnusable(body.stream)) {
+            throw new TypeError("Cannot clone a body that has already been used");
+        }
+        const [stream, copy] = body.stream.tee();
+        body.stream = stream;
+        bodies.set(to, { stream: copy, bytes: body.bytes });
+    }
+
+    // Writes a streamed request body chunk by chunk, each write waiting for the
+    // connection to take the previous chunk
+    async function sendStream(stream, rid) {
+        const reader = stream.getReader();
+        try {
+            while (true) {
+                const { value, done } = await reader.read();
+                if (done) {
+                    break;
+                }
+                if (!(value instanceof Uint8Array)) {
+                    throw new TypeError("Body chunks must be Uint8Arrays");
+                }
+                await op_fetch_write(rid, value);
+            }
+            op_fetch_close(rid);
+        } catch (error) {
+            op_fetch_close(rid, String(error));
+            reader.cancel(error).catch(() => {});
+        }
+    }
+
+    const bodyMethods = {
+        get body() {
+            return bodies.get(this).stream;
+        },
+
+        get bodyUsed() {
+            const { stream } = bodies.get(this);
+            return stream !== null && internals.isReadableStreamDisturbed(stream);
+        },
+
+        async arrayBuffer() {
+            const bytes = await consumeBody(this);
+            return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+        },
+
+        async bytes() {
+            return new Uint8Array(await this.arrayBuffer());
+        },
+
+        async text() {
+            return op_utf8_decode(await consumeBody(this));
+        },
+
+        async json() {
+            return JSON.parse(await this.text());
+        },
+    };
+
+    const normalizedMethods = ["DELETE", "GET", "HEAD", "OPTIONS", "POST", "PUT"];
+    const forbiddenMethods = ["CONNECT", "TRACE", "TRACK"];
+    const redirectModes = ["follow", "error", "manual"];
+
+    class Request {
+        #url;
+        #method;
+        #headers;
+        #redirect;
+        #signal;
+
+        constructor(input, init = {}) {
+            init ??= {};
+            const source = input instanceof Request ? input : null;
+            this.#url = source ? source.url : op_url_parse(String(input));
+
+            let method = init.method !== undefined ? String(init.method) : (source?.method ?? "GET");
+            if (!tokenPattern.test(method)) {
+                throw new TypeError(`Invalid method: '${method}'`);
+            }
+            if (forbiddenMethods.includes(method.toUpperCase())) {
+                throw new TypeError(`Method '${method}' is forbidden`);
+            }
+            if (normalizedMethods.includes(method.toUpperCase())) {
+                method = method.toUpperCase();
+            }
+            this.#method = method;
+
+            const redirect = init.redirect !== undefined ? String(init.redirect) : (source?.redirect ?? "follow");
+            if (!redirectModes.includes(redirect)) {
+                throw new TypeError(`Invalid redirect mode: '${redirect}'`);
+            }
+            this.#redirect = redirect;
+
+            const signal = init.signal !== undefined ? init.signal : (source?.signal ?? null);
+            if (signal !== null && !(signal instanceof AbortSignal)) {
+                throw new TypeError("signal must be an AbortSignal");
+            }
+            this.#signal = signal ?? new AbortController().signal;
+            this.#headers = new Headers(init.headers ?? source?.headers);
+
+            const body = init.body;
+            if (body !== undefined && body !== null && (method === "GET" || method === "HEAD")) {
+                throw new TypeError(`Request with ${method} method cannot have a body`);
+            }
+            if (body === undefined && source) {
+                cloneBody(source, this);
+            } else {
+                initBody(this, this.#headers, body);
+            }
+        }
+
+        get url() {
+            return this.#url;
+        }
+
+        get method() {
+            return this.#method;
+        }
+
+        get headers() {
+            return this.#headers;
+        }
+
+        get redirect() {
+            return this.#redirect;
+        }
+
+        get signal() {
+            return this.#signal;
+        }
+
+        clone() {
+            return new Request(this);
+        }
+    }
+
+    const nullBodyStatuses = [101, 103, 204, 205, 304];
+    const redirectStatuses = [301, 302, 303, 307, 308];
+    let initNetworkResponse;
+
+    class Response {
+        #type = "default";
+        #url = "";
+        #redirected = false;
+        #status;
+        #statusText;
+        #headers;
+
+        constructor(body = null, init = {}) {
+            init ??= {};
+            const status = init.status === undefined ? 200 : Number(init.status);
+            if (!Number.isInteger(status) || status < 200 || status > 599) {
+                throw new RangeError(`Invalid response status: ${init.status}`);
+            }
+            const statusText = init.statusText === undefined ? "" : String(init.statusText);
+            if (/[^\t\x20-\x7e\x80-\xff]/.test(statusText)) {
+                throw new TypeError(`Invalid status text: '${statusText}'`);
+            }
+            if (body !== null && nullBodyStatuses.includes(status)) {
+                throw new TypeError(`Response with status ${status} cannot have a body`);
+            }
+
+            this.#status = status;
+            this.#statusText = statusText;
+            this.#headers = new Headers(init.headers);
+            initBody(this, this.#headers, body);
+        }
+
+        static {
+            initNetworkResponse = (response, head, signal, redirect) => {
+                response.#url = head.url;
+                response.#redirected = head.redirected;
+                // A redirect that wasn't followed is opaque: nothing about it is exposed
+                if (redirect === "manual" && redirectStatuses.includes(head.status)) {
+                    response.#type = "opaqueredirect";
+                    response.#status = 0;
+                    response.#statusText = "";
+                    response.#headers = new Headers();
+                    immutableHeaders.add(response.#headers);
+                    op_fetch_close(head.rid);
+                    bodies.set(response, { stream: null, bytes: null });
+                    return;
+                }
+                response.#type = "basic";
+                response.#status = head.status;
+                response.#statusText = head.statusText;
+                response.#headers = new Headers(head.headers);
+                immutableHeaders.add(response.#headers);
+                if (nullBodyStatuses.includes(head.status)) {
+                    op_fetch_close(head.rid);
+                    bodies.set(response, { stream: null, bytes: null });
+                } else {
+                    bodies.set(response, { stream: networkStream(head.rid, signal), bytes: null });
+                }
+            };
+        }
+
+        static error() {
+            const response = new Response();
+            response.#type = "error";
+            response.#status = 0;
+            immutableHeaders.add(response.#headers);
+            return response;
+        }
+
+        static redirect(url, status = 302) {
+            if (!redirectStatuses.includes(status)) {
+                throw new RangeError(`Invalid redirect status: ${status}`);
+            }
+            const response = new Response(null, { status, headers: { location: op_url_parse(String(url)) } });
+            immutableHeaders.add(response.#headers);
+            return response;
+        }
+
+        static json(data, init = {}) {
+            const text = JSON.stringify(data);
+            if (text === undefined) {
+                throw new TypeError("Value is not JSON serializable");
+            }
+            const headers = new Headers(init?.headers);
+            if (!headers.has("content-type")) {
+                headers.set("content-type", "application/json");
+            }
+            return new Response(text, { ...init, headers });
+        }
+
+        get type() {
+            return this.#type;
+        }
+
+        get url() {
+            return this.#url;
+        }
+
+        get redirected() {
+            return this.#redirected;
+        }
+
+        get status() {
+            return this.#status;
+        }
+
+        get ok() {
+            return this.#status >= 200 && this.#status <= 299;
+        }
+
+        get statusText() {
+            return this.#statusText;
+        }
+
+        get headers() {
+            return this.#headers;
+        }
+
+        clone() {
+            const response = new Response();
+            response.#type = this.#type;
+            response.#url = this.#url;
+            response.#redirected = this.#redirected;
+            response.#status = this.#status;
+            response.#statusText = this.#statusText;
+            response.#headers = new Headers(this.#headers);
+            if (immutableHeaders.has(this.#headers)) {
+                immutableHeaders.add(response.#headers);
+            }
+            cloneBody(this, response);
+            return response;
+        }
+    }
+
+    // Body methods are shared like the spec's Body mixin, and non-enumerable like
+    // class methods
+    for (const { prototype } of [Request, Response]) {
+        for (const [name, descriptor] of Object.entries(Object.getOwnPropertyDescriptors(bodyMethods))) {
+            Object.defineProperty(prototype, name, { ...descriptor, enumerable: false });
+        }
+    }
+
+    globalThis.fetch = async function fetch(input, init = undefined) {
+        const request = new Request(input, init);
+        const { signal } = request;
+        signal.throwIfAborted();
+        const headers = [...request.headers];
+        if (!request.headers.has("accept")) {
+            headers.push(["accept", "*/*"]);
+        }
+        const { stream, bytes } = bodies.get(request);
+        let body = null;
+        if (stream !== null && bytes !== null) {
+            body = await consumeBody(request);
+        } else if (stream !== null) {
+            if (isUnusable(stream)) {
+                throw new TypeError("Body has already been used");
+            }
+            body = op_fetch_upload();
+            sendStream(stream, body);
+        }
+
+        // Aborting drops the request's task on the event loop, which closes the
+        // connection, and rejects with the signal's reason
+        let head;
+        let stopWatching = null;
+        try {
+            signal.throwIfAborted();
+            const pending = op_fetch(
+                { url: request.url, method: request.method, headers, redirect: request.redirect },
+                body,
+            );
+            stopWatching = internals.onAbort(signal, () => internals.cancelOp(pending, signal.reason));
+            head = await pending;
+        } catch (error) {
+            if (typeof body === "number") {
+                op_fetch_close(body);
+            }
+            throw signal.aborted ? signal.reason : error;
+        } finally {
+            stopWatching?.();
+        }
+        const response = new Response();
+        initNetworkResponse(response, head, signal, request.redirect);
+        return response;
+    };
+
+    globalThis.Headers = Headers;
+    globalThis.Request = Request;
+    globalThis.Response = Response;
+"#;
@@ -0,0 +1,558 @@
+use v8;
+
+/// Array, map and set entries shown before the rest are summed up as `... N more items`.
+const MAX_ENTRIES: usize = 100;
+/// Objects whose entries fit in this many columns are printed on one line.
+const LINE_WIDTH: usize = 72;
+
+/// How `inspect` renders a value.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InspectOptions {
+    /// Levels of nested objects to show before eliding them as `[Object]`, or `None`
+    /// to show everything.
+    pub depth: Option<usize>,
+    /// Highlight values with ANSI colors.
+    pub colors: bool,
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        Self { depth: Some(2), colors: false }
+    }
+}
+
+/// Renders `value` for humans, the way Node's `util.inspect` does: strings quoted,
+/// objects with their class name and entries, cycles marked as `[Circular *1]`.
+///
+/// Accessor properties are shown as `[Getter]` rather than called, and proxies as
+/// their target, so inspecting a value runs as little user code as possible.
+pub(crate) fn inspect<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    value: v8::Local<'s, v8::Value>,
+    options: InspectOptions,
+) -> String {
+    let mut inspector = Inspector {
+        options,
+        stack: Vec::new(),
+        circular: Vec::new(),
+    };
+    inspector.value(scope, value, 0)
+}
+
+/// A number the way JavaScript prints it, except that `-0` keeps its sign.
+pub(crate) fn number_to_string(scope: &mut v8::PinScope, number: f64) -> String {
+    if number == 0.0 && number.is_sign_negative() {
+        return "-0".to_string();
+    }
+    v8::Number::new(scope, number).to_rust_string_lossy(scope)
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Number,
+    String,
+    Undefined,
+    Null,
+    /// Functions, accessors, cycles and elided objects.
+    Special,
+    Date,
+    RegExp,
+}
+
+impl Style {
+    /// The ANSI codes turning the style on and off.
+    fn codes(self) -> (u8, u8) {
+        match self {
+            Style::Number => (33, 39),
+            Style::String => (32, 39),
+            Style::Undefined => (90, 39),
+            Style::Null => (1, 22),
+            Style::Special => (36, 39),
+            Style::Date => (35, 39),
+            Style::RegExp => (31, 39),
+        }
+    }
+}
+
+struct Inspector<'s> {
+    options: InspectOptions,
+    /// The objects being rendered, outermost first.
+    stack: Vec<v8::Local<'s, v8::Object>>,
+    /// Objects found to contain themselves, numbered `*1`, `*2`, ... in this order.
+    circular: Vec<v8::Local<'s, v8::Object>>,
+}
+
+impl<'s> Inspector<'s> {
+    fn style(&self, text: &str, style: Style) -> String {
+        if !self.options.colors {
+            return text.to_string();
+        }
+        let (on, off) = style.codes();
+        format!("\x1b[{}m{}\x1b[{}m", on, text, off)
+    }
+
+    /// Renders a value nested `level` objects deep.
+    fn value(&mut self, scope: &mut v8::PinScope<'s, '_>, value: v8::Local<'s, v8::Value>, level: usize) -> String {
+        if value.is_undefined() {
+            return self.style("undefined", Style::Undefined);
+        }
+        if value.is_null() {
+            return self.style("null", Style::Null);
+        }
+        if value.is_boolean() {
+            return self.style(if value.is_true() { "true" } else { "false" }, Style::Number);
+        }
+        if value.is_number() {
+            let number = number_to_string(scope, value.number_value(scope).unwrap_or(f64::NAN));
+            return self.style(&number, Style::Number);
+        }
+        if value.is_big_int() {
+            return self.style(&format!("{}n", value.to_rust_string_lossy(scope)), Style::Number);
+        }
+        if value.is_string() {
+            return self.style(&quote(&value.to_rust_string_lossy(scope)), Style::String);
+        }
+        if let Ok(symbol) = v8::Local::<v8::Symbol>::try_from(value) {
+            return self.style(&symbol_to_string(scope, symbol), Style::String);
+        }
+        match v8::Local::<v8::Object>::try_from(value) {
+            Ok(object) => self.object(scope, object, level),
+            Err(_) => value.to_rust_string_lossy(scope),
+        }
+    }
+
+    fn object(&mut self, scope: &mut v8::PinScope<'s, '_>, object: v8::Local<'s, v8::Object>, level: usize) -> String {
+        if let Some(&seen) = self.stack.iter().find(|seen| seen.strict_equals(object.into())) {
+            let number = match self.circular.iter().position(|other| other.strict_equals(seen.into())) {
+                Some(i) => i + 1,
+                None => {
+                    self.circular.push(seen);
+                    self.circular.len()
+                }
+            };
+            return self.style(&format!("[Circular *{}]", number), Style::Special);
+        }
+
+        let too_deep = self.options.depth.is_some_and(|depth| level > depth);
+        if too_deep && !object.is_function() {
+            let name = if object.is_array() {
+                "Array".to_string()
+            } else {
+                constructor_name(scope, object).unwrap_or_else(|| "Object".to_string())
+            };
+            return self.style(&format!("[{}]", name), Style::Special);
+        }
+
+        self.stack.push(object);
+        let text = self.object_contents(scope, object, level, too_deep);
+        self.stack.pop();
+
+        // Only known once everything inside has been rendered
+        match self.circular.iter().position(|other| other.strict_equals(object.into())) {
+            Some(i) => format!("{} {}", self.style(&format!("<ref *{}>", i + 1), Style::Special), text),
+            None => text,
+        }
+    }
+
+    fn object_contents(
+        &mut self,
+        scope: &mut v8::PinScope<'s, '_>,
+        object: v8::Local<'s, v8::Object>,
+        level: usize,
+        too_deep: bool,
+    ) -> String {
+        if let Ok(proxy) = v8::Local::<v8::Proxy>::try_from(object) {
+            let target = proxy.get_target(scope);
+            return self.value(scope, target, level);
+        }
+
+        if let Ok(function) = v8::Local::<v8::Function>::try_from(object) {
+            let label = self.style(&function_label(scope, function), Style::Special);
+            let entries = if too_deep { Vec::new() } else { self.properties(scope, object, level, false) };
+            return if entries.is_empty() { label } else { self.wrap(&label, "{", "}", entries, level) };
+        }
+
+        if object.is_native_error() {
+            return self.error(scope, object, level);
+        }
+
+        if let Ok(array) = v8::Local::<v8::Array>::try_from(object) {
+            let length = array.length() as usize;
+            let mut entries = self.elements(scope, object, length, level);
+            entries.extend(self.properties(scope, object, level, true));
+            let prefix = match constructor_name(scope, object) {
+                Some(name) if name != "Array" => format!("{}({})", name, length),
+                _ => String::new(),
+            };
+            return self.wrap(&prefix, "[", "]", entries, level);
+        }
+
+        if let Ok(typed_array) = v8::Local::<v8::TypedArray>::try_from(object) {
+            let length = typed_array.length();
+            let entries = self.elements(scope, object, length, level);
+            let name = constructor_name(scope, object).unwrap_or_else(|| "TypedArray".to_string());
+            return self.wrap(&format!("{}({})", name, length), "[", "]", entries, level);
+        }
+
+        if let Ok(map) = v8::Local::<v8::Map>::try_from(object) {
+            let items = map.as_array(scope);
+            let size = items.length() as usize / 2;
+            let mut entries = Vec::new();
+            for i in 0..size.min(MAX_ENTRIES) {
+                let key = array_item(scope, items, 2 * i);
+                let value = array_item(scope, items, 2 * i + 1);
+                let key = self.value(scope, key, level + 1);
+                let value = self.value(scope, value, level + 1);
+                entries.push(format!("{} => {}", key, value));
+            }
+            if size > MAX_ENTRIES {
+                entries.push(more_items(size - MAX_ENTRIES));
+            }
+            entries.extend(self.properties(scope, object, level, false));
+            let name = constructor_name(scope, object).unwrap_or_else(|| "Map".to_string());
+            return self.wrap(&format!("{}({})", name, size), "{", "}", entries, level);
+        }
+
+        if let Ok(set) = v8::Local::<v8::Set>::try_from(object) {
+            let items = set.as_array(scope);
+            let size = items.length() as usize;
+            let mut entries = Vec::new();
+            for i in 0..size.min(MAX_ENTRIES) {
+                let item = array_item(scope, items, i);
+                entries.push(self.value(scope, item, level + 1));
+            }
+            if size > MAX_ENTRIES {
+                entries.push(more_items(size - MAX_ENTRIES));
+            }
+            entries.extend(self.properties(scope, object, level, false));
+            let name = constructor_name(scope, object).unwrap_or_else(|| "Set".to_string());
+            return self.wrap(&format!("{}({})", name, size), "{", "}", entries, level);
+        }
+
+        if let Ok(date) = v8::Local::<v8::Date>::try_from(object) {
+            return self.style(&iso_date(date.value_of()), Style::Date);
+        }
+
+        if object.is_reg_exp() {
+            return self.style(&object.to_rust_string_lossy(scope), Style::RegExp);
+        }
+
+        if let Ok(promise) = v8::Local::<v8::Promise>::try_from(object) {
+            let state = match promise.state() {
+                v8::PromiseState::Pending => self.style("<pending>", Style::Special),
+                v8::PromiseState::Fulfilled => {
+                    let result = promise.result(scope);
+                    self.value(scope, result, level + 1)
+                }
+                v8::PromiseState::Rejected => {
+                    let result = promise.result(scope);
+                    let reason = self.value(scope, result, level + 1);
+                    format!("{} {}", self.style("<rejected>", Style::Special), reason)
+                }
+            };
+            return self.wrap("Promise", "{", "}", vec![state], level);
+        }
+
+        if object.is_weak_map() || object.is_weak_set() {
+            let name = constructor_name(scope, object).unwrap_or_default();
+            return format!("{} {{ {} }}", name, self.style("<items unknown>", Style::Special));
+        }
+
+        if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(object) {
+            let length = self.style(&buffer.byte_length().to_string(), Style::Number);
+            return self.wrap("ArrayBuffer", "{", "}", vec![format!("byteLength: {}", length)], level);
+        }
+
+        let entries = self.properties(scope, object, level, false);
+        let prefix = object_prefix(scope, object);
+        self.wrap(&prefix, "{", "}", entries, level)
+    }
+
+    /// The first `length` indexed elements of an array or typed array.
+    fn elements(
+        &mut self,
+        scope: &mut v8::PinScope<'s, '_>,
+        object: v8::Local<'s, v8::Object>,
+        length: usize,
+        level: usize,
+    ) -> Vec<String> {
+        let mut entries = Vec::new();
+        for i in 0..length.min(MAX_ENTRIES) {
+            let item = object
+                .get_index(scope, i as u32)
+                .unwrap_or_else(|| v8::undefined(scope).into());
+            entries.push(self.value(scope, item, level + 1));
+        }
+        if length > MAX_ENTRIES {
+            entries.push(more_items(length - MAX_ENTRIES));
+        }
+        entries
+    }
+
+    /// `key: value` for each own enumerable property. Arrays list their elements
+    /// separately, so `skip_indices` leaves those out.
+    fn properties(
+        &mut self,
+        scope: &mut v8::PinScope<'s, '_>,
+        object: v8::Local<'s, v8::Object>,
+        level: usize,
+        skip_indices: bool,
+    ) -> Vec<String> {
+        let args = v8::GetPropertyNamesArgs {
+            mode: v8::KeyCollectionMode::OwnOnly,
+            property_filter: v8::PropertyFilter::ONLY_ENUMERABLE,
+            index_filter: if skip_indices {
+                v8::IndexFilter::SkipIndices
+            } else {
+                v8::IndexFilter::IncludeIndices
+            },
+            key_conversion: v8::KeyConversionMode::ConvertToString,
+        };
+        let Some(keys) = object.get_property_names(scope, args) else {
+            return Vec::new();
+        };
+
+        let mut entries = Vec::new();
+        for i in 0..keys.length() as usize {
+            let key = array_item(scope, keys, i);
+            let Ok(name) = v8::Local::<v8::Name>::try_from(key) else {
+                continue;
+            };
+            let key = self.key(scope, key);
+            let value = self.property(scope, object, name, level);
+            entries.push(format!("{}: {}", key, value));
+        }
+        entries
+    }
+
+    fn key(&self, scope: &mut v8::PinScope<'s, '_>, key: v8::Local<'s, v8::Value>) -> String {
+        if let Ok(symbol) = v8::Local::<v8::Symbol>::try_from(key) {
+            return format!("[{}]", self.style(&symbol_to_string(scope, symbol), Style::String));
+        }
+        let key = key.to_rust_string_lossy(scope);
+        if is_identifier(&key) { key } else { self.style(&quote(&key), Style::String) }
+    }
+
+    /// The value of an own property, without calling its getter if it has one.
+    fn property(
+        &mut self,
+        scope: &mut v8::PinScope<'s, '_>,
+        object: v8::Local<'s, v8::Object>,
+        name: v8::Local<'s, v8::Name>,
+        level: usize,
+    ) -> String {
+        let Some(descriptor) = object
+            .get_own_property_descriptor(scope, name)
+            .and_then(|descriptor| v8::Local::<v8::Object>::try_from(descriptor).ok())
+        else {
+            return self.style("undefined", Style::Undefined);
+        };
+
+        let getter = field(scope, descriptor, "get").is_some_and(|get| !get.is_undefined());
+        let setter = field(scope, descriptor, "set").is_some_and(|set| !set.is_undefined());
+        match (getter, setter) {
+            (true, true) => self.style("[Getter/Setter]", Style::Special),
+            (true, false) => self.style("[Getter]", Style::Special),
+            (false, true) => self.style("[Setter]", Style::Special),
+            (false, false) => {
+                let value = field(scope, descriptor, "value").unwrap_or_else(|| v8::undefined(scope).into());
+                self.value(scope, value, level + 1)
+            }
+        }
+    }
+
+    /// The error's stack, followed by any properties added to it.
+    fn error(&mut self, scope: &mut v8::PinScope<'s, '_>, object: v8::Local<'s, v8::Object>, level: usize) -> String {
+        let stack = field(scope, object, "stack")
+            .filter(|stack| stack.is_string())
+            .map(|stack| stack.to_rust_string_lossy(scope));
+        let mut text = stack.unwrap_or_else(|| format!("[{}]", object.to_rust_string_lossy(scope)));
+        if level > 0 {
+            text = text.replace('\n', &format!("\n{}", "  ".repeat(level)));
+        }
+
+        let entries = self.properties(scope, object, level, false);
+        if entries.is_empty() { text } else { self.wrap(&text, "{", "}", entries, level) }
+    }
+
+    /// Puts `entries` between `open` and `close`, on one line if they are short enough
+    /// and one per line otherwise.
+    fn wrap(&self, prefix: &str, open: &str, close: &str, entries: Vec<String>, level: usize) -> String {
+        let start = if prefix.is_empty() { String::new() } else { format!("{} ", prefix) };
+        if entries.is_empty() {
+            return format!("{}{}{}", start, open, close);
+        }
+
+        let width = 2 * level
+            + visible_len(&start)
+            + entries.iter().map(|entry| visible_len(entry) + 2).sum::<usize>()
+            + 2;
+        if width <= LINE_WIDTH && !entries.iter().any(|entry| entry.contains('\n')) {
+            return format!("{}{} {} {}", start, open, entries.join(", "), close);
+        }
+
+        let indent = "  ".repeat(level + 1);
+        format!(
+            "{}{}\n{}{}\n{}{}",
+            start,
+            open,
+            indent,
+            entries.join(&format!(",\n{}", indent)),
+            "  ".repeat(level),
+            close
+        )
+    }
+}
+
+fn field<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    object: v8::Local<'s, v8::Object>,
+    name: &str,
+) -> Option<v8::Local<'s, v8::Value>> {
+    let key = v8::String::new(scope, name)?;
+    object.get(scope, key.into())
+}
+
+fn array_item<'s>(scope: &mut v8::PinScope<'s, '_>, array: v8::Local<'s, v8::Array>, index: usize) -> v8::Local<'s, v8::Value> {
+    array
+        .get_index(scope, index as u32)
+        .unwrap_or_else(|| v8::undefined(scope).into())
+}
+
+fn constructor_name(scope: &mut v8::PinScope, object: v8::Local<v8::Object>) -> Option<String> {
+    let name = object.get_constructor_name().to_rust_string_lossy(scope);
+    (!name.is_empty()).then_some(name)
+}
+
+/// What goes before the braces of a plain object: its class, or a note that it has no
+/// prototype.
+fn object_prefix(scope: &mut v8::PinScope, object: v8::Local<v8::Object>) -> String {
+    if object.is_module_namespace_object() {
+        return "[Module: null prototype]".to_string();
+    }
+    if object.get_prototype(scope).is_none_or(|prototype| prototype.is_null()) {
+        return "[Object: null prototype]".to_string();
+    }
+    match constructor_name(scope, object) {
+        Some(name) if name != "Object" => name,
+        _ => String::new(),
+    }
+}
+
+fn function_label(scope: &mut v8::PinScope, function: v8::Local<v8::Function>) -> String {
+    let name = function.get_name(scope).to_rust_string_lossy(scope);
+    let source = function.to_rust_string_lossy(scope);
+    if source.starts_with("class ") || source.starts_with("class{") {
+        return if name.is_empty() { "[class (anonymous)]".to_string() } else { format!("[class {}]", name) };
+    }
+
+    let kind = match (function.is_async_function(), function.is_generator_function()) {
+        (true, true) => "AsyncGeneratorFunction",
+        (true, false) => "AsyncFunction",
+        (false, true) => "GeneratorFunction",
+        (false, false) => "Function",
+    };
+    if name.is_empty() { format!("[{} (anonymous)]", kind) } else { format!("[{}: {}]", kind, name) }
+}
+
+fn symbol_to_string(scope: &mut v8::PinScope, symbol: v8::Local<v8::Symbol>) -> String {
+    let description = symbol.description(scope);
+    if description.is_undefined() {
+        "Symbol()".to_string()
+    } else {
+        format!("Symbol({})", description.to_rust_string_lossy(scope))
+    }
+}
+
+fn more_items(count: usize) -> String {
+    format!("... {} more item{}", count, if count == 1 { "" } else { "s" })
+}
+
+/// Quotes a string with single quotes, or double quotes or backticks if it contains
+/// single quotes, escaping control characters.
+fn quote(text: &str) -> String {
+    let quote = ['\'', '"', '`']
+        .into_iter()
+        .find(|quote| !text.contains(*quote))
+        .unwrap_or('\'');
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push(quote);
+    for c in text.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\u{8}' => quoted.push_str("\\b"),
+            '\u{c}' => quoted.push_str("\\f"),
+            '\u{b}' => quoted.push_str("\\v"),
+            '\\' => quoted.push_str("\\\\"),
+            c if c == quote => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push(quote);
+    quoted
+}
+
+/// Whether an object key can be shown without quotes.
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The width of `text` on screen, leaving out color codes.
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            len += 1;
+        }
+    }
+    len
+}
+
+/// `Date.prototype.toISOString`, without calling into JavaScript.
+fn iso_date(time: f64) -> String {
+    if !time.is_finite() {
+        return "Invalid Date".to_string();
+    }
+    const DAY_MS: i64 = 86_400_000;
+    let time = time as i64;
+    let (year, month, day) = civil_from_days(time.div_euclid(DAY_MS));
+    let ms = time.rem_euclid(DAY_MS);
+    let year = if (0..=9999).contains(&year) { format!("{:04}", year) } else { format!("{:+07}", year) };
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Converts days since the Unix epoch to a proleptic Gregorian `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,175 @@
+use v8;
+
+/// Private namespace for the runtime's own bindings. The object is passed to each
+/// bootstrap script as its `internals` parameter and otherwise only hangs off the
+/// global object under a private symbol, so user code cannot read, replace or delete
+/// anything on it. Cached in an isolate slot for quick access from Rust.
+struct Internals {
+    object: v8::Global<v8::Object>,
+}
+
+fn private_key<'s>(scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Private> {
+    let name = v8::String::new(scope, "toyjs#internals").unwrap();
+    v8::Private::for_api(scope, Some(name))
+}
+
+pub fn setup_internals(scope: &mut v8::PinScope) {
+    let object = v8::Object::new(scope);
+    let global = scope.get_current_context().global(scope);
+    let key = private_key(scope);
+    global.set_private(scope, key, object.into());
+
+    let object = v8::Global::new(scope, object);
+    scope.set_slot(Internals { object });
+}
+
+/// Captures the built-ins the runtime's own scripts rely on as `internals.primordials`,
+/// before any user code runs. Bootstrap scripts keep their state in these, so a script
+/// that replaces `Map.prototype.get` or `Array.prototype.push` cannot reach or break it.
+///
+/// * `SafeMap`, `SafeSet`, `SafeWeakMap`, `SafeWeakSet`, `SafeWeakRef` and
+///   `SafeFinalizationRegistry`: subclasses with their own frozen copy of the built-in
+///   prototype's methods.
+/// * `ArrayPrototypePush` and friends: array methods that take the array as their first
+///   argument, named after the method.
+pub fn setup_primordials(scope: &mut v8::PinScope) {
+    let js_code = r#"
+        const { defineProperty, getOwnPropertyDescriptor, ownKeys } = Reflect;
+        const { freeze } = Object;
+        const uncurryThis = Function.prototype.bind.bind(Function.prototype.call);
+
+        function makeSafe(unsafe, safe) {
+            for (const key of ownKeys(unsafe.prototype)) {
+                if (getOwnPropertyDescriptor(safe.prototype, key) === undefined) {
+                    defineProperty(safe.prototype, key, getOwnPropertyDescriptor(unsafe.prototype, key));
+                }
+            }
+            freeze(safe.prototype);
+            return freeze(safe);
+        }
+
+        const primordials = {
+            SafeMap: makeSafe(Map, class SafeMap extends Map {}),
+            SafeSet: makeSafe(Set, class SafeSet extends Set {}),
+            SafeWeakMap: makeSafe(WeakMap, class SafeWeakMap extends WeakMap {}),
+            SafeWeakSet: makeSafe(WeakSet, class SafeWeakSet extends WeakSet {}),
+            SafeWeakRef: makeSafe(WeakRef, class SafeWeakRef extends WeakRef {}),
+            SafeFinalizationRegistry: makeSafe(
+                FinalizationRegistry,
+                class SafeFinalizationRegistry extends FinalizationRegistry {},
+            ),
+        };
+        for (const name of ["findIndex", "push", "slice", "some", "splice"]) {
+            const key = `ArrayPrototype${name[0].toUpperCase()}${name.slice(1)}`;
+            primordials[key] = uncurryThis(Array.prototype[name]);
+        }
+        internals.primordials = freeze(primordials);
+    "#;
+
+    let internals_obj = object(scope);
+    run_bootstrap(scope, "ext:primordials.js", js_code, &[("internals", internals_obj.into())]);
+}
+
+/// Picks up the internals object of a context restored from a snapshot.
+pub fn restore_internals(scope: &mut v8::PinScope) {
+    let global = scope.get_current_context().global(scope);
+    let key = private_key(scope);
+    let object = global
+        .get_private(scope, key)
+        .and_then(|value| v8::Local::<v8::Object>::try_from(value).ok())
+        .expect("startup snapshot was not created by JsRuntime::create_snapshot");
+
+    let object = v8::Global::new(scope, object);
+    scope.set_slot(Internals { object });
+}
+
+pub fn object<'s>(scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Object> {
+    let object = scope
+        .get_slot::<Internals>()
+        .expect("internals are set up before any bootstrap script runs")
+        .object
+        .clone();
+    v8::Local::new(scope, object)
+}
+
+/// Adds a native function to the internals object.
+pub fn set_function(
+    scope: &mut v8::PinScope,
+    name: &str,
+    callback: impl v8::MapFnTo<v8::FunctionCallback>,
+) {
+    let func = v8::Function::new(scope, callback).unwrap();
+    set_value(scope, name, func.into());
+}
+
+pub fn get<'s>(scope: &mut v8::PinScope<'s, '_>, name: &str) -> Option<v8::Local<'s, v8::Value>> {
+    let internals = object(scope);
+    let key = v8::String::new(scope, name).unwrap();
+    internals.get(scope, key.into()).filter(|value| !value.is_undefined())
+}
+
+pub fn set_value(scope: &mut v8::PinScope, name: &str, value: v8::Local<v8::Value>) {
+    let internals = object(scope);
+    let key = v8::String::new(scope, name).unwrap();
+    internals.set(scope, key.into(), value);
+}
+
+/// Calls the internal function `name`. Returns `None` if it is missing or threw.
+pub fn call<'s>(
+    scope: &mut v8::PinScope<'s, '_>,
+    name: &str,
+    args: &[v8::Local<v8::Value>],
+) -> Option<v8::Local<'s, v8::Value>> {
+    let internals = object(scope);
+    let key = v8::String::new(scope, name).unwrap();
+    let func = internals
+        .get(scope, key.into())
+        .and_then(|value| v8::Local::<v8::Function>::try_from(value).ok())?;
+    let recv = v8::undefined(scope).into();
+    func.call(scope, recv, args)
+}
+
+/// Runs `source` as the body of a function whose parameters are the names in `args`,
+/// called with their values. Bootstrap code reaches private bindings this way instead
+/// of through globals.
+pub fn run_bootstrap(
+    scope: &mut v8::PinScope,
+    resource_name: &str,
+    source: &str,
+    args: &[(&str, v8::Local<v8::Value>)],
+) {
+    let code = v8::String::new(scope, source).unwrap();
+    let resource_name = v8::String::new(scope, resource_name).unwrap();
+    let origin = v8::ScriptOrigin::new(
+        scope,
+        resource_name.into(),
+        0,
+        0,
+        false,
+        0,
+        None,
+        false,
+        false,
+        false,
+        None,
+    );
+    let mut source = v8::script_compiler::Source::new(code, Some(&origin));
+
+    let names: Vec<_> = args
+        .iter()
+        .map(|(name, _)| v8::String::new(scope, name).unwrap())
+        .collect();
+    let values: Vec<_> = args.iter().map(|(_, value)| *value).collect();
+
+    let func = v8::script_compiler::compile_function(
+        scope,
+        &mut source,
+        &names,
+        &[],
+        v8::script_compiler::CompileOptions::NoCompileOptions,
+        v8::script_compiler::NoCacheReason::NoReason,
+    )
+    .unwrap();
+    let recv = v8::undefined(scope).into();
+    func.call(scope, recv, &values).unwrap();
+}
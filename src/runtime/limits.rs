@@ -0,0 +1,201 @@
+use super::JsError;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use v8;
+
+/// Heap and execution-time limits for one runtime, from `RuntimeOptions`.
+pub(crate) struct Limits {
+    heap: Option<Box<HeapLimit>>,
+    max_heap_size: usize,
+    watchdog: Option<Watchdog>,
+    execution_timeout: Duration,
+}
+
+impl Limits {
+    pub(crate) fn new(
+        isolate: &mut v8::OwnedIsolate,
+        max_heap_size: Option<usize>,
+        execution_timeout: Option<Duration>,
+    ) -> Self {
+        let heap = max_heap_size.map(|_| {
+            let heap = Box::new(HeapLimit {
+                isolate: isolate.thread_safe_handle(),
+                exceeded: AtomicBool::new(false),
+                initial_limit: AtomicUsize::new(0),
+            });
+            isolate.add_near_heap_limit_callback(near_heap_limit_callback, heap.callback_data());
+            heap
+        });
+        let watchdog = execution_timeout.map(|_| Watchdog::new(isolate.thread_safe_handle()));
+
+        Self {
+            heap,
+            max_heap_size: max_heap_size.unwrap_or(0),
+            watchdog,
+            execution_timeout: execution_timeout.unwrap_or_default(),
+        }
+    }
+
+    /// Starts the execution budget for a script or callback. It ends when the
+    /// returned guard is dropped.
+    pub(crate) fn start_budget(&self) -> BudgetGuard<'_> {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.arm(Instant::now() + self.execution_timeout);
+        }
+        BudgetGuard { limits: self }
+    }
+
+    /// Builds the error for an execution that was terminated, and lifts the
+    /// termination so the runtime can keep running other code.
+    pub(crate) fn termination_error(&self, isolate: &mut v8::Isolate) -> JsError {
+        if let Some(heap) = &self.heap
+            && heap.exceeded.swap(false, Ordering::SeqCst)
+        {
+            isolate.cancel_terminate_execution();
+            // Take back the headroom the callback granted for unwinding, so the next
+            // run gets the configured limit, and arm the callback again for it
+            isolate.remove_near_heap_limit_callback(
+                near_heap_limit_callback,
+                heap.initial_limit.load(Ordering::SeqCst),
+            );
+            isolate.add_near_heap_limit_callback(near_heap_limit_callback, heap.callback_data());
+            return JsError::heap_limit_exceeded(self.max_heap_size);
+        }
+
+        if let Some(watchdog) = &self.watchdog
+            && watchdog.shared.fired.load(Ordering::SeqCst)
+        {
+            return JsError::time_limit_exceeded(self.execution_timeout);
+        }
+
+        JsError::terminated()
+    }
+}
+
+pub(crate) struct BudgetGuard<'a> {
+    limits: &'a Limits,
+}
+
+impl Drop for BudgetGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(watchdog) = &self.limits.watchdog {
+            watchdog.disarm();
+        }
+    }
+}
+
+struct HeapLimit {
+    isolate: v8::IsolateHandle,
+    exceeded: AtomicBool,
+    /// The heap limit the isolate was created with, as reported to the callback.
+    initial_limit: AtomicUsize,
+}
+
+impl HeapLimit {
+    /// The box is owned by `Limits`, which the runtime drops after the isolate.
+    fn callback_data(&self) -> *mut c_void {
+        self as *const HeapLimit as *mut c_void
+    }
+}
+
+unsafe extern "C" fn near_heap_limit_callback(
+    data: *mut c_void,
+    current_heap_limit: usize,
+    initial_heap_limit: usize,
+) -> usize {
+    let heap = unsafe { &*(data as *const HeapLimit) };
+    heap.initial_limit.store(initial_heap_limit, Ordering::SeqCst);
+    heap.exceeded.store(true, Ordering::SeqCst);
+    heap.isolate.terminate_execution();
+    // Give V8 room to unwind the terminated script instead of aborting the process
+    current_heap_limit * 2
+}
+
+/// A thread that terminates the isolate once an armed deadline passes.
+struct Watchdog {
+    shared: Arc<WatchdogShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct WatchdogShared {
+    state: Mutex<WatchdogState>,
+    condvar: Condvar,
+    isolate: v8::IsolateHandle,
+    fired: AtomicBool,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    deadline: Option<Instant>,
+    shutdown: bool,
+}
+
+impl Watchdog {
+    fn new(isolate: v8::IsolateHandle) -> Self {
+        let shared = Arc::new(WatchdogShared {
+            state: Mutex::new(WatchdogState::default()),
+            condvar: Condvar::new(),
+            isolate,
+            fired: AtomicBool::new(false),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("toyjs-watchdog".to_string())
+            .spawn(move || thread_shared.run())
+            .expect("Failed to spawn watchdog thread");
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    fn arm(&self, deadline: Instant) {
+        self.shared.state.lock().unwrap().deadline = Some(deadline);
+        self.shared.condvar.notify_one();
+    }
+
+    fn disarm(&self) {
+        self.shared.state.lock().unwrap().deadline = None;
+        // The deadline may have passed just after the script finished on its own;
+        // don't let that termination hit the next script
+        if self.shared.fired.swap(false, Ordering::SeqCst) {
+            self.shared.isolate.cancel_terminate_execution();
+        }
+    }
+}
+
+impl WatchdogShared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.shutdown {
+            match state.deadline {
+                None => state = self.condvar.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.deadline = None;
+                        self.fired.store(true, Ordering::SeqCst);
+                        self.isolate.terminate_execution();
+                    } else {
+                        state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
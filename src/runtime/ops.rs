@@ -0,0 +1,518 @@
+use super::{CallbackId, PendingOps, SchedulerMessage, internals};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+use v8::{self, MapFnTo};
+
+/// A value passed between JavaScript and an op.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpValue {
+    /// Any JSON-compatible value. `undefined` arrives as `null`.
+    Json(serde_json::Value),
+    /// The contents of a `Uint8Array` or `ArrayBuffer`. Returned to JavaScript as a `Uint8Array`.
+    Bytes(Vec<u8>),
+}
+
+impl OpValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OpValue::Json(value) => value.as_str(),
+            OpValue::Bytes(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Value> for OpValue {
+    fn from(value: serde_json::Value) -> Self {
+        OpValue::Json(value)
+    }
+}
+
+impl From<Vec<u8>> for OpValue {
+    fn from(bytes: Vec<u8>) -> Self {
+        OpValue::Bytes(bytes)
+    }
+}
+
+/// An op failure, thrown (sync ops) or rejected (async ops) as a JavaScript error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpError {
+    /// Error class, e.g. `"TypeError"`. Unknown classes become an `Error` with this `name`.
+    pub class: String,
+    pub message: String,
+}
+
+impl OpError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self::with_class("Error", message)
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::with_class("TypeError", message)
+    }
+
+    pub fn with_class(class: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            class: class.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.class, self.message)
+    }
+}
+
+impl<E: std::error::Error> From<E> for OpError {
+    fn from(error: E) -> Self {
+        OpError::new(error.to_string())
+    }
+}
+
+pub type OpResult = Result<OpValue, OpError>;
+pub type OpFuture = Pin<Box<dyn Future<Output = OpResult> + Send>>;
+
+type SyncOpFn = dyn Fn(Vec<OpValue>) -> OpResult + Send + Sync;
+type AsyncOpFn = dyn Fn(Vec<OpValue>) -> OpFuture + Send + Sync;
+
+#[derive(Clone)]
+enum OpKind {
+    Sync(Arc<SyncOpFn>),
+    Async(Arc<AsyncOpFn>),
+}
+
+/// A named native function exposed to extension JavaScript as `ops.<name>`.
+#[derive(Clone)]
+pub struct OpDecl {
+    name: String,
+    kind: OpKind,
+    unref: bool,
+}
+
+impl OpDecl {
+    /// An op that runs on the V8 thread and returns its result directly.
+    pub fn new_sync<F>(name: impl Into<String>, op: F) -> Self
+    where
+        F: Fn(Vec<OpValue>) -> OpResult + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            kind: OpKind::Sync(Arc::new(op)),
+            unref: false,
+        }
+    }
+
+    /// An op that returns a Promise. The future is spawned on the event loop and the
+    /// promise settles with its output on a later `process_callbacks()`.
+    pub fn new_async<F, Fut>(name: impl Into<String>, op: F) -> Self
+    where
+        F: Fn(Vec<OpValue>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = OpResult> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            kind: OpKind::Async(Arc::new(move |args| Box::pin(op(args)))),
+            unref: false,
+        }
+    }
+
+    /// Stops calls of this async op from keeping the runtime alive: `run_until_idle()`
+    /// returns even while one is in flight, like for an unref'd timer.
+    pub fn unref(mut self) -> Self {
+        self.unref = true;
+        self
+    }
+}
+
+/// A bundle of ops plus the JavaScript that builds a public API on top of them.
+///
+/// ```ignore
+/// let ext = Extension::new("greeter")
+///     .op(OpDecl::new_sync("op_greet", |args| {
+///         let name = args.first().and_then(|v| v.as_str()).unwrap_or("world");
+///         Ok(serde_json::json!(format!("Hello, {}!", name)).into())
+///     }))
+///     .js("globalThis.greet = (name) => ops.op_greet(name);");
+/// ```
+#[derive(Clone)]
+pub struct Extension {
+    name: String,
+    ops: Vec<OpDecl>,
+    js: Vec<String>,
+}
+
+impl Extension {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ops: Vec::new(),
+            js: Vec::new(),
+        }
+    }
+
+    pub fn op(mut self, op: OpDecl) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Adds a bootstrap script, run once all ops are installed. The script runs as a
+    /// function body with `ops` and the runtime's private `internals` in scope; neither
+    /// is reachable from user code, so anything public has to be put on `globalThis`.
+    pub fn js(mut self, source: impl Into<String>) -> Self {
+        self.js.push(source.into());
+        self
+    }
+}
+
+/// Registered ops and the promises of async ops still in flight.
+struct OpState {
+    ops: Vec<OpDecl>,
+    scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
+    next_promise_id: CallbackId,
+    resolvers: HashMap<CallbackId, v8::Global<v8::PromiseResolver>>,
+}
+
+/// Installs the ops of `extensions` as functions on the private `ops` object, then runs
+/// their bootstrap scripts. `first_index` is the `OpState` index of the first op, so
+/// extensions installed later (e.g. on top of a snapshot) continue the numbering.
+pub fn install_extensions(scope: &mut v8::PinScope, extensions: &[Extension], first_index: usize) {
+    let ops_obj = match internals::get(scope, "ops").and_then(|value| v8::Local::<v8::Object>::try_from(value).ok()) {
+        Some(ops_obj) => ops_obj,
+        None => {
+            let ops_obj = v8::Object::new(scope);
+            internals::set_value(scope, "ops", ops_obj.into());
+            internals::set_function(scope, "cancelOp", cancel_op);
+            internals::set_function(scope, "refOp", ref_op);
+            ops_obj
+        }
+    };
+
+    for (i, op) in extensions.iter().flat_map(|ext| ext.ops.iter()).enumerate() {
+        let index = v8::Integer::new(scope, (first_index + i) as i32);
+        let builder = match op.kind {
+            OpKind::Sync(_) => v8::Function::builder(op_sync_callback),
+            OpKind::Async(_) => v8::Function::builder(op_async_callback),
+        };
+        let func = builder.data(index.into()).build(scope).unwrap();
+        let name = v8::String::new(scope, &op.name).unwrap();
+        ops_obj.set(scope, name.into(), func.into());
+    }
+
+    let internals_obj = internals::object(scope);
+    for ext in extensions {
+        for source in &ext.js {
+            internals::run_bootstrap(
+                scope,
+                &format!("ext:{}.js", ext.name),
+                source,
+                &[("ops", ops_obj.into()), ("internals", internals_obj.into())],
+            );
+        }
+    }
+}
+
+/// Registers the native side of every installed op, in the order they were installed.
+pub fn init_op_state<'a>(
+    scope: &mut v8::PinScope,
+    extensions: impl IntoIterator<Item = &'a Extension>,
+    scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
+) {
+    let ops = extensions
+        .into_iter()
+        .flat_map(|ext| ext.ops.iter().cloned())
+        .collect();
+
+    scope.set_slot(OpState {
+        ops,
+        scheduler_tx,
+        next_promise_id: 1,
+        resolvers: HashMap::new(),
+    });
+}
+
+pub(crate) fn op_count(extensions: &[Extension]) -> usize {
+    extensions.iter().map(|ext| ext.ops.len()).sum()
+}
+
+/// The native callbacks behind every op, for the snapshot's external references.
+pub(crate) fn external_references() -> Vec<v8::ExternalReference> {
+    vec![
+        v8::ExternalReference {
+            function: op_sync_callback.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: op_async_callback.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: cancel_op.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: ref_op.map_fn_to(),
+        },
+    ]
+}
+
+/// Settles the promise of a completed async op.
+pub fn resolve_op(scope: &mut v8::PinScope, id: CallbackId, result: OpResult) {
+    let Some(resolver) = scope
+        .get_slot_mut::<OpState>()
+        .and_then(|state| state.resolvers.remove(&id))
+    else {
+        return;
+    };
+    let resolver = v8::Local::new(scope, resolver);
+
+    match result.and_then(|value| to_v8(scope, value)) {
+        Ok(value) => {
+            resolver.resolve(scope, value);
+        }
+        Err(error) => {
+            let exception = to_exception(scope, &error);
+            resolver.reject(scope, exception);
+        }
+    }
+}
+
+/// Finds the op a callback was installed for, whether it is unref'd, and a span for
+/// this call of it.
+fn lookup_op(scope: &mut v8::PinScope, args: &v8::FunctionCallbackArguments) -> Option<(OpKind, bool, tracing::Span)> {
+    let index = args.data().int32_value(scope)? as usize;
+    let state = scope.get_slot::<OpState>()?;
+    let op = state.ops.get(index)?;
+    let span = tracing::debug_span!("op", name = %op.name, id = tracing::field::Empty);
+    Some((op.kind.clone(), op.unref, span))
+}
+
+fn collect_args(
+    scope: &mut v8::PinScope,
+    args: &v8::FunctionCallbackArguments,
+) -> Result<Vec<OpValue>, OpError> {
+    (0..args.length())
+        .map(|i| from_v8(scope, args.get(i)))
+        .collect()
+}
+
+fn op_sync_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let Some((OpKind::Sync(op), _, span)) = lookup_op(scope, &args) else {
+        return;
+    };
+    let _span = span.entered();
+
+    match collect_args(scope, &args).and_then(|op_args| op(op_args)).and_then(|value| to_v8(scope, value)) {
+        Ok(value) => retval.set(value),
+        Err(error) => {
+            let exception = to_exception(scope, &error);
+            scope.throw_exception(exception);
+        }
+    }
+}
+
+fn op_async_callback(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let Some((OpKind::Async(op), unref, span)) = lookup_op(scope, &args) else {
+        return;
+    };
+
+    let resolver = v8::PromiseResolver::new(scope).unwrap();
+    let promise = resolver.get_promise(scope);
+    retval.set(promise.into());
+
+    let op_args = match collect_args(scope, &args) {
+        Ok(op_args) => op_args,
+        Err(error) => {
+            let exception = to_exception(scope, &error);
+            resolver.reject(scope, exception);
+            return;
+        }
+    };
+
+    let resolver = v8::Global::new(scope, resolver);
+
+    let Some(state) = scope.get_slot_mut::<OpState>() else {
+        return;
+    };
+    let id = state.next_promise_id;
+    state.next_promise_id += 1;
+    state.resolvers.insert(id, resolver);
+
+    // The span follows the future onto the event loop, so whatever the op logs is
+    // reported inside it
+    span.record("id", id);
+    let future = span.in_scope(|| op(op_args)).instrument(span);
+    let _ = state.scheduler_tx.send(SchedulerMessage::Op(id, Box::pin(future)));
+
+    let key = op_id_key(scope);
+    let id_value = v8::Number::new(scope, id as f64);
+    promise.set_private(scope, key, id_value.into());
+
+    if !unref && let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        pending.ops.insert(id);
+    }
+}
+
+/// The private property an async op's promise carries its id in, so the op can be
+/// found from the promise without searching.
+fn op_id_key<'s>(scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Private> {
+    let name = v8::String::new(scope, "toyjs#opId").unwrap();
+    v8::Private::for_api(scope, Some(name))
+}
+
+/// The id of the async op behind `promise`, if it is an op promise.
+fn promise_op_id(scope: &mut v8::PinScope, promise: v8::Local<v8::Value>) -> Option<CallbackId> {
+    let promise = v8::Local::<v8::Promise>::try_from(promise).ok()?;
+    let key = op_id_key(scope);
+    let id = promise.get_private(scope, key).filter(|id| id.is_number())?;
+    Some(id.integer_value(scope)? as CallbackId)
+}
+
+/// `internals.cancelOp(promise, reason)`: stops the async op behind `promise`. Its
+/// future is dropped on the event loop and the promise rejects with `reason`, usually
+/// the aborting signal's, or an `AbortError` `DOMException` without one. Returns false
+/// if the op has already settled.
+fn cancel_op(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    retval.set_bool(false);
+    let Some(id) = promise_op_id(scope, args.get(0)) else {
+        return;
+    };
+    let Some(resolver) = scope.get_slot_mut::<OpState>().and_then(|state| {
+        let resolver = state.resolvers.remove(&id)?;
+        let _ = state.scheduler_tx.send(SchedulerMessage::CancelOp(id));
+        Some(resolver)
+    }) else {
+        return;
+    };
+
+    if let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        pending.ops.remove(&id);
+    }
+
+    let resolver = v8::Local::new(scope, resolver);
+    let reason = match args.get(1) {
+        reason if reason.is_undefined() => abort_error(scope),
+        reason => reason,
+    };
+    resolver.reject(scope, reason);
+    retval.set_bool(true);
+}
+
+/// `new DOMException("The operation was aborted.", "AbortError")`, as `AbortSignal` creates.
+fn abort_error<'s>(scope: &mut v8::PinScope<'s, '_>) -> v8::Local<'s, v8::Value> {
+    let message = v8::String::new(scope, "The operation was aborted.").unwrap();
+    let name = v8::String::new(scope, "AbortError").unwrap();
+    internals::get(scope, "DOMException")
+        .and_then(|value| v8::Local::<v8::Function>::try_from(value).ok())
+        .and_then(|constructor| constructor.new_instance(scope, &[message.into(), name.into()]))
+        .map(Into::into)
+        .unwrap_or_else(|| v8::Exception::error(scope, message))
+}
+
+/// `internals.refOp(promise, keepAlive)`: sets whether the async op behind `promise`
+/// keeps the runtime alive, whatever its declaration says. Returns false if the op has
+/// already settled.
+fn ref_op(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    retval.set_bool(false);
+    let Some(id) = promise_op_id(scope, args.get(0)) else {
+        return;
+    };
+    let keep_alive = args.get(1).boolean_value(scope);
+    if !scope
+        .get_slot::<OpState>()
+        .is_some_and(|state| state.resolvers.contains_key(&id))
+    {
+        return;
+    }
+
+    if let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        if keep_alive {
+            pending.ops.insert(id);
+        } else {
+            pending.ops.remove(&id);
+        }
+    }
+    retval.set_bool(true);
+}
+
+fn from_v8(scope: &mut v8::PinScope, value: v8::Local<v8::Value>) -> Result<OpValue, OpError> {
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(value) {
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Ok(OpValue::Bytes(bytes));
+    }
+
+    if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+        let view = v8::Uint8Array::new(scope, buffer, 0, buffer.byte_length()).unwrap();
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Ok(OpValue::Bytes(bytes));
+    }
+
+    if value.is_undefined() || value.is_function() || value.is_symbol() {
+        return Ok(OpValue::Json(serde_json::Value::Null));
+    }
+
+    let json = v8::json::stringify(scope, value)
+        .ok_or_else(|| OpError::type_error("Op argument could not be serialized to JSON"))?;
+    let json = json.to_rust_string_lossy(scope);
+    serde_json::from_str(&json)
+        .map(OpValue::Json)
+        .map_err(|e| OpError::type_error(e.to_string()))
+}
+
+fn to_v8<'s>(scope: &mut v8::PinScope<'s, '_>, value: OpValue) -> Result<v8::Local<'s, v8::Value>, OpError> {
+    match value {
+        OpValue::Json(serde_json::Value::Null) => Ok(v8::null(scope).into()),
+        OpValue::Json(value) => {
+            let json = v8::String::new(scope, &value.to_string())
+                .ok_or_else(|| OpError::new("Op result is too large"))?;
+            v8::json::parse(scope, json).ok_or_else(|| OpError::new("Op result is not valid JSON"))
+        }
+        OpValue::Bytes(bytes) => {
+            let len = bytes.len();
+            let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared();
+            let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+            Ok(v8::Uint8Array::new(scope, buffer, 0, len).unwrap().into())
+        }
+    }
+}
+
+fn to_exception<'s>(scope: &mut v8::PinScope<'s, '_>, error: &OpError) -> v8::Local<'s, v8::Value> {
+    let message = v8::String::new(scope, &error.message).unwrap();
+    match error.class.as_str() {
+        "TypeError" => v8::Exception::type_error(scope, message),
+        "RangeError" => v8::Exception::range_error(scope, message),
+        "SyntaxError" => v8::Exception::syntax_error(scope, message),
+        "ReferenceError" => v8::Exception::reference_error(scope, message),
+        class => {
+            let exception = v8::Exception::error(scope, message);
+            if class != "Error"
+                && let Ok(obj) = v8::Local::<v8::Object>::try_from(exception)
+            {
+                let key = v8::String::new(scope, "name").unwrap();
+                let name = v8::String::new(scope, class).unwrap();
+                obj.set(scope, key.into(), name.into());
+            }
+            exception
+        }
+    }
+}
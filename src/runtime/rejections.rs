@@ -0,0 +1,50 @@
+use v8;
+
+/// Promises that were rejected without a handler. Entries are removed again if a
+/// handler is attached later, so whatever survives a microtask checkpoint is
+/// truly unhandled.
+#[derive(Default)]
+pub(crate) struct PendingRejections {
+    rejections: Vec<(v8::Global<v8::Promise>, v8::Global<v8::Value>)>,
+}
+
+impl PendingRejections {
+    pub(crate) fn take(&mut self) -> Vec<(v8::Global<v8::Promise>, v8::Global<v8::Value>)> {
+        std::mem::take(&mut self.rejections)
+    }
+
+    /// Puts rejections taken earlier back, ahead of any that were queued since.
+    pub(crate) fn requeue(
+        &mut self,
+        rejections: impl IntoIterator<Item = (v8::Global<v8::Promise>, v8::Global<v8::Value>)>,
+    ) {
+        self.rejections.splice(0..0, rejections);
+    }
+}
+
+pub extern "C" fn promise_reject_callback(message: v8::PromiseRejectMessage) {
+    let scope_storage = std::pin::pin!(unsafe { v8::CallbackScope::new(&message) });
+    let scope = &mut scope_storage.init();
+    let handle_scope = std::pin::pin!(v8::HandleScope::new(scope));
+    let scope = &mut handle_scope.init();
+
+    let promise = message.get_promise();
+
+    match message.get_event() {
+        v8::PromiseRejectEvent::PromiseRejectWithNoHandler => {
+            let reason = message
+                .get_value()
+                .unwrap_or_else(|| v8::undefined(scope).into());
+            let entry = (v8::Global::new(scope, promise), v8::Global::new(scope, reason));
+            if let Some(pending) = scope.get_slot_mut::<PendingRejections>() {
+                pending.rejections.push(entry);
+            }
+        }
+        v8::PromiseRejectEvent::PromiseHandlerAddedAfterReject => {
+            if let Some(pending) = scope.get_slot_mut::<PendingRejections>() {
+                pending.rejections.retain(|(pending_promise, _)| *pending_promise != promise);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,28 @@
+use super::{bindings, console, ops, timers, workers};
+use std::borrow::Cow;
+use v8;
+
+/// The snapshot `build.rs` creates with the `startup-snapshot` feature, which runtimes
+/// start from unless given another one. Empty without the feature.
+#[cfg(toyjs_startup_snapshot)]
+static BUILTIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/toyjs.snap"));
+#[cfg(not(toyjs_startup_snapshot))]
+static BUILTIN: &[u8] = &[];
+
+/// The snapshot a runtime starts from: `requested`, or else the built-in one. `None`,
+/// from an empty blob, means running the bootstrap instead.
+pub(crate) fn startup_snapshot(requested: Option<&'static [u8]>) -> Option<&'static [u8]> {
+    Some(requested.unwrap_or(BUILTIN)).filter(|blob| !blob.is_empty())
+}
+
+/// Every native callback the bootstrapped context holds a reference to. V8 stores
+/// these as indices into this table when writing a snapshot and maps them back when
+/// reading it, so creating and loading a snapshot must use the same list.
+pub(crate) fn external_references() -> Cow<'static, [v8::ExternalReference]> {
+    let mut references = bindings::external_references();
+    references.extend(timers::external_references());
+    references.extend(console::external_references());
+    references.extend(workers::external_references());
+    references.extend(ops::external_references());
+    Cow::Owned(references)
+}
@@ -0,0 +1,517 @@
+use super::internals;
+use v8;
+
+/// Installs `ReadableStream`, its default reader and controller, and the two queuing
+/// strategies. Byte streams, BYOB readers and piping to writable streams are not
+/// supported.
+pub fn setup_streams(scope: &mut v8::PinScope) {
+    let js_code = r#"
+        const { SafeWeakMap } = internals.primordials;
+
+        // The state of each stream, shared with its controller and reader. Streams are
+        // driven through these plain records so the public classes stay thin
+        const streamStates = new SafeWeakMap();
+        const readerStates = new SafeWeakMap();
+        const controllerStates = new SafeWeakMap();
+
+        // Controllers are only created by their stream
+        const constructing = Symbol("constructing");
+
+        function deferred() {
+            let resolve, reject;
+            const promise = new Promise((res, rej) => {
+                resolve = res;
+                reject = rej;
+            });
+            return { promise, resolve, reject };
+        }
+
+        function rejected(reason) {
+            const result = deferred();
+            result.promise.catch(() => {});
+            result.reject(reason);
+            return result;
+        }
+
+        function desiredSize(state) {
+            if (state.state === "errored") {
+                return null;
+            }
+            if (state.state === "closed") {
+                return 0;
+            }
+            return state.highWaterMark - state.queueTotalSize;
+        }
+
+        function shouldPull(state) {
+            if (state.state !== "readable" || state.closeRequested || !state.started) {
+                return false;
+            }
+            if (state.reader && state.reader.readRequests.length > 0) {
+                return true;
+            }
+            return desiredSize(state) > 0;
+        }
+
+        function callPull(state) {
+            if (!shouldPull(state)) {
+                return;
+            }
+            if (state.pulling) {
+                state.pullAgain = true;
+                return;
+            }
+            state.pulling = true;
+            let result;
+            try {
+                result = state.pull(state.controller);
+            } catch (error) {
+                result = Promise.reject(error);
+            }
+            Promise.resolve(result).then(
+                () => {
+                    state.pulling = false;
+                    if (state.pullAgain) {
+                        state.pullAgain = false;
+                        callPull(state);
+                    }
+                },
+                (error) => errorStream(state, error),
+            );
+        }
+
+        function enqueue(state, chunk) {
+            if (state.closeRequested || state.state !== "readable") {
+                throw new TypeError("Cannot enqueue a chunk into a closed stream");
+            }
+            const reader = state.reader;
+            if (reader && reader.readRequests.length > 0) {
+                reader.readRequests.shift().resolve({ value: chunk, done: false });
+            } else {
+                let size;
+                try {
+                    size = state.size(chunk);
+                    if (typeof size !== "number" || !(size >= 0) || size === Infinity) {
+                        throw new RangeError("Chunk size must be a finite, non-negative number");
+                    }
+                } catch (error) {
+                    errorStream(state, error);
+                    throw error;
+                }
+                state.queue.push({ value: chunk, size });
+                state.queueTotalSize += size;
+            }
+            callPull(state);
+        }
+
+        function closeStream(state) {
+            if (state.closeRequested || state.state !== "readable") {
+                throw new TypeError("Cannot close a stream that is already closed");
+            }
+            state.closeRequested = true;
+            if (state.queue.length === 0) {
+                finishClose(state);
+            }
+        }
+
+        function finishClose(state) {
+            state.state = "closed";
+            state.pull = state.cancel = null;
+            const reader = state.reader;
+            if (reader) {
+                for (const request of reader.readRequests.splice(0)) {
+                    request.resolve({ value: undefined, done: true });
+                }
+                reader.closed.resolve();
+            }
+        }
+
+        function errorStream(state, error) {
+            if (state.state !== "readable") {
+                return;
+            }
+            state.state = "errored";
+            state.storedError = error;
+            state.queue = [];
+            state.queueTotalSize = 0;
+            state.pull = state.cancel = null;
+            const reader = state.reader;
+            if (reader) {
+                for (const request of reader.readRequests.splice(0)) {
+                    request.reject(error);
+                }
+                reader.closed.promise.catch(() => {});
+                reader.closed.reject(error);
+            }
+        }
+
+        function cancelStream(state, reason) {
+            state.disturbed = true;
+            if (state.state === "closed") {
+                return Promise.resolve();
+            }
+            if (state.state === "errored") {
+                return Promise.reject(state.storedError);
+            }
+            const cancel = state.cancel;
+            state.queue = [];
+            state.queueTotalSize = 0;
+            finishClose(state);
+            try {
+                return Promise.resolve(cancel?.(reason)).then(() => undefined);
+            } catch (error) {
+                return Promise.reject(error);
+            }
+        }
+
+        function read(reader) {
+            const state = reader.stream;
+            if (!state) {
+                return Promise.reject(new TypeError("Reader has been released"));
+            }
+            state.disturbed = true;
+            if (state.state === "closed") {
+                return Promise.resolve({ value: undefined, done: true });
+            }
+            if (state.state === "errored") {
+                return Promise.reject(state.storedError);
+            }
+            if (state.queue.length > 0) {
+                const { value, size } = state.queue.shift();
+                state.queueTotalSize = state.queue.length === 0 ? 0 : state.queueTotalSize - size;
+                if (state.closeRequested && state.queue.length === 0) {
+                    finishClose(state);
+                } else {
+                    callPull(state);
+                }
+                return Promise.resolve({ value, done: false });
+            }
+            const request = deferred();
+            reader.readRequests.push(request);
+            callPull(state);
+            return request.promise;
+        }
+
+        function releaseReader(reader) {
+            const state = reader.stream;
+            if (!state) {
+                return;
+            }
+            const error = new TypeError("Reader has been released");
+            for (const request of reader.readRequests.splice(0)) {
+                request.reject(error);
+            }
+            if (state.state === "readable") {
+                reader.closed.promise.catch(() => {});
+                reader.closed.reject(error);
+            } else {
+                reader.closed = rejected(error);
+            }
+            state.reader = null;
+            reader.stream = null;
+        }
+
+        function extractStrategy(strategy) {
+            const highWaterMark = strategy?.highWaterMark === undefined ? 1 : Number(strategy.highWaterMark);
+            if (Number.isNaN(highWaterMark) || highWaterMark < 0) {
+                throw new RangeError("highWaterMark must be a non-negative number");
+            }
+            const size = strategy?.size;
+            if (size !== undefined && typeof size !== "function") {
+                throw new TypeError("size must be a function");
+            }
+            return { highWaterMark, size: size ? (chunk) => size(chunk) : () => 1 };
+        }
+
+        function stateOf(stream) {
+            const state = streamStates.get(stream);
+            if (!state) {
+                throw new TypeError("Illegal invocation");
+            }
+            return state;
+        }
+
+        class ReadableStreamDefaultController {
+            constructor(token) {
+                if (token !== constructing) {
+                    throw new TypeError("Illegal constructor");
+                }
+            }
+
+            get desiredSize() {
+                return desiredSize(controllerStates.get(this));
+            }
+
+            close() {
+                closeStream(controllerStates.get(this));
+            }
+
+            enqueue(chunk) {
+                enqueue(controllerStates.get(this), chunk);
+            }
+
+            error(error) {
+                errorStream(controllerStates.get(this), error);
+            }
+        }
+
+        class ReadableStream {
+            constructor(underlyingSource = {}, strategy = {}) {
+                underlyingSource ??= {};
+                if (underlyingSource.type !== undefined) {
+                    throw new RangeError(`Unsupported stream type: '${underlyingSource.type}'`);
+                }
+                const { highWaterMark, size } = extractStrategy(strategy);
+                const state = {
+                    state: "readable",
+                    storedError: undefined,
+                    disturbed: false,
+                    reader: null,
+                    queue: [],
+                    queueTotalSize: 0,
+                    highWaterMark,
+                    size,
+                    started: false,
+                    closeRequested: false,
+                    pulling: false,
+                    pullAgain: false,
+                    pull: underlyingSource.pull ? (controller) => underlyingSource.pull(controller) : null,
+                    cancel: underlyingSource.cancel ? (reason) => underlyingSource.cancel(reason) : null,
+                    controller: new ReadableStreamDefaultController(constructing),
+                };
+                state.pull ??= () => {};
+                streamStates.set(this, state);
+                controllerStates.set(state.controller, state);
+
+                const started = underlyingSource.start ? underlyingSource.start(state.controller) : undefined;
+                Promise.resolve(started).then(
+                    () => {
+                        state.started = true;
+                        callPull(state);
+                    },
+                    (error) => errorStream(state, error),
+                );
+            }
+
+            static from(iterable) {
+                const method = iterable?.[Symbol.asyncIterator] ?? iterable?.[Symbol.iterator];
+                if (typeof method !== "function") {
+                    throw new TypeError("ReadableStream.from requires an iterable");
+                }
+                const iterator = method.call(iterable);
+                return new ReadableStream({
+                    async pull(controller) {
+                        const { value, done } = await iterator.next();
+                        if (done) {
+                            controller.close();
+                        } else {
+                            controller.enqueue(await value);
+                        }
+                    },
+                    async cancel(reason) {
+                        await iterator.return?.(reason);
+                    },
+                }, { highWaterMark: 0 });
+            }
+
+            get locked() {
+                return stateOf(this).reader !== null;
+            }
+
+            cancel(reason = undefined) {
+                const state = streamStates.get(this);
+                if (!state) {
+                    return Promise.reject(new TypeError("Illegal invocation"));
+                }
+                if (state.reader) {
+                    return Promise.reject(new TypeError("Cannot cancel a locked stream"));
+                }
+                return cancelStream(state, reason);
+            }
+
+            getReader(options = {}) {
+                if (options?.mode !== undefined) {
+                    throw new RangeError(`Unsupported reader mode: '${options.mode}'`);
+                }
+                return new ReadableStreamDefaultReader(this);
+            }
+
+            // Both branches get the same chunks. The source is read as fast as the
+            // faster branch reads, and only cancelled once both branches are
+            tee() {
+                const reader = new ReadableStreamDefaultReader(this);
+                let reading = false;
+                let readAgain = false;
+                const canceled = [false, false];
+                const reasons = [undefined, undefined];
+                const cancelled = deferred();
+                const branches = [];
+
+                const pull = () => {
+                    if (reading) {
+                        readAgain = true;
+                        return;
+                    }
+                    reading = true;
+                    reader.read().then(
+                        ({ value, done }) => {
+                            reading = false;
+                            for (let i = 0; i < 2; i++) {
+                                if (canceled[i]) {
+                                    continue;
+                                }
+                                const state = streamStates.get(branches[i]);
+                                if (done) {
+                                    closeStream(state);
+                                } else {
+                                    enqueue(state, value);
+                                }
+                            }
+                            if (done) {
+                                cancelled.resolve();
+                            } else if (readAgain) {
+                                readAgain = false;
+                                pull();
+                            }
+                        },
+                        (error) => {
+                            reading = false;
+                            for (const branch of branches) {
+                                errorStream(streamStates.get(branch), error);
+                            }
+                            cancelled.resolve();
+                        },
+                    );
+                };
+
+                const cancel = (i) => (reason) => {
+                    canceled[i] = true;
+                    reasons[i] = reason;
+                    if (canceled[0] && canceled[1]) {
+                        cancelled.resolve(cancelStream(readerStates.get(reader).stream, reasons));
+                    }
+                    return cancelled.promise;
+                };
+
+                for (let i = 0; i < 2; i++) {
+                    branches.push(new ReadableStream({ pull, cancel: cancel(i) }, { highWaterMark: 0 }));
+                }
+                return branches;
+            }
+
+            values(options = {}) {
+                const reader = this.getReader();
+                const preventCancel = !!options?.preventCancel;
+                return (async function* () {
+                    let finished = false;
+                    try {
+                        while (true) {
+                            let result;
+                            try {
+                                result = await reader.read();
+                            } catch (error) {
+                                finished = true;
+                                throw error;
+                            }
+                            if (result.done) {
+                                finished = true;
+                                return;
+                            }
+                            yield result.value;
+                        }
+                    } finally {
+                        if (!finished && !preventCancel) {
+                            await reader.cancel();
+                        }
+                        reader.releaseLock();
+                    }
+                })();
+            }
+
+            [Symbol.asyncIterator](options = {}) {
+                return this.values(options);
+            }
+        }
+
+        class ReadableStreamDefaultReader {
+            constructor(stream) {
+                const state = stateOf(stream);
+                if (state.reader) {
+                    throw new TypeError("ReadableStream is already locked to a reader");
+                }
+                const reader = { stream: state, readRequests: [], closed: deferred() };
+                if (state.state === "closed") {
+                    reader.closed.resolve();
+                } else if (state.state === "errored") {
+                    reader.closed = rejected(state.storedError);
+                }
+                state.reader = reader;
+                readerStates.set(this, reader);
+            }
+
+            get closed() {
+                return readerStates.get(this).closed.promise;
+            }
+
+            read() {
+                return read(readerStates.get(this));
+            }
+
+            cancel(reason = undefined) {
+                const reader = readerStates.get(this);
+                if (!reader.stream) {
+                    return Promise.reject(new TypeError("Reader has been released"));
+                }
+                return cancelStream(reader.stream, reason);
+            }
+
+            releaseLock() {
+                releaseReader(readerStates.get(this));
+            }
+        }
+
+        class CountQueuingStrategy {
+            #highWaterMark;
+
+            constructor({ highWaterMark }) {
+                this.#highWaterMark = Number(highWaterMark);
+            }
+
+            get highWaterMark() {
+                return this.#highWaterMark;
+            }
+
+            get size() {
+                return () => 1;
+            }
+        }
+
+        class ByteLengthQueuingStrategy {
+            #highWaterMark;
+
+            constructor({ highWaterMark }) {
+                this.#highWaterMark = Number(highWaterMark);
+            }
+
+            get highWaterMark() {
+                return this.#highWaterMark;
+            }
+
+            get size() {
+                return (chunk) => chunk.byteLength;
+            }
+        }
+
+        globalThis.ReadableStream = ReadableStream;
+        globalThis.ReadableStreamDefaultReader = ReadableStreamDefaultReader;
+        globalThis.ReadableStreamDefaultController = ReadableStreamDefaultController;
+        globalThis.CountQueuingStrategy = CountQueuingStrategy;
+        globalThis.ByteLengthQueuingStrategy = ByteLengthQueuingStrategy;
+
+        // A stream that has been read from or cancelled can't be used as a body
+        internals.isReadableStreamDisturbed = (stream) => stateOf(stream).disturbed;
+    "#;
+
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:streams.js", js_code, &[("internals", internals_obj.into())]);
+}
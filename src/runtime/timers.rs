@@ -1,152 +1,451 @@
-use super::SchedulerMessage;
+use super::{CallbackId, CallbackMessage, JsError, PendingOps, SchedulerMessage, internals};
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
-use v8;
+use v8::{self, MapFnTo};
 
-struct TimerState {
+/// Stored in an isolate slot for the native timer bindings.
+pub(crate) struct TimerState {
     scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
+    time_origin: Instant,
 }
 
-fn get_timer_state<'a>(scope: &mut v8::PinScope) -> Option<&'a TimerState> {
-    let global = scope.get_current_context().global(scope);
-    let state_key = v8::String::new(scope, "__timerState").unwrap();
-    let state_val = global.get(scope, state_key.into())?;
+impl TimerState {
+    pub(crate) fn new(scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>) -> Self {
+        Self {
+            scheduler_tx,
+            time_origin: Instant::now(),
+        }
+    }
+}
+
+/// Timer queue used instead of tokio timers when the runtime runs on virtual time.
+/// Stored in an isolate slot; its presence is what puts the runtime in virtual mode.
+/// Time only moves when the embedder advances it.
+pub(crate) struct VirtualClock {
+    /// Milliseconds since the runtime was created.
+    now: u64,
+    /// Wall-clock time the virtual clock started at, reported by `Date.now()`.
+    epoch_ms: f64,
+    /// Scheduled timers keyed by due time, then by scheduling order.
+    timers: BTreeMap<(u64, u64), (CallbackId, Option<u64>)>,
+    next_seq: u64,
+}
+
+impl VirtualClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: 0,
+            epoch_ms: epoch_ms(),
+            timers: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub(crate) fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub(crate) fn set_now(&mut self, now: u64) {
+        self.now = self.now.max(now);
+    }
+
+    fn schedule(&mut self, id: CallbackId, delay: u64, interval: Option<u64>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.timers.insert((self.now + delay, seq), (id, interval));
+    }
+
+    fn clear(&mut self, id: CallbackId) {
+        self.timers.retain(|_, (timer_id, _)| *timer_id != id);
+    }
+
+    /// Removes the earliest timer due at or before `until`, moves the clock to its due
+    /// time and returns the callback to run. Intervals are rescheduled for their next tick.
+    pub(crate) fn pop_due(&mut self, until: u64) -> Option<CallbackMessage> {
+        let entry = self.timers.first_entry()?;
+        if entry.key().0 > until {
+            return None;
+        }
 
-    if !state_val.is_external() {
-        return None;
+        let ((due, _), (id, interval)) = entry.remove_entry();
+        self.now = due;
+        match interval {
+            Some(period) => {
+                self.schedule(id, period, Some(period));
+                Some(CallbackMessage::ExecuteInterval(id))
+            }
+            None => Some(CallbackMessage::ExecuteTimeout(id)),
+        }
     }
+}
 
-    let external: v8::Local<v8::External> = state_val.try_into().ok()?;
-    let state_ptr = external.value() as *const TimerState;
-    Some(unsafe { &*state_ptr })
+fn epoch_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
 }
 
-pub fn setup_timers(
+/// The native callbacks installed by `setup_timers`, for the snapshot's external references.
+pub(crate) fn external_references() -> Vec<v8::ExternalReference> {
+    [
+        schedule_timeout.map_fn_to(),
+        schedule_interval.map_fn_to(),
+        clear_timer.map_fn_to(),
+        date_now.map_fn_to(),
+        performance_now.map_fn_to(),
+    ]
+    .into_iter()
+    .map(|function| v8::ExternalReference { function })
+    .collect()
+}
+
+fn schedule_timeout(
     scope: &mut v8::PinScope,
-    scheduler_tx: mpsc::UnboundedSender<SchedulerMessage>,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
 ) {
-    let global = scope.get_current_context().global(scope);
-
-    let state = TimerState { scheduler_tx };
-    let state_ptr = Box::into_raw(Box::new(state)) as *mut std::ffi::c_void;
-    let external = v8::External::new(scope, state_ptr);
-    let state_key = v8::String::new(scope, "__timerState").unwrap();
-    global.set(scope, state_key.into(), external.into());
-
-    let native_schedule_timeout = v8::Function::new(
-        scope,
-        |scope: &mut v8::PinScope,
-         args: v8::FunctionCallbackArguments,
-         mut _retval: v8::ReturnValue| {
-            if args.length() < 2 {
-                return;
-            }
+    if args.length() < 2 {
+        return;
+    }
 
-            let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
-            let delay = args.get(1).number_value(scope).unwrap_or(0.0) as u64;
+    let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
+    let delay = args.get(1).number_value(scope).unwrap_or(0.0) as u64;
+    // Passing `false` schedules a timeout that doesn't keep the runtime alive
+    let keep_alive = !args.get(2).is_false();
 
-            if let Some(state) = get_timer_state(scope) {
-                let _ = state.scheduler_tx.send(SchedulerMessage::ScheduleTimeout(id, delay));
-            }
-        },
-    )
-    .unwrap();
-
-    let name = v8::String::new(scope, "__nativeScheduleTimeout").unwrap();
-    global.set(scope, name.into(), native_schedule_timeout.into());
-
-    let native_schedule_interval = v8::Function::new(
-        scope,
-        |scope: &mut v8::PinScope,
-         args: v8::FunctionCallbackArguments,
-         mut _retval: v8::ReturnValue| {
-            if args.length() < 2 {
-                return;
-            }
+    if keep_alive && let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        pending.timeouts.insert(id);
+    }
 
-            let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
-            let interval = args.get(1).number_value(scope).unwrap_or(0.0) as u64;
+    if let Some(clock) = scope.get_slot_mut::<VirtualClock>() {
+        clock.schedule(id, delay, None);
+    } else if let Some(state) = scope.get_slot::<TimerState>() {
+        let _ = state.scheduler_tx.send(SchedulerMessage::ScheduleTimeout(id, delay));
+    }
+}
 
-            if let Some(state) = get_timer_state(scope) {
-                let _ = state.scheduler_tx.send(SchedulerMessage::ScheduleInterval(id, interval));
-            }
-        },
-    )
-    .unwrap();
-
-    let name = v8::String::new(scope, "__nativeScheduleInterval").unwrap();
-    global.set(scope, name.into(), native_schedule_interval.into());
-
-    let native_clear_timer = v8::Function::new(
-        scope,
-        |scope: &mut v8::PinScope,
-         args: v8::FunctionCallbackArguments,
-         mut _retval: v8::ReturnValue| {
-            if args.length() < 1 {
-                return;
-            }
+fn schedule_interval(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    if args.length() < 2 {
+        return;
+    }
 
-            let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
+    let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
+    let interval = args.get(1).number_value(scope).unwrap_or(0.0) as u64;
 
-            if let Some(state) = get_timer_state(scope) {
-                let _ = state.scheduler_tx.send(SchedulerMessage::ClearTimer(id));
-            }
-        },
-    )
-    .unwrap();
+    if let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        pending.intervals.insert(id);
+    }
+
+    if let Some(clock) = scope.get_slot_mut::<VirtualClock>() {
+        // A zero period would never let virtual time move past the interval
+        clock.schedule(id, interval.max(1), Some(interval.max(1)));
+    } else if let Some(state) = scope.get_slot::<TimerState>() {
+        let _ = state.scheduler_tx.send(SchedulerMessage::ScheduleInterval(id, interval));
+    }
+}
+
+fn clear_timer(
+    scope: &mut v8::PinScope,
+    args: v8::FunctionCallbackArguments,
+    mut _retval: v8::ReturnValue,
+) {
+    if args.length() < 1 {
+        return;
+    }
+
+    let id = args.get(0).number_value(scope).unwrap_or(0.0) as u64;
+
+    if let Some(pending) = scope.get_slot_mut::<PendingOps>() {
+        pending.timeouts.remove(&id);
+        pending.intervals.remove(&id);
+    }
+
+    if let Some(clock) = scope.get_slot_mut::<VirtualClock>() {
+        clock.clear(id);
+    } else if let Some(state) = scope.get_slot::<TimerState>() {
+        let _ = state.scheduler_tx.send(SchedulerMessage::ClearTimer(id));
+    }
+}
+
+fn date_now(
+    scope: &mut v8::PinScope,
+    _args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let now = match scope.get_slot::<VirtualClock>() {
+        Some(clock) => clock.epoch_ms + clock.now as f64,
+        None => epoch_ms(),
+    };
+    retval.set(v8::Number::new(scope, now.floor()).into());
+}
 
-    let name = v8::String::new(scope, "__nativeClearTimer").unwrap();
-    global.set(scope, name.into(), native_clear_timer.into());
+fn performance_now(
+    scope: &mut v8::PinScope,
+    _args: v8::FunctionCallbackArguments,
+    mut retval: v8::ReturnValue,
+) {
+    let now = match scope.get_slot::<VirtualClock>() {
+        Some(clock) => clock.now as f64,
+        None => scope
+            .get_slot::<TimerState>()
+            .map(|state| state.time_origin.elapsed().as_secs_f64() * 1000.0)
+            .unwrap_or(0.0),
+    };
+    retval.set(v8::Number::new(scope, now).into());
+}
+
+/// Installs the timer globals. The sender they schedule through is looked up from the
+/// `TimerState` slot at call time, so this can run while building a snapshot.
+pub fn setup_timers(scope: &mut v8::PinScope) {
+    internals::set_function(scope, "scheduleTimeout", schedule_timeout);
+    internals::set_function(scope, "scheduleInterval", schedule_interval);
+    internals::set_function(scope, "clearTimer", clear_timer);
+    internals::set_function(scope, "dateNow", date_now);
+    internals::set_function(scope, "performanceNow", performance_now);
 
     let js_code = r#"
-        // Timer state
-        globalThis.__timerCallbacks = new Map();
-        globalThis.__intervalIds = new Set();
-        globalThis.__nextTimerId = 1;
+        const { SafeMap, SafeSet } = internals.primordials;
 
-        // setTimeout implementation
-        globalThis.setTimeout = function(callback, delay) {
-            const id = globalThis.__nextTimerId++;
-            globalThis.__timerCallbacks.set(id, callback);
-            __nativeScheduleTimeout(id, delay || 0);
+        // Timer state, private to this closure
+        const timerCallbacks = new SafeMap();
+        const intervalIds = new SafeSet();
+        let nextTimerId = 1;
+
+        // Arguments after the delay are passed to the callback
+        function bindArgs(callback, args) {
+            return args.length === 0 ? callback : () => callback(...args);
+        }
+
+        // `keepAlive` false leaves the timer out of the work that keeps the runtime alive
+        function startTimeout(callback, delay, keepAlive) {
+            const id = nextTimerId++;
+            timerCallbacks.set(id, callback);
+            internals.scheduleTimeout(id, delay || 0, keepAlive);
             return id;
+        }
+
+        // setTimeout implementation
+        globalThis.setTimeout = function setTimeout(callback, delay, ...args) {
+            return startTimeout(bindArgs(callback, args), delay, true);
         };
 
         // setInterval implementation
-        globalThis.setInterval = function(callback, interval) {
-            const id = globalThis.__nextTimerId++;
-            globalThis.__timerCallbacks.set(id, callback);
-            globalThis.__intervalIds.add(id);
-            __nativeScheduleInterval(id, interval || 0);
+        globalThis.setInterval = function setInterval(callback, interval, ...args) {
+            const id = nextTimerId++;
+            timerCallbacks.set(id, bindArgs(callback, args));
+            intervalIds.add(id);
+            internals.scheduleInterval(id, interval || 0);
             return id;
         };
 
+        // A timeout for the runtime's own use that doesn't keep it alive, like the
+        // timer behind AbortSignal.timeout()
+        internals.setUnrefTimeout = function(callback, delay) {
+            return startTimeout(callback, delay, false);
+        };
+
         // clearTimeout and clearInterval
-        globalThis.clearTimeout = function(id) {
-            globalThis.__timerCallbacks.delete(id);
-            globalThis.__intervalIds.delete(id);
-            __nativeClearTimer(id);
+        function clearTimer(id) {
+            timerCallbacks.delete(id);
+            intervalIds.delete(id);
+            internals.clearTimer(id);
+        }
+
+        // The `node:timers/promises` module. Its setTimeout runs on the same timer ops
+        // as the global one, and aborting `signal` clears the timer and rejects with the
+        // signal's reason. `ref: false` doesn't keep the runtime alive for the timer
+        internals.timersPromises = {
+            setTimeout(delay, value, options = {}) {
+                return new Promise((resolve, reject) => {
+                    if (options === null || typeof options !== "object") {
+                        throw new TypeError("options must be an object");
+                    }
+                    const { signal, ref = true } = options;
+                    if (signal === undefined) {
+                        startTimeout(() => resolve(value), delay, ref !== false);
+                        return;
+                    }
+
+                    let id;
+                    const stop = internals.onAbort(signal, () => {
+                        clearTimer(id);
+                        reject(signal.reason);
+                    });
+                    if (stop === null) {
+                        reject(signal.reason);
+                        return;
+                    }
+                    id = startTimeout(() => {
+                        stop();
+                        resolve(value);
+                    }, delay, ref !== false);
+                });
+            },
         };
 
-        globalThis.clearInterval = function(id) {
-            globalThis.__timerCallbacks.delete(id);
-            globalThis.__intervalIds.delete(id);
-            __nativeClearTimer(id);
+        globalThis.clearTimeout = function clearTimeout(id) {
+            clearTimer(id);
+        };
+
+        globalThis.clearInterval = function clearInterval(id) {
+            clearTimer(id);
+        };
+
+        // Follows virtual time when it is enabled, like Date on a virtual clock
+        // Measured on first use rather than at bootstrap, which may be baked into a snapshot
+        let timeOrigin;
+        globalThis.performance = {
+            get timeOrigin() {
+                timeOrigin ??= internals.dateNow() - internals.performanceNow();
+                return timeOrigin;
+            },
+            now() {
+                return internals.performanceNow();
+            },
         };
 
         // Callback executor called from Rust
-        globalThis.__executeTimer = function(id) {
-            const callback = globalThis.__timerCallbacks.get(id);
+        internals.executeTimer = function(id) {
+            const callback = timerCallbacks.get(id);
             if (callback) {
-                callback();
-                // For setTimeout (not interval), remove the callback after execution
-                if (!globalThis.__intervalIds.has(id)) {
-                    globalThis.__timerCallbacks.delete(id);
+                // For setTimeout (not interval), remove the callback before it runs
+                if (!intervalIds.has(id)) {
+                    timerCallbacks.delete(id);
                 }
+                callback();
             }
         };
     "#;
 
-    let code_str = v8::String::new(scope, js_code).unwrap();
-    let script = v8::Script::compile(scope, code_str, None).unwrap();
-    script.run(scope).unwrap();
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:timers.js", js_code, &[("internals", internals_obj.into())]);
+}
+
+/// Replaces `Date` with a wrapper whose `Date.now()`, `new Date()` and `Date()` read the
+/// virtual clock. Only installed on runtimes with `RuntimeOptions::virtual_time`, after
+/// the bootstrap, so other runtimes and startup snapshots keep the built-in `Date`.
+pub fn setup_virtual_date(scope: &mut v8::PinScope) {
+    let js_code = r#"
+        const NativeDate = Date;
+        const VirtualDate = function Date(...args) {
+            if (new.target === undefined) {
+                return new NativeDate(internals.dateNow()).toString();
+            }
+            return Reflect.construct(NativeDate, args.length === 0 ? [internals.dateNow()] : args, new.target);
+        };
+        Object.defineProperty(VirtualDate, "length", { value: NativeDate.length });
+        Object.defineProperty(VirtualDate, "prototype", { value: NativeDate.prototype });
+        Object.setPrototypeOf(VirtualDate, NativeDate);
+        // So that `new Date().constructor === Date` still holds
+        Object.defineProperty(NativeDate.prototype, "constructor", {
+            value: VirtualDate,
+            writable: true,
+            configurable: true,
+        });
+        VirtualDate.now = function now() {
+            return internals.dateNow();
+        };
+        globalThis.Date = VirtualDate;
+    "#;
+
+    let internals_obj = internals::object(scope);
+    internals::run_bootstrap(scope, "ext:virtual_date.js", js_code, &[("internals", internals_obj.into())]);
+}
+
+/// Upper bound on the virtual timers one `run_all_timers()` or `run_until_idle()` call
+/// fires before assuming they will never finish.
+const MAX_VIRTUAL_TIMERS: usize = 10_000;
+
+/// Counts the virtual timers fired by one call that drains the virtual clock, so an
+/// interval that is never cleared can't spin forever.
+pub(crate) struct TimerBudget {
+    caller: &'static str,
+    fired: usize,
+}
+
+impl TimerBudget {
+    pub(crate) fn new(caller: &'static str) -> Self {
+        Self { caller, fired: 0 }
+    }
+
+    /// Accounts for one more timer, failing once `MAX_VIRTUAL_TIMERS` have fired.
+    pub(crate) fn spend(&mut self) -> Result<(), JsError> {
+        if self.fired == MAX_VIRTUAL_TIMERS {
+            return Err(JsError::new(format!(
+                "Aborted {}() after {} timers, is an interval never cleared?",
+                self.caller, MAX_VIRTUAL_TIMERS
+            )));
+        }
+        self.fired += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pops every timer due by `until`, as (virtual time it fired at, timer id).
+    fn due(clock: &mut VirtualClock, until: u64) -> Vec<(u64, CallbackId)> {
+        let mut fired = Vec::new();
+        while let Some(msg) = clock.pop_due(until) {
+            match msg {
+                CallbackMessage::ExecuteTimeout(id) | CallbackMessage::ExecuteInterval(id) => fired.push((clock.now(), id)),
+                CallbackMessage::OpResult(..) => unreachable!(),
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn fires_timers_in_due_order_then_scheduling_order() {
+        let mut clock = VirtualClock::new();
+        clock.schedule(1, 30, None);
+        clock.schedule(2, 10, None);
+        clock.schedule(3, 10, None);
+
+        assert_eq!(due(&mut clock, 9), vec![]);
+        assert_eq!(due(&mut clock, 30), vec![(10, 2), (10, 3), (30, 1)]);
+        assert_eq!(clock.pop_due(u64::MAX).map(|_| ()), None);
+    }
+
+    #[test]
+    fn reschedules_intervals() {
+        let mut clock = VirtualClock::new();
+        clock.schedule(1, 10, Some(10));
+        clock.schedule(2, 25, None);
+
+        assert_eq!(due(&mut clock, 30), vec![(10, 1), (20, 1), (25, 2), (30, 1)]);
+        clock.clear(1);
+        assert_eq!(due(&mut clock, 100), vec![]);
+    }
+
+    #[test]
+    fn never_moves_backwards() {
+        let mut clock = VirtualClock::new();
+        clock.set_now(50);
+        clock.set_now(20);
+        assert_eq!(clock.now(), 50);
+
+        // Scheduled relative to the current virtual time
+        clock.schedule(1, 5, None);
+        assert_eq!(due(&mut clock, 54), vec![]);
+        assert_eq!(due(&mut clock, 55), vec![(55, 1)]);
+    }
+
+    #[test]
+    fn budget_runs_out_after_the_maximum() {
+        let mut budget = TimerBudget::new("run_all_timers");
+        for _ in 0..MAX_VIRTUAL_TIMERS {
+            budget.spend().unwrap();
+        }
+        let error = budget.spend().unwrap_err();
+        assert_eq!(error.message(), "Aborted run_all_timers() after 10000 timers, is an interval never cleared?");
+    }
 }
@@ -0,0 +1,546 @@
+use super::ops::{Extension, OpDecl, OpError, OpValue};
+use super::{JsError, JsRuntime, RuntimeOptions, internals};
+use crate::modules::ModuleLoader;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::mpsc;
+use v8::{self, MapFnTo, ValueDeserializerHelper, ValueSerializerHelper};
+
+/// Something a worker reports back to its parent. The channel closing means the
+/// worker has exited.
+enum WorkerEvent {
+    Message(Vec<u8>),
+    Error(JsError),
+    /// The worker has run out of work, having handled this many messages.
+    Idle(u64),
+}
+
+/// The parent's side of a running worker.
+struct WorkerHandle {
+    /// Serialized messages for the worker. Dropping it ends the worker's receive loop.
+    to_worker: mpsc::UnboundedSender<Vec<u8>>,
+    from_worker: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<WorkerEvent>>>,
+    close_tx: mpsc::UnboundedSender<()>,
+    /// Set by the worker once its isolate exists, so terminate() can interrupt it even
+    /// while it is busy.
+    isolate: Arc<OnceLock<v8::IsolateHandle>>,
+}
+
+#[derive(Default)]
+struct WorkerTable {
+    next_id: u64,
+    workers: HashMap<u64, WorkerHandle>,
+    defaults: WorkerDefaults,
+}
+
+/// The parts of the creating runtime's options that its workers inherit.
+#[derive(Clone, Default)]
+pub(crate) struct WorkerDefaults {
+    /// Workers start from the same snapshot as the runtime that created them.
+    pub(crate) startup_snapshot: Option<&'static [u8]>,
+    pub(crate) code_cache_dir: Option<PathBuf>,
+    /// Workers load modules through the same loader as the runtime that created them.
+    pub(crate) module_loader: Option<Arc<dyn ModuleLoader>>,
+}
+
+type Workers = Arc<Mutex<WorkerTable>>;
+
+/// The `Worker` class, for creating workers from this runtime.
+pub(crate) fn extension(defaults: WorkerDefaults) -> Extension {
+    let workers = Workers::new(Mutex::new(WorkerTable {
+        defaults,
+        ..Default::default()
+    }));
+
+    let create = workers.clone();
+    let post = workers.clone();
+    let recv = workers.clone();
+    let terminate = workers;
+
+    Extension::new("worker")
+        .op(OpDecl::new_sync("op_host_create_worker", move |args| {
+            op_host_create_worker(&create, args)
+        }))
+        .op(OpDecl::new_sync("op_host_post_message", move |args| {
+            op_host_post_message(&post, args)
+        }))
+        // Waiting for messages doesn't keep the parent alive by itself. `Worker` refs
+        // the op while the worker is busy
+        .op(OpDecl::new_async("op_host_recv_message", move |args| {
+            op_host_recv_message(recv.clone(), args)
+        })
+        .unref())
+        .op(OpDecl::new_sync("op_host_terminate_worker", move |args| {
+            op_host_terminate_worker(&terminate, args)
+        }))
+        .js(WORKER_JS)
+}
+
+/// `postMessage`, `onmessage` and `close` inside a worker, talking to its parent.
+/// `handled` is kept up to date with the number of messages the worker has dispatched.
+fn worker_scope_extension(
+    to_parent: mpsc::UnboundedSender<WorkerEvent>,
+    from_parent: mpsc::UnboundedReceiver<Vec<u8>>,
+    close_tx: mpsc::UnboundedSender<()>,
+    handled: Arc<AtomicU64>,
+) -> Extension {
+    let from_parent = Arc::new(tokio::sync::Mutex::new(from_parent));
+
+    Extension::new("worker_scope")
+        .op(OpDecl::new_sync("op_worker_post_message", move |args| {
+            let data = message_bytes(args.into_iter().next())?;
+            let _ = to_parent.send(WorkerEvent::Message(data));
+            Ok(serde_json::Value::Null.into())
+        }))
+        // Unref'd, so the worker's run_until_idle() returns once it has nothing else
+        // to do. `run_worker` then keeps waiting for messages while the parent is there
+        .op(OpDecl::new_async("op_worker_recv_message", move |args| {
+            // Called again right after each message is dispatched, with the count so far
+            if let Some(OpValue::Json(count)) = args.first()
+                && let Some(count) = count.as_u64()
+            {
+                handled.store(count, Ordering::SeqCst);
+            }
+            let from_parent = from_parent.clone();
+            async move {
+                match from_parent.lock().await.recv().await {
+                    Some(data) => Ok(data.into()),
+                    None => Ok(serde_json::Value::Null.into()),
+                }
+            }
+        })
+        .unref())
+        .op(OpDecl::new_sync("op_worker_close", move |_args| {
+            let _ = close_tx.send(());
+            Ok(serde_json::Value::Null.into())
+        }))
+        .js(WORKER_SCOPE_JS)
+}
+
+fn worker_id(args: &[OpValue]) -> Result<u64, OpError> {
+    match args.first() {
+        Some(OpValue::Json(value)) => value.as_u64(),
+        _ => None,
+    }
+    .ok_or_else(|| OpError::type_error("Invalid worker id"))
+}
+
+fn message_bytes(arg: Option<OpValue>) -> Result<Vec<u8>, OpError> {
+    match arg {
+        Some(OpValue::Bytes(data)) => Ok(data),
+        _ => Err(OpError::type_error("Worker messages must be serialized")),
+    }
+}
+
+fn op_host_create_worker(workers: &Workers, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let specifier = args
+        .first()
+        .and_then(OpValue::as_str)
+        .ok_or_else(|| OpError::type_error("Worker requires a module specifier"))?;
+    let referrer = args.get(1).and_then(OpValue::as_str);
+    // Not held across the spawn below, so other workers' ops aren't blocked while
+    // this one starts
+    let (id, defaults) = {
+        let mut table = workers.lock().unwrap();
+        table.next_id += 1;
+        (table.next_id, table.defaults.clone())
+    };
+    let path = resolve_worker_module(defaults.module_loader.as_deref(), specifier, referrer)?;
+
+    let (to_worker, from_parent) = mpsc::unbounded_channel();
+    let (to_parent, from_worker) = mpsc::unbounded_channel();
+    let (close_tx, close_rx) = mpsc::unbounded_channel();
+    let isolate = Arc::new(OnceLock::new());
+
+    let scope_close_tx = close_tx.clone();
+    let worker_isolate = isolate.clone();
+    std::thread::Builder::new()
+        .name(format!("worker-{}", id))
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = to_parent.send(WorkerEvent::Error(JsError::new(format!(
+                        "Failed to start worker event loop: {}",
+                        e
+                    ))));
+                    return;
+                }
+            };
+            let handled = Arc::new(AtomicU64::new(0));
+            let extension = worker_scope_extension(to_parent.clone(), from_parent, scope_close_tx, handled.clone());
+            let options = RuntimeOptions {
+                extensions: vec![extension],
+                startup_snapshot: defaults.startup_snapshot,
+                code_cache_dir: defaults.code_cache_dir,
+                module_loader: defaults.module_loader,
+                ..Default::default()
+            };
+            let channels = WorkerChannels {
+                to_parent,
+                close_rx,
+                isolate: worker_isolate,
+                handled,
+            };
+            runtime.block_on(run_worker(path, options, channels));
+        })?;
+
+    // The worker starts in the background. Messages posted before it is ready wait in
+    // its channel
+    workers.lock().unwrap().workers.insert(
+        id,
+        WorkerHandle {
+            to_worker,
+            from_worker: Arc::new(tokio::sync::Mutex::new(from_worker)),
+            close_tx,
+            isolate,
+        },
+    );
+    Ok(serde_json::json!(id).into())
+}
+
+/// Worker specifiers are resolved against the module or script that called `new Worker()`,
+/// like an import from it. Without one, e.g. from `execute_script`, they resolve against
+/// the current working directory, the same way `exec` resolves its entry module.
+fn resolve_worker_module(
+    loader: Option<&dyn ModuleLoader>,
+    specifier: &str,
+    referrer: Option<&str>,
+) -> Result<PathBuf, OpError> {
+    let path = specifier.strip_prefix("file://").unwrap_or(specifier);
+    let base = match referrer {
+        Some(referrer) => referrer.to_string(),
+        None => std::env::current_dir()?.to_string_lossy().to_string(),
+    };
+    let resolved = match loader {
+        Some(loader) => loader.resolve(path, &base),
+        None => crate::modules::FsModuleLoader::resolve_path(&base, path).map_err(|e| e.to_string()),
+    };
+    resolved.map(PathBuf::from).map_err(OpError::type_error)
+}
+
+/// The worker's side of the channels to its parent, beyond the message ops.
+struct WorkerChannels {
+    to_parent: mpsc::UnboundedSender<WorkerEvent>,
+    close_rx: mpsc::UnboundedReceiver<()>,
+    isolate: Arc<OnceLock<v8::IsolateHandle>>,
+    handled: Arc<AtomicU64>,
+}
+
+/// Runs the worker's module, then keeps handling messages for as long as the parent
+/// holds on to the worker. Each time it runs out of work it reports how many messages
+/// it has handled, which lets the parent stop waiting for it.
+async fn run_worker(path: PathBuf, options: RuntimeOptions, channels: WorkerChannels) {
+    let WorkerChannels {
+        to_parent,
+        mut close_rx,
+        isolate,
+        handled,
+    } = channels;
+
+    let mut runtime = JsRuntime::with_options(options);
+    let _ = isolate.set(runtime.isolate.thread_safe_handle());
+    // terminate() sends the close before looking for the handle, so a worker terminated
+    // while starting either sees the close here or has its execution terminated
+    if close_rx.try_recv().is_ok() {
+        runtime.shutdown();
+        return;
+    }
+    let event_loop = runtime.run_event_loop();
+
+    let result = tokio::select! {
+        result = async {
+            runtime.execute_module(&path).await?;
+            loop {
+                runtime.run_until_idle().await?;
+                // Sent after any messages posted so far, so the parent sees those first
+                let _ = to_parent.send(WorkerEvent::Idle(handled.load(Ordering::SeqCst)));
+                // Only the unref'd receive op is left. The parent dropping its side
+                // means no message can arrive anymore
+                tokio::select! {
+                    more = runtime.wait_for_callbacks() => {
+                        if !more? {
+                            return Ok(());
+                        }
+                    }
+                    _ = to_parent.closed() => return Ok(()),
+                }
+            }
+        } => result,
+        _ = close_rx.recv() => Ok(()),
+    };
+
+    // An error caused by terminate() interrupting the worker is not worth reporting
+    let closed = close_rx.try_recv().is_ok();
+    if let Err(error) = result
+        && !closed
+    {
+        let _ = to_parent.send(WorkerEvent::Error(error));
+    }
+
+    runtime.shutdown();
+    let _ = event_loop.await;
+}
+
+fn op_host_post_message(workers: &Workers, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let id = worker_id(&args)?;
+    let data = message_bytes(args.into_iter().nth(1))?;
+    if let Some(worker) = workers.lock().unwrap().workers.get(&id) {
+        let _ = worker.to_worker.send(data);
+    }
+    Ok(serde_json::Value::Null.into())
+}
+
+/// Resolves with the worker's next message as bytes, an error description object,
+/// `{ handled }` when the worker has run out of work, or `null` once it has exited.
+async fn op_host_recv_message(workers: Workers, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let id = worker_id(&args)?;
+    let Some(from_worker) = workers
+        .lock()
+        .unwrap()
+        .workers
+        .get(&id)
+        .map(|worker| worker.from_worker.clone())
+    else {
+        return Ok(serde_json::Value::Null.into());
+    };
+
+    let event = from_worker.lock().await.recv().await;
+    match event {
+        Some(WorkerEvent::Message(data)) => Ok(data.into()),
+        Some(WorkerEvent::Error(error)) => Ok(serde_json::json!({
+            "name": error.name(),
+            "message": error.message(),
+            "filename": error.resource_name(),
+            "lineno": error.line(),
+            "colno": error.column(),
+        })
+        .into()),
+        Some(WorkerEvent::Idle(handled)) => Ok(serde_json::json!({ "handled": handled }).into()),
+        None => {
+            workers.lock().unwrap().workers.remove(&id);
+            Ok(serde_json::Value::Null.into())
+        }
+    }
+}
+
+fn op_host_terminate_worker(workers: &Workers, args: Vec<OpValue>) -> Result<OpValue, OpError> {
+    let id = worker_id(&args)?;
+    if let Some(worker) = workers.lock().unwrap().workers.get(&id) {
+        let _ = worker.close_tx.send(());
+        if let Some(isolate) = worker.isolate.get() {
+            isolate.terminate_execution();
+        }
+    }
+    Ok(serde_json::Value::Null.into())
+}
+
+struct SerializerDelegate;
+
+impl v8::ValueSerializerImpl for SerializerDelegate {
+    fn throw_data_clone_error<'s>(
+        &self,
+        scope: &mut v8::PinScope<'s, '_>,
+        message: v8::Local<'s, v8::String>,
+    ) {
+        let exception = v8::Exception::error(scope, message);
+        if let Ok(obj) = v8::Local::<v8::Object>::try_from(exception) {
+            let key = v8::String::new(scope, "name").unwrap();
+            let name = v8::String::new(scope, "DataCloneError").unwrap();
+            obj.set(scope, key.into(), name.into());
+        }
+        scope.throw_exception(exception);
+    }
+}
+
+struct DeserializerDelegate;
+
+impl v8::ValueDeserializerImpl for DeserializerDelegate {}
+
+/// Installs the structured clone helpers that worker messages are encoded with, and the
+/// caller lookup `new Worker()` resolves its specifier against.
+pub fn setup_worker_internals(scope: &mut v8::PinScope) {
+    internals::set_function(scope, "serialize", serialize);
+    internals::set_function(scope, "deserialize", deserialize);
+    internals::set_function(scope, "callerScript", caller_script);
+}
+
+/// The native callbacks installed by `setup_worker_internals`, for the snapshot's
+/// external references.
+pub(crate) fn external_references() -> Vec<v8::ExternalReference> {
+    vec![
+        v8::ExternalReference {
+            function: serialize.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: deserialize.map_fn_to(),
+        },
+        v8::ExternalReference {
+            function: caller_script.map_fn_to(),
+        },
+    ]
+}
+
+/// `internals.callerScript()`: the resource name of the innermost script or module on
+/// the stack that isn't runtime code, or `undefined` if there is none.
+fn caller_script(scope: &mut v8::PinScope, _args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let Some(stack_trace) = v8::StackTrace::current_stack_trace(scope, 16) else {
+        return;
+    };
+    for i in 0..stack_trace.get_frame_count() {
+        let Some(name) = stack_trace
+            .get_frame(scope, i)
+            .and_then(|frame| frame.get_script_name_or_source_url(scope))
+        else {
+            continue;
+        };
+        if !name.to_rust_string_lossy(scope).starts_with("ext:") {
+            retval.set(name.into());
+            return;
+        }
+    }
+}
+
+fn serialize(scope: &mut v8::PinScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let context = scope.get_current_context();
+    let serializer = v8::ValueSerializer::new(scope, Box::new(SerializerDelegate));
+    serializer.write_header();
+    if serializer.write_value(context, args.get(0)) != Some(true) {
+        return;
+    }
+
+    let bytes = serializer.release();
+    let len = bytes.len();
+    let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared();
+    let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+    retval.set(v8::Uint8Array::new(scope, buffer, 0, len).unwrap().into());
+}
+
+fn deserialize(scope: &mut v8::PinScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(args.get(0)) else {
+        return;
+    };
+    let mut bytes = vec![0; view.byte_length()];
+    view.copy_contents(&mut bytes);
+
+    let context = scope.get_current_context();
+    let deserializer = v8::ValueDeserializer::new(scope, Box::new(DeserializerDelegate), &bytes);
+    if deserializer.read_header(context) != Some(true) {
+        return;
+    }
+    if let Some(value) = deserializer.read_value(context) {
+        retval.set(value);
+    }
+}
+
+const WORKER_JS: &str = r#"
+    globalThis.Worker = class Worker extends EventTarget {
+        #id;
+        #terminated = false;
+        // Messages posted so far, and whether the worker may still be working on some
+        #sent = 0;
+        #busy = true;
+        #pending = null;
+
+        constructor(specifier, options = {}) {
+            super();
+            if (options.type !== "module") {
+                throw new TypeError('Only module workers are supported, use { type: "module" }');
+            }
+            // Relative specifiers resolve against the calling module, like imports do
+            this.#id = ops.op_host_create_worker(String(specifier), internals.callerScript() ?? null);
+            this.#receive();
+        }
+
+        postMessage(message) {
+            if (!this.#terminated) {
+                ops.op_host_post_message(this.#id, internals.serialize(message));
+                this.#sent++;
+                if (!this.#busy) {
+                    this.#busy = true;
+                    internals.refOp(this.#pending, true);
+                }
+            }
+        }
+
+        terminate() {
+            if (!this.#terminated) {
+                this.#terminated = true;
+                ops.op_host_terminate_worker(this.#id);
+            }
+        }
+
+        async #receive() {
+            for (;;) {
+                // Receiving only keeps the runtime alive while the worker is busy, so an
+                // idle worker doesn't keep its parent running forever
+                this.#pending = ops.op_host_recv_message(this.#id);
+                if (this.#busy) {
+                    internals.refOp(this.#pending, true);
+                }
+                const event = await this.#pending;
+                this.#pending = null;
+                if (event === null || this.#terminated) {
+                    return;
+                }
+
+                if (event instanceof Uint8Array) {
+                    this.dispatchEvent(new MessageEvent("message", { data: internals.deserialize(event) }));
+                    continue;
+                }
+
+                // The worker has caught up with the messages it had seen when it went idle
+                if ("handled" in event) {
+                    this.#busy = event.handled < this.#sent;
+                    continue;
+                }
+
+                const error = new Error(event.message);
+                error.name = event.name;
+                const errorEvent = new ErrorEvent("error", {
+                    message: `${event.name}: ${event.message}`,
+                    filename: event.filename ?? "",
+                    lineno: event.lineno ?? 0,
+                    colno: event.colno ?? 0,
+                    error,
+                    cancelable: true,
+                });
+                // Reported like an uncaught exception, unless an error handler prevents it
+                if (this.dispatchEvent(errorEvent)) {
+                    internals.consoleWrite(true, `Uncaught (in worker) ${errorEvent.message}`);
+                }
+            }
+        }
+    };
+"#;
+
+const WORKER_SCOPE_JS: &str = r#"
+    globalThis.self = globalThis;
+
+    globalThis.postMessage = function postMessage(message) {
+        ops.op_worker_post_message(internals.serialize(message));
+    };
+
+    globalThis.close = function close() {
+        ops.op_worker_close();
+    };
+
+    (async () => {
+        // Tells the runtime how many messages have been dispatched, so the parent knows
+        // when the worker has caught up
+        let handled = 0;
+        for (;;) {
+            const data = await ops.op_worker_recv_message(handled);
+            if (data === null) {
+                return;
+            }
+            internals.dispatchGlobalEvent(new MessageEvent("message", { data: internals.deserialize(data) }));
+            handled++;
+        }
+    })();
+"#;
@@ -7,7 +7,7 @@ fn main() -> anyhow::Result<()> {
       let scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
       let mut handle_scope = scope.init();
       let context = v8::Context::new(&handle_scope, Default::default());
-      let mut context_scope = v8::ContextScope::new(&mut handle_scope, context);
+      let context_scope = v8::ContextScope::new(&mut handle_scope, context);
       let src= "`the answer is ${6*7}`";
       let code = v8::String::new(&context_scope, src).unwrap();
       let script = v8::Script::compile(&context_scope, code, None).unwrap();
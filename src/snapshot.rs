@@ -0,0 +1,18 @@
+use toyjs::runtime::JsRuntime;
+use std::env;
+
+// Writes a startup snapshot for `RuntimeOptions::startup_snapshot`, so it can be
+// produced ahead of time and loaded from disk when the runtime starts.
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: snapshot <output_path>");
+        std::process::exit(1);
+    }
+
+    let snapshot = JsRuntime::create_snapshot();
+    std::fs::write(&args[1], &snapshot)?;
+    println!("Wrote {} byte snapshot to {}", snapshot.len(), args[1]);
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::net::TcpListener;
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+fn virtual_runtime() -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        virtual_time: true,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn promise_timers_resolve_or_abort() {
+    let mut runtime = virtual_runtime();
+    runtime
+        .execute_script_module(
+            r#"import { setTimeout as sleep } from "node:timers/promises";
+               const controller = new AbortController();
+               const log = [];
+               const aborted = sleep(100, "late", { signal: controller.signal }).catch((error) => error.name);
+               sleep(50, "early").then((value) => {
+                   log.push(value);
+                   controller.abort();
+               });
+               log.push(await aborted);
+               log.push(await sleep(10, "after"));
+               log.push(await sleep(10, 0, { signal: AbortSignal.abort("why") }).catch((reason) => reason));
+               globalThis.result = log.join();"#,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(runtime.execute_script("result").unwrap(), "early,AbortError,after,why");
+    // The aborted timer was cleared rather than left to fire at 100ms
+    runtime.run_all_timers().unwrap();
+    assert_eq!(runtime.execute_script("performance.now()").unwrap(), "60");
+}
+
+#[test]
+fn set_timeout_passes_every_extra_argument_to_the_callback() {
+    let mut runtime = virtual_runtime();
+    runtime
+        .execute_script(
+            r#"globalThis.result = "";
+               setTimeout((options, n) => { result = `${options.signal} ${n}`; }, 0, { signal: "not a signal" }, 2);"#,
+        )
+        .unwrap();
+
+    runtime.run_all_timers().unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "not a signal 2");
+}
+
+#[tokio::test]
+async fn aborting_fetch_rejects_with_the_signal_reason() {
+    // Accepts the connection but never answers, so only the abort can settle the fetch
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let _connections: Vec<_> = listener.incoming().collect();
+    });
+
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script_module(&format!(
+            r#"const controller = new AbortController();
+               const request = fetch("http://{address}/", {{ signal: controller.signal }});
+               setTimeout(() => controller.abort(), 10);
+               const error = await request.catch((error) => error);
+               const custom = await fetch("http://{address}/", {{ signal: AbortSignal.abort("stop") }}).catch((reason) => reason);
+               globalThis.result = [error instanceof DOMException, error.name, error === controller.signal.reason, custom].join();"#
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(runtime.execute_script("result").unwrap(), "true,AbortError,true,stop");
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use toyjs::modules::CodeCacheStats;
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+async fn run(main: &std::path::Path, cache_dir: &std::path::Path) -> CodeCacheStats {
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        code_cache_dir: Some(cache_dir.to_path_buf()),
+        ..Default::default()
+    });
+    runtime.execute_module(main).await.unwrap();
+    runtime.code_cache_stats().unwrap()
+}
+
+#[tokio::test]
+async fn later_runs_compile_from_the_cache() {
+    let dir = common::fixture(
+        "code-cache",
+        &[
+            ("main.js", "import { add } from './math.js'; globalThis.sum = add(1, 2);"),
+            ("math.js", "export function add(a, b) { return a + b; }"),
+        ],
+    );
+    let cache_dir = dir.join("cache");
+    let main = dir.join("main.js");
+
+    let first = run(&main, &cache_dir).await;
+    assert_eq!((first.hits, first.misses), (0, 2));
+
+    let second = run(&main, &cache_dir).await;
+    assert_eq!((second.hits, second.misses, second.rejected), (2, 0, 0));
+
+    // Editing one module only misses the cache for that module
+    std::fs::write(dir.join("math.js"), "export function add(a, b) { return b + a; }").unwrap();
+    let third = run(&main, &cache_dir).await;
+    assert_eq!((third.hits, third.misses), (1, 1));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
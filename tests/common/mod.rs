@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+/// Creates a fresh directory under the system temp dir holding `files`, given as
+/// (relative path, contents) pairs. Returns its canonical path.
+pub fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("toyjs-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (path, contents) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    dir.canonicalize().unwrap()
+}
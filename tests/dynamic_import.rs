@@ -0,0 +1,141 @@
+mod common;
+
+use std::time::Duration;
+use toyjs::runtime::{JsErrorKind, JsRuntime, RuntimeOptions};
+
+#[tokio::test]
+async fn resolves_with_the_module_namespace() {
+    let dir = common::fixture(
+        "dynamic-import-namespace",
+        &[
+            ("lib.js", "export default 'default'; export const named = 'named';"),
+            (
+                "main.js",
+                "const ns = await import('./lib.js');
+                 globalThis.result = [ns.default, ns.named, Object.keys(ns), ns[Symbol.toStringTag]].join();",
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "default,named,default,named,Module");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn rejects_when_the_module_is_missing() {
+    let dir = common::fixture(
+        "dynamic-import-missing",
+        &[(
+            "main.js",
+            "globalThis.result = await import('./missing.js').then(() => 'loaded', (error) => error.message);",
+        )],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    let message = runtime.execute_script("result").unwrap();
+    assert!(message.contains("missing.js"), "{}", message);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn waits_for_top_level_await_in_the_imported_module() {
+    let dir = common::fixture(
+        "dynamic-import-tla",
+        &[
+            ("slow.js", "export const value = await new Promise((resolve) => setTimeout(() => resolve(5), 10));"),
+            ("main.js", "globalThis.result = (await import('./slow.js')).value * performance.now();"),
+        ],
+    );
+
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        virtual_time: true,
+        ..Default::default()
+    });
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "50");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn loads_imports_queued_while_a_module_evaluates_on_a_later_turn() {
+    let dir = common::fixture(
+        "dynamic-import-queued",
+        &[
+            ("a.js", "order.push('a'); globalThis.b = import('./b.js');"),
+            ("b.js", "order.push('b');"),
+            (
+                "main.js",
+                "globalThis.order = [];
+                 const a = import('./a.js');
+                 order.push('main');
+                 await a;
+                 order.push('a loaded');
+                 await globalThis.b;
+                 order.push('b loaded');
+                 globalThis.result = order.join();",
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "main,a,a loaded,b,b loaded");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn fills_in_import_meta() {
+    let dir = common::fixture(
+        "dynamic-import-meta",
+        &[
+            ("lib.js", "export const main = import.meta.main; export const url = import.meta.url;"),
+            (
+                "main.js",
+                "import { main as libMain, url } from './lib.js';
+                 globalThis.result = [import.meta.main, libMain, import.meta.resolve('./lib.js') === url, import.meta.filename].join();",
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(
+        runtime.execute_script("result").unwrap(),
+        format!("true,false,true,{}", dir.join("main.js").display())
+    );
+    assert_eq!(runtime.execute_script("import.meta.url").unwrap_err().name(), "SyntaxError");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn settles_every_import_when_one_is_terminated() {
+    let dir = common::fixture(
+        "dynamic-import-terminated",
+        &[("loop.js", "while (true) {}"), ("lib.js", "export const value = 1;")],
+    );
+
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        execution_timeout: Some(Duration::from_millis(50)),
+        ..Default::default()
+    });
+    runtime
+        .execute_script(&format!(
+            "globalThis.log = [];
+             import('{endless}').catch((error) => log.push(error.message));
+             import('{lib}').then((ns) => log.push(ns.value));",
+            endless = dir.join("loop.js").display(),
+            lib = dir.join("lib.js").display(),
+        ))
+        .unwrap();
+
+    let error = runtime.process_callbacks().unwrap_err();
+    assert_eq!(error.kind(), JsErrorKind::TimeLimitExceeded);
+    // The interrupted import rejects and the one behind it still loads
+    runtime.process_callbacks().unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "Execution timed out after 50ms,1");
+    assert!(!runtime.has_pending_ops());
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use toyjs::runtime::{JsErrorKind, JsRuntime, StackFrame};
+
+#[tokio::test]
+async fn reports_location_and_stack_frames_one_based() {
+    let dir = common::fixture(
+        "errors-stack",
+        &[(
+            "main.js",
+            "function inner() { throw new TypeError(\"bad\"); }\n\
+             function outer() { inner(); }\n\
+             outer();",
+        )],
+    );
+    let path = dir.join("main.js").to_string_lossy().into_owned();
+
+    let error = JsRuntime::new().execute_module(&dir.join("main.js")).await.unwrap_err();
+    assert_eq!(error.kind(), JsErrorKind::Exception);
+    assert_eq!((error.name(), error.message()), ("TypeError", "bad"));
+    assert_eq!(error.resource_name(), Some(path.as_str()));
+    assert_eq!((error.line(), error.column()), (Some(1), Some(26)));
+    assert_eq!(error.source_line(), Some("function inner() { throw new TypeError(\"bad\"); }"));
+
+    let frame = |function_name: Option<&str>, line, column| StackFrame {
+        function_name: function_name.map(str::to_string),
+        script_name: Some(path.clone()),
+        line,
+        column,
+    };
+    assert_eq!(
+        &error.frames()[..3],
+        &[frame(Some("inner"), 1, 26), frame(Some("outer"), 2, 20), frame(None, 3, 1)]
+    );
+    assert!(
+        error.to_string().starts_with(&format!(
+            "Uncaught TypeError: bad\n    at inner ({path}:1:26)\n    at outer ({path}:2:20)\n    at {path}:3:1"
+        )),
+        "{}",
+        error
+    );
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn formats_thrown_values_that_are_not_errors() {
+    let error = JsRuntime::new().execute_script("throw 42").unwrap_err();
+    assert_eq!((error.name(), error.message()), ("", "42"));
+    assert!(error.to_string().starts_with("Uncaught 42"), "{}", error);
+}
+
+#[test]
+fn reports_syntax_errors_at_the_offending_token() {
+    let error = JsRuntime::new().execute_script("let a = 1;\nlet b = ;").unwrap_err();
+    assert_eq!(error.name(), "SyntaxError");
+    assert_eq!((error.line(), error.column()), (Some(2), Some(9)));
+    assert_eq!(error.source_line(), Some("let b = ;"));
+}
+
+#[test]
+fn runtime_errors_have_a_name_and_message_only() {
+    let error = toyjs::runtime::JsError::new("module not found");
+    assert_eq!((error.name(), error.message()), ("Error", "module not found"));
+    assert_eq!((error.line(), error.column(), error.frames().len()), (None, None, 0));
+    assert_eq!(error.to_string(), "Uncaught Error: module not found");
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+use toyjs::runtime::JsRuntime;
+
+#[tokio::test]
+async fn runs_until_timers_and_intervals_are_done() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script(
+            "globalThis.log = [];
+             setTimeout(() => log.push('timeout'), 30);
+             let ticks = 0;
+             const interval = setInterval(() => {
+                 log.push(`tick ${++ticks}`);
+                 if (ticks === 3) clearInterval(interval);
+             }, 5);",
+        )
+        .unwrap();
+    assert!(runtime.has_pending_ops());
+
+    let start = Instant::now();
+    runtime.run_until_idle().await.unwrap();
+    // Returns as soon as the last timer has fired rather than after a fixed wait
+    assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+    assert!(!runtime.has_pending_ops());
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "tick 1,tick 2,tick 3,timeout");
+}
+
+#[tokio::test]
+async fn returns_at_once_without_pending_work() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script("globalThis.n = 0; clearTimeout(setTimeout(() => n++, 10)); Promise.resolve().then(() => n += 10);")
+        .unwrap();
+
+    assert!(!runtime.has_pending_ops());
+    runtime.run_until_idle().await.unwrap();
+    assert_eq!(runtime.execute_script("n").unwrap(), "10");
+}
+
+#[tokio::test]
+async fn stops_at_an_error_thrown_by_a_timer() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script("setTimeout(() => { throw new Error('from a timer'); }, 5);")
+        .unwrap();
+
+    let error = runtime.run_until_idle().await.unwrap_err();
+    assert_eq!(error.message(), "from a timer");
+}
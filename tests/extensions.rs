@@ -0,0 +1,83 @@
+use toyjs::runtime::{Extension, JsRuntime, OpDecl, OpError, OpValue, RuntimeOptions};
+
+fn number(args: &[OpValue], index: usize) -> Result<f64, OpError> {
+    match args.get(index) {
+        Some(OpValue::Json(value)) => value.as_f64().ok_or_else(|| OpError::type_error("expected a number")),
+        _ => Err(OpError::type_error("expected a number")),
+    }
+}
+
+fn math_extension() -> Extension {
+    Extension::new("math")
+        .op(OpDecl::new_sync("op_sum", |args| {
+            Ok(serde_json::json!(number(&args, 0)? + number(&args, 1)?).into())
+        }))
+        .op(OpDecl::new_async("op_sqrt", |args| async move {
+            let value = number(&args, 0)?;
+            if value < 0.0 {
+                return Err(OpError::with_class("RangeError", "cannot take the root of a negative number"));
+            }
+            tokio::task::yield_now().await;
+            Ok(serde_json::json!(value.sqrt()).into())
+        }))
+        .op(OpDecl::new_sync("op_bytes", |args| match args.first() {
+            Some(OpValue::Bytes(bytes)) => Ok(bytes.iter().rev().copied().collect::<Vec<u8>>().into()),
+            _ => Err(OpError::with_class("MathError", "expected bytes")),
+        }))
+        .js("globalThis.math = { sum: ops.op_sum, sqrt: ops.op_sqrt, reverse: ops.op_bytes };")
+}
+
+fn runtime() -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        extensions: vec![math_extension()],
+        ..Default::default()
+    })
+}
+
+/// Describes how `expression` fails, as "<name>: <message>".
+fn thrown(runtime: &mut JsRuntime, expression: &str) -> String {
+    runtime
+        .execute_script(&format!(
+            "try {{ {expression}; 'no error' }} catch (error) {{ `${{error.name}}: ${{error.message}}` }}"
+        ))
+        .unwrap()
+}
+
+#[test]
+fn calls_sync_ops_and_throws_their_errors() {
+    let mut runtime = runtime();
+    assert_eq!(runtime.execute_script("math.sum(2, 3)").unwrap(), "5");
+    assert_eq!(
+        runtime.execute_script("Array.from(math.reverse(new Uint8Array([1, 2, 3]))).join()").unwrap(),
+        "3,2,1"
+    );
+
+    assert_eq!(thrown(&mut runtime, "math.sum('a', 3)"), "TypeError: expected a number");
+    // Classes V8 has no constructor for become an Error with that name
+    assert_eq!(thrown(&mut runtime, "math.reverse(1)"), "MathError: expected bytes");
+}
+
+#[tokio::test]
+async fn resolves_and_rejects_async_ops() {
+    let mut runtime = runtime();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script_module(
+            "const root = await math.sqrt(16);
+             const error = await math.sqrt(-1).catch((error) => error);
+             globalThis.result = [root, error instanceof RangeError, error.message].join();",
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        runtime.execute_script("result").unwrap(),
+        "4,true,cannot take the root of a negative number"
+    );
+}
+
+#[test]
+fn keeps_ops_private_to_the_extension_script() {
+    let mut runtime = runtime();
+    assert_eq!(runtime.execute_script("typeof ops").unwrap(), "undefined");
+}
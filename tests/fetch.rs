@@ -0,0 +1,54 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use toyjs::runtime::JsRuntime;
+
+/// Serves a redirect from `/start` to `/target`, which answers "arrived".
+fn redirect_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            let response = if request_line.starts_with("GET /start ") {
+                "HTTP/1.1 302 Found\r\nlocation: /target\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            } else {
+                "HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\narrived"
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn follows_stops_at_or_rejects_redirects_by_mode() {
+    let address = redirect_server();
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script_module(&format!(
+            r#"const url = "http://{address}/start";
+               const followed = await fetch(url);
+               const manual = await fetch(url, {{ redirect: "manual" }});
+               const error = await fetch(url, {{ redirect: "error" }}).catch((error) => error);
+               globalThis.result = JSON.stringify({{
+                   followed: [followed.type, followed.status, followed.redirected, followed.url.endsWith("/target"), await followed.text()],
+                   manual: [manual.type, manual.status, manual.statusText, [...manual.headers].length, manual.url === url, manual.body, await manual.text()],
+                   error: error.name,
+               }});"#
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        runtime.execute_script("result").unwrap(),
+        r#"{"followed":["basic",200,true,true,"arrived"],"manual":["opaqueredirect",0,"",0,true,null,""],"error":"TypeError"}"#
+    );
+}
@@ -0,0 +1,81 @@
+use toyjs::runtime::JsRuntime;
+
+/// Runs `code`, which must evaluate to a string, in a fresh runtime.
+fn eval(code: &str) -> String {
+    JsRuntime::new().execute_script(code).unwrap()
+}
+
+#[test]
+fn normalizes_names_and_values() {
+    let result = eval(
+        r#"const h = new Headers({ "Content-Type": " text/plain \t", "X-A": 1 });
+           h.append("x-a", "2");
+           JSON.stringify([h.get("content-type"), h.get("X-A"), h.has("X-a")])"#,
+    );
+    assert_eq!(result, r#"["text/plain","1, 2",true]"#);
+}
+
+#[test]
+fn iterates_sorted_with_combined_values_except_set_cookie() {
+    let result = eval(
+        r#"const h = new Headers([["b", "1"], ["Set-Cookie", "a=1"], ["A", "x"], ["set-cookie", "b=2"], ["b", "2"]]);
+           JSON.stringify([...h])"#,
+    );
+    assert_eq!(result, r#"[["a","x"],["b","1, 2"],["set-cookie","a=1"],["set-cookie","b=2"]]"#);
+
+    let cookies = eval(
+        r#"const h = new Headers();
+           h.append("Set-Cookie", "a=1");
+           h.append("set-cookie", "b=2");
+           JSON.stringify([h.getSetCookie(), h.get("set-cookie")])"#,
+    );
+    assert_eq!(cookies, r#"[["a=1","b=2"],"a=1, b=2"]"#);
+}
+
+#[test]
+fn set_replaces_every_value() {
+    let result = eval(
+        r#"const h = new Headers([["a", "1"], ["b", "2"], ["a", "3"]]);
+           h.set("A", "4");
+           h.delete("b");
+           JSON.stringify([...h.entries(), h.get("b")])"#,
+    );
+    assert_eq!(result, r#"[["a","4"],null]"#);
+}
+
+#[test]
+fn rejects_invalid_headers() {
+    let result = eval(
+        r#"const attempts = [
+               () => new Headers({ "bad name": "x" }),
+               () => new Headers({ a: "x\ny" }),
+               () => new Headers([["a"]]),
+               () => new Headers("a"),
+           ];
+           JSON.stringify(attempts.map((attempt) => {
+               try {
+                   attempt();
+                   return "no error";
+               } catch (e) {
+                   return `${e.constructor.name}: ${e.message}`;
+               }
+           }))"#,
+    );
+    assert_eq!(
+        result,
+        r#"["TypeError: Invalid header name: 'bad name'","TypeError: Invalid header value: 'x\ny'","TypeError: Each header must be a [name, value] pair","TypeError: Headers must be initialized with an object or an iterable of pairs"]"#
+    );
+}
+
+#[test]
+fn response_error_headers_are_immutable() {
+    let result = eval(
+        r#"try {
+               Response.error().headers.set("a", "b");
+               "mutable"
+           } catch (e) {
+               e.message
+           }"#,
+    );
+    assert_eq!(result, "Headers are immutable");
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use std::path::Path;
+use std::sync::Arc;
+use toyjs::modules::InMemoryModuleLoader;
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+#[tokio::test]
+async fn describes_file_modules() {
+    let dir = common::fixture(
+        "import-meta-files",
+        &[
+            ("sub dir/lib.js", "export const meta = { ...import.meta };"),
+            (
+                "main.js",
+                "import { meta } from './sub dir/lib.js';
+                 let missing;
+                 try { import.meta.resolve('./nope.js'); } catch (error) { missing = error.name; }
+                 globalThis.result = JSON.stringify({
+                     main: import.meta.main,
+                     url: import.meta.url,
+                     lib: [meta.main, meta.url, meta.filename, meta.dirname],
+                     resolved: import.meta.resolve('./sub dir/lib.js') === meta.url,
+                     missing,
+                 });",
+            ),
+        ],
+    );
+    let root = dir.display();
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(
+        runtime.execute_script("result").unwrap(),
+        format!(
+            r#"{{"main":true,"url":"file://{root}/main.js","lib":[false,"file://{root}/sub%20dir/lib.js","{root}/sub dir/lib.js","{root}/sub dir"],"resolved":true,"missing":"TypeError"}}"#
+        )
+    );
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn leaves_out_file_paths_for_modules_not_read_from_disk() {
+    let loader = InMemoryModuleLoader::new()
+        .with_module("/app/main.js", "globalThis.result = ['filename' in import.meta, import.meta.url].join();");
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        module_loader: Some(Arc::new(loader)),
+        ..Default::default()
+    });
+
+    runtime.execute_module(Path::new("/app/main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "false,file:///app/main.js");
+}
@@ -0,0 +1,58 @@
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+#[test]
+fn keeps_runtime_bindings_out_of_reach() {
+    let mut runtime = JsRuntime::new();
+    assert_eq!(
+        runtime
+            .execute_script(
+                "[Object.getOwnPropertyNames(globalThis).filter((name) => name.startsWith('__')).length,
+                  Object.getOwnPropertySymbols(globalThis).length,
+                  typeof internals,
+                  typeof ops].join()"
+            )
+            .unwrap(),
+        "0,0,undefined,undefined"
+    );
+
+    // Runtime-only globals can't be replaced or deleted
+    assert_eq!(
+        runtime.execute_script("print = 1; delete globalThis.add; [typeof print, typeof add].join()").unwrap(),
+        "function,function"
+    );
+}
+
+#[test]
+fn survives_user_code_replacing_built_in_methods() {
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        virtual_time: true,
+        ..Default::default()
+    });
+    runtime
+        .execute_script(
+            "for (const { prototype } of [Map, Set, WeakMap, WeakSet]) {
+                 for (const name of ['get', 'set', 'has', 'add', 'delete']) {
+                     if (name in prototype) prototype[name] = () => { throw new Error(`polluted ${name}`); };
+                 }
+             }
+             Array.prototype.push = () => { throw new Error('polluted push'); };
+
+             globalThis.log = '';
+             const target = new EventTarget();
+             target.addEventListener('ping', () => { log += 'ping '; }, { once: true });
+             target.dispatchEvent(new Event('ping'));
+             target.dispatchEvent(new Event('ping'));
+
+             let ticks = 0;
+             const interval = setInterval(() => {
+                 log += `tick${++ticks} `;
+                 if (ticks === 2) clearInterval(interval);
+             }, 10);
+             setTimeout(() => { log += 'timeout'; }, 50);
+             clearTimeout(setTimeout(() => { log += 'cleared'; }, 20));",
+        )
+        .unwrap();
+
+    runtime.run_all_timers().unwrap();
+    assert_eq!(runtime.execute_script("log").unwrap(), "ping tick1 tick2 timeout");
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use toyjs::runtime::JsRuntime;
+
+#[tokio::test]
+async fn imports_json_with_a_type_attribute() {
+    let dir = common::fixture(
+        "json-modules-import",
+        &[
+            ("config.json", r#"{ "name": "toyjs", "ports": [80, 443] }"#),
+            (
+                "main.js",
+                r#"import config from "./config.json" with { type: "json" };
+                   const dynamic = await import("./config.json", { with: { type: "json" } });
+                   globalThis.result = [config.name, config.ports.join("+"), dynamic.default === config, Object.keys(dynamic)].join();"#,
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "toyjs,80+443,true,default");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn rejects_json_without_the_attribute_and_unknown_types() {
+    let dir = common::fixture(
+        "json-modules-errors",
+        &[
+            ("data.json", "[1, 2]"),
+            ("script.js", "export default 1;"),
+            ("invalid.json", "{ nope"),
+            (
+                "main.js",
+                r#"const attempt = (promise) => promise.then(() => "loaded", (error) => error.message);
+                   globalThis.result = [
+                       await attempt(import("./data.json")),
+                       await attempt(import("./script.js", { with: { type: "css" } })),
+                       await attempt(import("./invalid.json", { with: { type: "json" } })),
+                   ];"#,
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    let messages: Vec<String> = serde_json::from_str(&runtime.execute_script("JSON.stringify(result)").unwrap()).unwrap();
+    let path = |name: &str| dir.join(name).display().to_string();
+    assert_eq!(
+        messages[0],
+        format!("'{}' is a JSON module and must be imported with {{ type: \"json\" }}", path("data.json"))
+    );
+    assert_eq!(messages[1], format!("Unknown module type 'css' for '{}'", path("script.js")));
+    assert!(messages[2].contains("JSON"), "{}", messages[2]);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn caches_a_file_separately_per_module_type() {
+    // Valid both as a JavaScript module without exports and as a JSON string
+    let dir = common::fixture(
+        "json-modules-cache",
+        &[
+            ("value", r#""text""#),
+            (
+                "main.js",
+                r#"import * as script from "./value";
+                   import json from "./value" with { type: "json" };
+                   globalThis.result = [Object.keys(script).length, json].join();"#,
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "0,text");
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+use toyjs::runtime::{JsErrorKind, JsRuntime, RuntimeOptions};
+
+fn with_timeout(timeout: Duration) -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        execution_timeout: Some(timeout),
+        ..Default::default()
+    })
+}
+
+const ALLOCATE_FOREVER: &str = "{ const chunks = []; while (true) chunks.push(new Array(10000).fill(chunks.length)); }";
+
+#[test]
+fn terminates_an_endless_loop_and_keeps_running() {
+    let mut runtime = with_timeout(Duration::from_millis(50));
+
+    let error = runtime.execute_script("while (true) {}").unwrap_err();
+    assert_eq!(error.kind(), JsErrorKind::TimeLimitExceeded);
+    assert_eq!(error.message(), "Execution timed out after 50ms");
+
+    assert_eq!(runtime.execute_script("1 + 1").unwrap(), "2");
+}
+
+#[test]
+fn terminates_an_endless_timer_callback() {
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        execution_timeout: Some(Duration::from_millis(50)),
+        virtual_time: true,
+        ..Default::default()
+    });
+    runtime
+        .execute_script("globalThis.fired = false; setTimeout(() => { fired = true; while (true) {} }, 10);")
+        .unwrap();
+
+    let error = runtime.run_all_timers().unwrap_err();
+    assert_eq!(error.kind(), JsErrorKind::TimeLimitExceeded);
+    assert_eq!(runtime.execute_script("String(fired)").unwrap(), "true");
+}
+
+#[test]
+fn a_deadline_passing_as_a_script_ends_does_not_hit_the_next_one() {
+    let mut runtime = with_timeout(Duration::from_millis(2));
+    for _ in 0..50 {
+        // Finishes right around the deadline, so the watchdog may fire just after it
+        let _ = runtime.execute_script("{ const end = Date.now() + 2; while (Date.now() < end) {} }");
+        assert_eq!(runtime.execute_script("'next'").unwrap(), "next");
+    }
+}
+
+#[test]
+fn stops_at_the_heap_limit_every_time() {
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        max_heap_size: Some(32 * 1024 * 1024),
+        ..Default::default()
+    });
+
+    for _ in 0..2 {
+        let error = runtime.execute_script(ALLOCATE_FOREVER).unwrap_err();
+        assert_eq!(error.kind(), JsErrorKind::HeapLimitExceeded);
+        assert_eq!(runtime.execute_script("[1, 2, 3].map((n) => n * 2).join()").unwrap(), "2,4,6");
+    }
+}
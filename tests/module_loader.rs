@@ -0,0 +1,50 @@
+use std::path::Path;
+use std::sync::Arc;
+use toyjs::modules::InMemoryModuleLoader;
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+fn runtime(loader: InMemoryModuleLoader) -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        module_loader: Some(Arc::new(loader)),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn runs_a_module_graph_from_memory() {
+    let loader = InMemoryModuleLoader::new()
+        .with_module(
+            "/app/main.js",
+            "import { answer } from './util.js';
+             const { double } = await import('./math');
+             globalThis.result = [double(answer), import.meta.url, import.meta.resolve('./util.js')].join();",
+        )
+        .with_module("/app/util.js", "export const answer = 21;")
+        .with_module("/app/math.ts", "export const double = (n: number): number => n * 2;");
+
+    let mut runtime = runtime(loader);
+    runtime.execute_module(Path::new("/app/main.js")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "42,file:///app/main.js,file:///app/util.js");
+}
+
+#[tokio::test]
+async fn runs_an_entry_module_with_a_bare_name() {
+    let loader = InMemoryModuleLoader::new()
+        .with_module("app", "import greet from 'greeting'; globalThis.result = greet('world');")
+        .with_module("greeting", "export default (name) => `hello ${name}`;");
+
+    let mut runtime = runtime(loader);
+    runtime.execute_module(Path::new("app")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "hello world");
+}
+
+#[tokio::test]
+async fn reports_modules_missing_from_memory() {
+    let loader = InMemoryModuleLoader::new().with_module("/app/main.js", "import './gone.js';");
+
+    let mut runtime = runtime(loader);
+    let error = runtime.execute_module(Path::new("/app/main.js")).await.unwrap_err();
+    assert_eq!(error.message(), "Cannot find module './gone.js' imported from '/app/main.js'");
+    let error = runtime.execute_module(Path::new("/app/other.js")).await.unwrap_err();
+    assert_eq!(error.message(), "Module '/app/other.js' not found");
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use toyjs::runtime::JsRuntime;
+
+const COUNTER: &str = "globalThis.evaluations = (globalThis.evaluations ?? 0) + 1; export let count = 0; export const increment = () => ++count;";
+
+#[tokio::test]
+async fn each_runtime_loads_its_own_modules() {
+    let dir = common::fixture(
+        "module-map-runtimes",
+        &[
+            ("counter.js", COUNTER),
+            ("main.js", "import { increment } from './counter.js'; globalThis.result = increment();"),
+        ],
+    );
+
+    let mut first = JsRuntime::new();
+    first.execute_module(&dir.join("main.js")).await.unwrap();
+    let mut second = JsRuntime::new();
+    second.execute_module(&dir.join("main.js")).await.unwrap();
+
+    // Each runtime evaluated the module once, with its own state
+    assert_eq!(second.execute_script("`${result} ${evaluations}`").unwrap(), "1 1");
+    assert_eq!(first.execute_script("`${result} ${evaluations}`").unwrap(), "1 1");
+    // Dropping a runtime drops only its own modules
+    drop(first);
+    assert_eq!(second.execute_script("result").unwrap(), "1");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn runtimes_on_different_threads_do_not_share_modules() {
+    let dir = common::fixture(
+        "module-map-threads",
+        &[
+            ("counter.js", COUNTER),
+            (
+                "main.js",
+                "import { increment } from './counter.js'; increment(); globalThis.result = increment();",
+            ),
+        ],
+    );
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let main = dir.join("main.js");
+            std::thread::spawn(move || {
+                let tokio = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+                tokio.block_on(async {
+                    let mut runtime = JsRuntime::new();
+                    runtime.execute_module(&main).await.unwrap();
+                    runtime.execute_script("`${result} ${evaluations}`").unwrap()
+                })
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), "2 1");
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
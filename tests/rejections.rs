@@ -0,0 +1,65 @@
+use toyjs::runtime::{JsRuntime, RuntimeOptions, UnhandledRejectionPolicy};
+
+fn runtime(policy: UnhandledRejectionPolicy) -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        unhandled_rejection: policy,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn throw_reports_each_rejection_in_turn() {
+    let mut runtime = runtime(UnhandledRejectionPolicy::Throw);
+    runtime
+        .execute_script("Promise.reject(new Error('first')); Promise.reject(new Error('second'));")
+        .unwrap();
+
+    assert_eq!(runtime.process_callbacks().unwrap_err().message(), "first");
+    assert_eq!(runtime.process_callbacks().unwrap_err().message(), "second");
+    runtime.process_callbacks().unwrap();
+}
+
+#[test]
+fn handlers_attached_before_the_checkpoint_count() {
+    let mut runtime = runtime(UnhandledRejectionPolicy::Throw);
+    runtime
+        .execute_script("const p = Promise.reject(new Error('late')); p.catch(() => {});")
+        .unwrap();
+
+    runtime.process_callbacks().unwrap();
+}
+
+#[test]
+fn warn_keeps_running() {
+    let mut runtime = runtime(UnhandledRejectionPolicy::Warn);
+    runtime
+        .execute_script("Promise.reject(new Error('ignored')); Promise.reject(new Error('also ignored'));")
+        .unwrap();
+
+    runtime.process_callbacks().unwrap();
+    assert_eq!(runtime.execute_script("'still running'").unwrap(), "still running");
+}
+
+#[test]
+fn dispatch_lets_listeners_handle_rejections() {
+    let mut runtime = runtime(UnhandledRejectionPolicy::Dispatch);
+    runtime
+        .execute_script(
+            "globalThis.seen = [];
+             addEventListener('unhandledrejection', (event) => {
+                 seen.push(event.reason.message);
+                 if (event.reason.message === 'handled') event.preventDefault();
+             });
+             Promise.reject(new Error('handled'));
+             Promise.reject(new Error('unhandled'));
+             Promise.reject(new Error('queued'));",
+        )
+        .unwrap();
+
+    assert_eq!(runtime.process_callbacks().unwrap_err().message(), "unhandled");
+    assert_eq!(runtime.execute_script("seen.join()").unwrap(), "handled,unhandled");
+    // The rejection behind the one that was returned is dispatched by the next call
+    assert_eq!(runtime.process_callbacks().unwrap_err().message(), "queued");
+    assert_eq!(runtime.execute_script("seen.join()").unwrap(), "handled,unhandled,queued");
+    runtime.process_callbacks().unwrap();
+}
@@ -0,0 +1,34 @@
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+const GLOBALS: &str = "Object.getOwnPropertyNames(globalThis).sort().join()";
+
+#[test]
+fn the_default_startup_matches_the_bootstrap() {
+    // An empty snapshot forces the bootstrap, whether or not one is embedded
+    let mut bootstrapped = JsRuntime::with_options(RuntimeOptions {
+        startup_snapshot: Some(&[]),
+        ..Default::default()
+    });
+    let mut default = JsRuntime::new();
+    assert_eq!(default.execute_script(GLOBALS).unwrap(), bootstrapped.execute_script(GLOBALS).unwrap());
+    assert_eq!(default.execute_script("typeof print").unwrap(), "function");
+}
+
+#[test]
+fn applies_options_on_top_of_a_snapshot() {
+    let snapshot: &'static [u8] = JsRuntime::create_snapshot().leak();
+    let mut runtime = JsRuntime::with_options(RuntimeOptions {
+        startup_snapshot: Some(snapshot),
+        virtual_time: true,
+        ..Default::default()
+    });
+    runtime
+        .execute_script(
+            "globalThis.log = [typeof fetch];
+             setTimeout(() => log.push(performance.now()), 10);",
+        )
+        .unwrap();
+
+    runtime.run_all_timers().unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "function,10");
+}
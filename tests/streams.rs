@@ -0,0 +1,121 @@
+use toyjs::runtime::JsRuntime;
+
+/// Runs `body` as an async function in a fresh runtime, drains microtasks and
+/// returns what it resolved to as a string.
+fn eval_async(body: &str) -> String {
+    let mut runtime = JsRuntime::new();
+    runtime
+        .execute_script(&format!(
+            "globalThis.result = 'pending';
+             (async () => {{ {body} }})().then(
+                 (value) => {{ result = String(value); }},
+                 (error) => {{ result = `error: ${{error}}`; }},
+             );"
+        ))
+        .unwrap();
+    runtime.process_callbacks().unwrap();
+    runtime.execute_script("result").unwrap()
+}
+
+#[test]
+fn tracks_desired_size_as_the_queue_fills_and_drains() {
+    let result = eval_async(
+        r#"let controller;
+           const stream = new ReadableStream({ start(c) { controller = c; } }, new CountQueuingStrategy({ highWaterMark: 3 }));
+           const sizes = [controller.desiredSize];
+           controller.enqueue("a");
+           controller.enqueue("b");
+           sizes.push(controller.desiredSize);
+           const reader = stream.getReader();
+           const first = await reader.read();
+           sizes.push(controller.desiredSize);
+           controller.close();
+           const rest = [await reader.read(), await reader.read()];
+           return JSON.stringify({ sizes, first, rest, closed: controller.desiredSize });"#,
+    );
+    assert_eq!(
+        result,
+        r#"{"sizes":[3,1,2],"first":{"value":"a","done":false},"rest":[{"value":"b","done":false},{"done":true}],"closed":0}"#
+    );
+}
+
+#[test]
+fn sizes_chunks_with_the_strategy() {
+    let bytes = eval_async(
+        r#"let controller;
+           new ReadableStream({ start(c) { controller = c; } }, new ByteLengthQueuingStrategy({ highWaterMark: 10 }));
+           controller.enqueue(new Uint8Array(4));
+           return controller.desiredSize;"#,
+    );
+    assert_eq!(bytes, "6");
+
+    let invalid = eval_async(
+        r#"let controller;
+           const stream = new ReadableStream({ start(c) { controller = c; } }, { highWaterMark: 5, size: () => -1 });
+           let thrown;
+           try { controller.enqueue("x"); } catch (error) { thrown = error.constructor.name; }
+           const read = await stream.getReader().read().catch((error) => error.message);
+           return JSON.stringify([thrown, read, controller.desiredSize]);"#,
+    );
+    assert_eq!(invalid, r#"["RangeError","Chunk size must be a finite, non-negative number",null]"#);
+}
+
+#[test]
+fn pulls_only_on_demand_with_a_zero_high_water_mark() {
+    let result = eval_async(
+        r#"let pulls = 0;
+           const stream = new ReadableStream({
+               pull(c) {
+                   pulls++;
+                   c.enqueue(pulls);
+                   if (pulls === 3) c.close();
+               },
+           }, { highWaterMark: 0 });
+           const pullsBeforeRead = pulls;
+           const chunks = [];
+           for await (const chunk of stream) chunks.push(chunk);
+           return JSON.stringify({ pullsBeforeRead, chunks, pulls });"#,
+    );
+    assert_eq!(result, r#"{"pullsBeforeRead":0,"chunks":[1,2,3],"pulls":3}"#);
+}
+
+#[test]
+fn tee_delivers_every_chunk_to_both_branches() {
+    let result = eval_async(
+        r#"const source = ReadableStream.from(["a", "b", "c"]);
+           const [left, right] = source.tee();
+           const readAll = async (stream) => {
+               const chunks = [];
+               for await (const chunk of stream) chunks.push(chunk);
+               return chunks;
+           };
+           return JSON.stringify([...(await Promise.all([readAll(left), readAll(right)])), source.locked]);"#,
+    );
+    assert_eq!(result, r#"[["a","b","c"],["a","b","c"],true]"#);
+}
+
+#[test]
+fn tee_cancels_the_source_once_both_branches_cancel() {
+    let result = eval_async(
+        r#"let reason = "not cancelled";
+           const source = new ReadableStream({ pull(c) { c.enqueue(1); }, cancel(r) { reason = r; } });
+           const [left, right] = source.tee();
+           const leftCancelled = left.cancel("left");
+           await Promise.resolve();
+           const afterOne = reason;
+           await Promise.all([leftCancelled, right.cancel("right")]);
+           return JSON.stringify([afterOne, reason]);"#,
+    );
+    assert_eq!(result, r#"["not cancelled",["left","right"]]"#);
+}
+
+#[test]
+fn tee_propagates_source_errors_to_both_branches() {
+    let result = eval_async(
+        r#"const source = new ReadableStream({ start(c) { c.error(new Error("boom")); } });
+           const [left, right] = source.tee();
+           const results = await Promise.allSettled([left.getReader().read(), right.getReader().read()]);
+           return results.map((result) => result.status + ": " + result.reason.message).join(", ");"#,
+    );
+    assert_eq!(result, "rejected: boom, rejected: boom");
+}
@@ -0,0 +1,48 @@
+use toyjs::runtime::{JsErrorKind, JsRuntime};
+
+#[tokio::test]
+async fn waits_for_top_level_await() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime
+        .execute_script_module(
+            "const value = await new Promise((resolve) => setTimeout(() => resolve('settled'), 10));
+             globalThis.result = value;",
+        )
+        .await
+        .unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "settled");
+}
+
+#[tokio::test]
+async fn reports_a_rejected_top_level_await_with_its_stack() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    let error = runtime
+        .execute_script_module(
+            "async function load() {\n\
+                 await null;\n\
+                 throw new TypeError('not loaded');\n\
+             }\n\
+             await load();",
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.kind(), JsErrorKind::Exception);
+    assert_eq!((error.name(), error.message()), ("TypeError", "not loaded"));
+    assert_eq!(error.frames()[0].function_name.as_deref(), Some("load"));
+    assert_eq!(error.frames()[0].line, 3);
+    assert!(error.to_string().starts_with("Uncaught TypeError: not loaded\n    at load ("), "{}", error);
+}
+
+#[tokio::test]
+async fn fails_when_top_level_await_can_never_settle() {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    let error = runtime
+        .execute_script_module("await new Promise(() => {});")
+        .await
+        .unwrap_err();
+    assert_eq!(error.message(), "Top-level await promise never resolved");
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use toyjs::runtime::JsRuntime;
+
+#[tokio::test]
+async fn runs_typescript_modules_importing_each_other() {
+    let dir = common::fixture(
+        "typescript",
+        &[
+            (
+                "main.ts",
+                "import { greet, type Greeting } from './greet.js';\n\
+                 const greeting: Greeting = greet('world');\n\
+                 globalThis.result = greeting.text;",
+            ),
+            (
+                "greet.ts",
+                "export interface Greeting { text: string }\n\
+                 enum Punctuation { Bang = '!' }\n\
+                 export function greet(name: string): Greeting {\n\
+                     return { text: `hello ${name}${Punctuation.Bang}` };\n\
+                 }",
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.ts")).await.unwrap();
+    assert_eq!(runtime.execute_script("result").unwrap(), "hello world!");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn errors_point_at_the_typescript_source() {
+    let dir = common::fixture(
+        "typescript-errors",
+        &[(
+            "main.ts",
+            "type Id = string;\n\
+             interface User {\n    id: Id;\n}\n\
+             const user: User = { id: 'a' };\n\
+             throw new Error(`bad user ${user.id}`);",
+        )],
+    );
+
+    let error = JsRuntime::new().execute_module(&dir.join("main.ts")).await.unwrap_err();
+    assert_eq!(error.message(), "bad user a");
+    assert_eq!((error.line(), error.column()), (Some(6), Some(7)));
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn compiles_jsx_in_tsx_modules() {
+    let dir = common::fixture(
+        "tsx",
+        &[
+            (
+                "react.js",
+                "export const Fragment = 'Fragment';\n\
+                 export function createElement(type, props, ...children) {\n\
+                     const name = typeof type === 'function' ? type.name : type;\n\
+                     return `<${name}${JSON.stringify(props)}>${children.flat().join('')}`;\n\
+                 }",
+            ),
+            (
+                "main.ts",
+                "import { render } from './App.js';\n\
+                 globalThis.result = render(['a', 'b']);",
+            ),
+            (
+                "App.tsx",
+                "import * as React from './react.js';\n\
+                 const Item = ({ label }: { label: string }) => <li>{label}</li>;\n\
+                 export function render(labels: string[]) {\n\
+                     return (\n\
+                         <>\n\
+                             {labels.map((label) => <Item label={label} />)}\n\
+                             &lt;end&gt;\n\
+                         </>\n\
+                     );\n\
+                 }\n\
+                 export function fail(): never {\n\
+                     throw new Error(<b /> as unknown as string);\n\
+                 }",
+            ),
+        ],
+    );
+
+    let mut runtime = JsRuntime::new();
+    runtime.execute_module(&dir.join("main.ts")).await.unwrap();
+    assert_eq!(
+        runtime.execute_script("result").unwrap(),
+        r#"<Fragmentnull><Item{"label":"a"}><Item{"label":"b"}><end>"#
+    );
+
+    // Lines after JSX stay where they were
+    runtime
+        .execute_script_module(&format!(
+            "import {{ fail }} from '{}'; globalThis.failed = (() => {{ try {{ fail(); }} catch (e) {{ return e.stack; }} }})();",
+            dir.join("App.tsx").display()
+        ))
+        .await
+        .unwrap();
+    let stack = runtime.execute_script("failed").unwrap();
+    assert!(stack.contains("App.tsx:12:"), "{}", stack);
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,117 @@
+use toyjs::runtime::{JsRuntime, RuntimeOptions};
+
+fn virtual_runtime() -> JsRuntime {
+    JsRuntime::with_options(RuntimeOptions {
+        virtual_time: true,
+        ..Default::default()
+    })
+}
+
+fn number(runtime: &mut JsRuntime, expression: &str) -> f64 {
+    runtime.execute_script(expression).unwrap().parse().unwrap()
+}
+
+#[test]
+fn advance_time_fires_due_timers_in_order() {
+    let mut runtime = virtual_runtime();
+    runtime
+        .execute_script(
+            "globalThis.log = [];
+             setTimeout(() => log.push('b'), 20);
+             setTimeout(() => log.push('a'), 10);
+             setTimeout(() => log.push('c'), 30);",
+        )
+        .unwrap();
+
+    runtime.advance_time(9).unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "");
+    runtime.advance_time(11).unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "a,b");
+    runtime.run_all_timers().unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "a,b,c");
+}
+
+#[test]
+fn timers_scheduled_by_callbacks_fire_if_due() {
+    let mut runtime = virtual_runtime();
+    runtime
+        .execute_script(
+            "globalThis.log = [];
+             setTimeout(() => {
+                 log.push(performance.now());
+                 setTimeout(() => log.push(performance.now()), 5);
+             }, 10);",
+        )
+        .unwrap();
+
+    runtime.advance_time(20).unwrap();
+    assert_eq!(runtime.execute_script("log.join()").unwrap(), "10,15");
+    assert_eq!(number(&mut runtime, "performance.now()"), 20.0);
+}
+
+#[test]
+fn date_follows_the_virtual_clock() {
+    let mut runtime = virtual_runtime();
+    let start = number(&mut runtime, "Date.now()");
+
+    runtime.advance_time(1500).unwrap();
+    assert_eq!(number(&mut runtime, "Date.now()"), start + 1500.0);
+    assert_eq!(number(&mut runtime, "new Date().getTime()"), start + 1500.0);
+    assert_eq!(number(&mut runtime, "performance.now()"), 1500.0);
+
+    // Dates built from explicit values and the Date API itself are untouched
+    assert_eq!(number(&mut runtime, "new Date(0).getTime()"), 0.0);
+    assert_eq!(number(&mut runtime, "Date.UTC(2020, 0, 1)"), 1577836800000.0);
+    assert_eq!(
+        runtime
+            .execute_script("new Date() instanceof Date && typeof Date() === 'string' && new Date().constructor === Date")
+            .unwrap(),
+        "true"
+    );
+}
+
+#[test]
+fn run_all_timers_gives_up_on_an_endless_interval() {
+    let mut runtime = virtual_runtime();
+    runtime.execute_script("setInterval(() => {}, 1)").unwrap();
+
+    let error = runtime.run_all_timers().unwrap_err();
+    assert!(error.message().contains("run_all_timers"), "{}", error);
+}
+
+#[tokio::test]
+async fn run_until_idle_skips_ahead_to_timers() {
+    let mut runtime = virtual_runtime();
+    runtime
+        .execute_script("globalThis.fired = false; setTimeout(() => { fired = true; }, 60 * 60 * 1000)")
+        .unwrap();
+
+    runtime.run_until_idle().await.unwrap();
+    assert_eq!(runtime.execute_script("fired").unwrap(), "true");
+    assert_eq!(number(&mut runtime, "performance.now()"), 3_600_000.0);
+}
+
+#[tokio::test]
+async fn run_until_idle_gives_up_on_an_endless_interval() {
+    let mut runtime = virtual_runtime();
+    runtime.execute_script("setInterval(() => {}, 1000)").unwrap();
+
+    let error = runtime.run_until_idle().await.unwrap_err();
+    assert!(error.message().contains("run_until_idle"), "{}", error);
+}
+
+#[test]
+fn date_is_the_builtin_without_virtual_time() {
+    let mut runtime = JsRuntime::new();
+    assert_eq!(
+        runtime
+            .execute_script(
+                "const descriptor = Object.getOwnPropertyDescriptor(Date.prototype, 'constructor');
+                 String(Date.prototype.constructor === Date && descriptor.enumerable === false
+                     && Date.name === 'Date' && Date.length === 7
+                     && /\\[native code\\]/.test(Function.prototype.toString.call(Date)))"
+            )
+            .unwrap(),
+        "true"
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::path::Path;
+use toyjs::runtime::JsRuntime;
+
+/// Runs `dir/main.js` with the event loop and waits until it and its workers are done.
+async fn run_main(dir: &Path) -> JsRuntime {
+    let mut runtime = JsRuntime::new();
+    let _event_loop = runtime.run_event_loop();
+    runtime.execute_module(&dir.join("main.js")).await.unwrap();
+    runtime.run_until_idle().await.unwrap();
+    runtime
+}
+
+#[tokio::test]
+async fn round_trips_messages_posted_before_the_worker_starts() {
+    let dir = common::fixture(
+        "workers-round-trip",
+        &[
+            ("worker.js", "onmessage = (event) => postMessage({ doubled: event.data.n * 2 });"),
+            (
+                "main.js",
+                "const worker = new Worker('./worker.js', { type: 'module' });
+                 const replies = [];
+                 const done = new Promise((resolve) => {
+                     worker.onmessage = (event) => {
+                         replies.push(event.data.doubled);
+                         if (replies.length === 3) resolve();
+                     };
+                 });
+                 for (const n of [1, 2, 3]) worker.postMessage({ n });
+                 await done;
+                 worker.terminate();
+                 globalThis.result = replies.join();",
+            ),
+        ],
+    );
+
+    let mut runtime = run_main(&dir).await;
+    assert_eq!(runtime.execute_script("result").unwrap(), "2,4,6");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn terminate_stops_a_busy_or_starting_worker() {
+    let dir = common::fixture(
+        "workers-terminate",
+        &[
+            ("busy.js", "postMessage('started'); while (true) {}"),
+            (
+                "main.js",
+                "globalThis.result = 'running';
+                 const busy = new Worker('./busy.js', { type: 'module' });
+                 busy.onmessage = () => {
+                     busy.terminate();
+                     result = 'terminated';
+                 };
+                 new Worker('./busy.js', { type: 'module' }).terminate();",
+            ),
+        ],
+    );
+
+    // Returns only once both workers are gone
+    let mut runtime = run_main(&dir).await;
+    assert_eq!(runtime.execute_script("result").unwrap(), "terminated");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn dispatches_worker_errors_on_the_worker() {
+    let dir = common::fixture(
+        "workers-error",
+        &[
+            ("worker.js", "const value = 1;\nthrow new RangeError(`bad ${value}`);"),
+            ("unhandled.js", "throw new Error('nobody listens');"),
+            (
+                "main.js",
+                "const worker = new Worker('./worker.js', { type: 'module' });
+                 const event = await new Promise((resolve) => {
+                     worker.onerror = (event) => {
+                         event.preventDefault();
+                         resolve(event);
+                     };
+                 });
+                 globalThis.result = [
+                     event.message,
+                     event.filename.endsWith('worker.js'),
+                     event.lineno,
+                     event.error.name,
+                 ].join();
+                 // Errors nobody handles are reported without failing the parent
+                 new Worker('./unhandled.js', { type: 'module' });",
+            ),
+        ],
+    );
+
+    let mut runtime = run_main(&dir).await;
+    assert_eq!(runtime.execute_script("result").unwrap(), "RangeError: bad 1,true,2,RangeError");
+    let _ = std::fs::remove_dir_all(dir);
+}